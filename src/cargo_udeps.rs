@@ -1,4 +1,9 @@
-use crate::{process::ProcessBuilderExt as _, shell::Shell, toolchain, workspace::TargetExt as _};
+use crate::{
+    process::ProcessBuilderExt as _,
+    shell::Shell,
+    toolchain,
+    workspace::{self, TargetExt as _},
+};
 use cargo_metadata as cm;
 use cargo_util::ProcessBuilder;
 use serde::Deserialize;
@@ -11,6 +16,8 @@ pub(crate) fn cargo_udeps(
     package: &cm::Package,
     target: &cm::Target,
     toolchain: &str,
+    features: &[String],
+    offline: bool,
     shell: &mut Shell,
 ) -> Result<HashSet<String>, String> {
     let cwd = &package.manifest_path.with_file_name("");
@@ -27,6 +34,13 @@ pub(crate) fn cargo_udeps(
         .arg("-p")
         .arg(&package.name)
         .args(&target.target_option())
+        .args(&workspace::features_option(features))
+        .args(&if offline { vec!["--offline"] } else { vec![] })
+        .args(&if shell.is_quiet() {
+            vec!["--quiet"]
+        } else {
+            vec![]
+        })
         .cwd(cwd)
         .try_inspect(|this| shell.status("Running", this))
         .map_err(|e| e.to_string())?
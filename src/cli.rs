@@ -0,0 +1,878 @@
+use crate::{daemon, notebook::NotebookFormat, ATCODER_CRATES, CODINGAME_CRATES};
+use indoc::indoc;
+use itertools::Itertools as _;
+use krates::PkgSpec;
+use std::{cell::RefCell, path::PathBuf, rc::Rc, str::FromStr};
+use structopt::{
+    clap::{self, AppSettings},
+    StructOpt,
+};
+
+// We need to prepend " " to `long_help`s.
+// https://github.com/BurntSushi/ripgrep/blob/9eddb71b8e86a04d7048b920b9b50a2e97068d03/crates/core/app.rs#L533-L539
+
+#[allow(clippy::large_enum_variant)]
+#[derive(StructOpt, Debug)]
+#[structopt(
+    author("Ryo Yamashita <qryxip@gmail.com>"),
+    about("Please run as `cargo equip`, not `cargo-equip`."),
+    bin_name("cargo"),
+    global_settings(&[AppSettings::DeriveDisplayOrder, AppSettings::UnifiedHelpMessage])
+)]
+pub enum Opt {
+    #[structopt(
+        about(indoc! {r#"
+
+            A Cargo subcommand to bundle your code into one `.rs` file for competitive programming.
+
+            Use -h for short descriptions and --help for more detials.
+        "#}),
+        author("Ryo Yamashita <qryxip@gmail.com>"),
+        usage(
+            r#"cargo equip [OPTIONS]
+    cargo equip [OPTIONS] --lib
+    cargo equip [OPTIONS] --bin <NAME>
+    cargo equip [OPTIONS] --example <NAME>
+    cargo equip [OPTIONS] --src <PATH>
+    cargo equip notebook [OPTIONS]
+    cargo equip stats [OPTIONS]
+    cargo equip rdeps [OPTIONS] <MODULE>
+    cargo equip doctor [OPTIONS]
+    cargo equip verify-metadata [OPTIONS]
+    cargo equip self-update [OPTIONS]
+    cargo equip [OPTIONS] bench-size
+    cargo equip [OPTIONS] daemon [--socket <PATH>]
+    cargo equip completions <SHELL>"#,
+        )
+    )]
+    Equip(OptEquip),
+    #[structopt(setting(AppSettings::Hidden))]
+    RustAnalyzerProcMacro {},
+}
+
+#[derive(StructOpt, Debug)]
+pub struct OptEquip {
+    /// Bundle the lib/bin/example target and its dependencies
+    #[structopt(
+        long,
+        value_name("PATH"),
+        long_help(indoc! {r#"
+            Bundle the lib/bin/example target and its dependencies.
+
+            This option is intended to be used from editors such as VSCode. Use `--lib`, `--bin` or `--example` for normal usage.
+
+            With `-`, the source is read from STDIN in place of the target selected by `--lib`, `--bin` or `--example`, or the one that would be selected without them. Other values cannot be combined with them.
+        "#})
+    )]
+    pub(crate) src: Option<PathBuf>,
+
+    /// Code to bundle in place of the content of `--src`, given by `--server`
+    #[structopt(skip)]
+    pub(crate) src_code: Option<String>,
+
+    /// What `cargo equip daemon` keeps in memory between the requests
+    #[structopt(skip)]
+    pub(crate) state: Option<Rc<RefCell<daemon::State>>>,
+
+    /// Bundle the library and its dependencies
+    #[structopt(long, conflicts_with_all(&["bin", "example"]))]
+    pub(crate) lib: bool,
+
+    /// Bundle the binary and its dependencies. Repeat to bundle several binaries into one file
+    #[structopt(
+        long,
+        value_name("NAME"),
+        number_of_values(1),
+        conflicts_with("example"),
+        long_help(indoc! {r#"
+            Bundle the binary and its dependencies.
+
+            If this option is given more than once, the binaries in one package are bundled into one file, each preceded by `--section-header` and wrapped in `mod bin_{name}`. A generated `fn main` runs the binary named by the first argument, or the first one without arguments. The libraries are expanded once and follow all of them.
+        "#})
+    )]
+    pub(crate) bin: Vec<String>,
+
+    /// Header of each binary when multiple `--bin`s are given. `{bin}` is replaced with the name
+    #[structopt(
+        long,
+        value_name("TEMPLATE"),
+        default_value("// ---------- {bin} ----------")
+    )]
+    pub(crate) section_header: String,
+
+    /// Bundle the binary example and its dependencies
+    #[structopt(long, value_name("NAME"))]
+    pub(crate) example: Option<String>,
+
+    /// Path to Cargo.toml
+    #[structopt(long, value_name("PATH"))]
+    pub(crate) manifest_path: Option<PathBuf>,
+
+    /// Directory of the workspace to use, instead of the one found from the current directory
+    #[structopt(long, value_name("PATH"), conflicts_with("manifest-path"))]
+    pub(crate) workspace_root: Option<PathBuf>,
+
+    /// Features of the package to activate, e.g. `local-debug`
+    #[structopt(long, value_name("FEATURES"), use_delimiter(true))]
+    pub(crate) features: Vec<String>,
+
+    /// Resolve crates that the target uses without depending on them from the registry in `cargo-equip/config.toml` in the config directory
+    #[structopt(long)]
+    pub(crate) use_registry: bool,
+
+    /// Do not ask which target to bundle when there are multiple ones
+    #[structopt(long)]
+    pub(crate) non_interactive: bool,
+
+    /// Path to the `cargo` executable to use instead of `$CARGO`
+    #[structopt(long, value_name("PATH"))]
+    pub(crate) cargo: Option<PathBuf>,
+
+    /// Exclude library crates from bundling
+    #[structopt(long, value_name("SPEC"))]
+    pub(crate) exclude: Vec<PkgSpec>,
+
+    /// Alias for `--exclude {crates available on AtCoder}`
+    #[structopt(
+        long,
+        long_help(Box::leak(
+            format!(
+                "Alias for:\n--exclude {}\n ",
+                ATCODER_CRATES.iter().format("\n          "),
+            )
+            .into_boxed_str(),
+        ))
+    )]
+    pub(crate) exclude_atcoder_crates: bool,
+
+    /// Alias for `--exclude {crates available on CodinGame}`
+    #[structopt(
+        long,
+        long_help(Box::leak(
+            format!(
+                "Alias for:\n--exclude {}\n ",
+                CODINGAME_CRATES.iter().format("\n          "),
+            )
+            .into_boxed_str(),
+        ))
+    )]
+    pub(crate) exclude_codingame_crates: bool,
+
+    /// Bundle only the modules, their descendants, and the modules they refer to
+    #[structopt(
+        long,
+        value_name("MODULES"),
+        use_delimiter(true),
+        conflicts_with("vendor"),
+        long_help(indoc! {r#"
+            Bundle only the modules, their descendants, and the modules they refer to.
+
+            Modules are given as absolute paths such as `::mylib::graph,::mylib::math`. Other modules of the crates are removed. References are found by scanning paths in the code, so modules used only through glob imports or macros have to be listed explicitly.
+        "#})
+    )]
+    pub(crate) only: Vec<String>,
+
+    /// Leave library crates whose source cannot be found unbundled instead of failing
+    #[structopt(long)]
+    pub(crate) exclude_unresolved: bool,
+
+    /// Do not include license and copyright notices for the users
+    #[structopt(
+        long,
+        value_name("DOMAIN_AND_USERNAME"),
+        long_help(
+            concat!(
+                indoc! {r#"
+                    Do not include license and copyright notices for the users.
+
+                    Supported formats:
+                    * github.com/{username}
+                    * gitlab.com/{username}
+                "#},
+                ' ',
+            )
+        )
+    )]
+    pub(crate) mine: Vec<User>,
+
+    /// `nightly` toolchain for `cargo-udeps` and `--allow-nightly` [default: nightly]
+    #[structopt(long, value_name("TOOLCHAIN"))]
+    pub(crate) toolchain: Option<String>,
+
+    /// Expand the libraries to the module
+    #[structopt(long, value_name("MODULE_PATH"), default_value("crate::__cargo_equip"))]
+    pub(crate) mod_path: CrateSinglePath,
+
+    /// Rename `fn main` of the bin to the name and call it from a generated `fn main`
+    #[structopt(long, value_name("NAME"), parse(try_from_str = syn::parse_str))]
+    pub(crate) entry_point: Option<syn::Ident>,
+
+    /// Allow `#![feature(..)]`s in the libraries and check the output with `--toolchain`
+    #[structopt(long)]
+    pub(crate) allow_nightly: bool,
+
+    /// Replace `include_str!`s and `include_bytes!`s with literals
+    #[structopt(long)]
+    pub(crate) inline_assets: bool,
+
+    /// Replace `env!("NAME")`s and `option_env!("NAME")`s with the value
+    #[structopt(long, value_name("NAME=VALUE"), parse(try_from_str = parse_define))]
+    pub(crate) define: Vec<(String, String)>,
+
+    /// Remove `eprint!`s, `eprintln!`s and `dbg!`s
+    #[structopt(long)]
+    pub(crate) strip_debug: bool,
+
+    /// Remove `debug_assert!`s, `debug_assert_eq!`s and `debug_assert_ne!`s
+    #[structopt(long)]
+    pub(crate) strip_debug_asserts: bool,
+
+    /// Rewrite `core::` and `alloc::` paths into `std::`, and remove `extern crate alloc;`
+    #[structopt(long)]
+    pub(crate) normalize_core_alloc: bool,
+
+    /// Remove the derive macros from the libraries, e.g. `Debug,Clone`
+    #[structopt(long, value_name("NAMES"), use_delimiter(true))]
+    pub(crate) strip_derives: Vec<String>,
+
+    /// Fail if the output uses file system, environment variables, network, processes, or threads
+    #[structopt(long)]
+    pub(crate) deny_io: bool,
+
+    /// Remove some part [possible values: docs, comments, hidden-modules]
+    #[structopt(
+        long,
+        value_name("REMOVE"),
+        possible_values(Remove::VARIANTS),
+        hide_possible_values(true),
+        long_help(concat!(
+            indoc! {r#"
+                Removes
+                * doc comments (`//! ..`, `/// ..`, `/** .. */`, `#[doc = ".."]`) with `--remove docs`.
+                * comments (`// ..`, `/* .. */`) with `--remove comments`.
+                * `#[doc(hidden)]` modules that are not referred to from the other modules or the bin with `--remove hidden-modules`.
+
+                ```
+                #[allow(dead_code)]
+                pub mod a {
+                    //! A.
+
+                    /// A.
+                    pub struct A; // aaaaa
+                }
+                ```
+
+                ↓
+
+                ```
+                #[allow(dead_code)]
+                pub mod a {
+                    pub struct A;
+                }
+                ```
+            "#},
+            ' ',
+        ))
+    )]
+    pub(crate) remove: Vec<Remove>,
+
+    /// Make the output fail to compile with rustc older than the version, e.g. `1.70`
+    #[structopt(
+        long,
+        value_name("VERSION"),
+        parse(try_from_str = parse_rustc_version),
+        long_help(indoc! {r#"
+            Make the output fail to compile with rustc older than the version, e.g. `1.70`.
+
+            A function using an API stabilized in the version is added to the top of the output, so that old rustc fails with an error pointing to it rather than to the bundled code. If there is no such API known to cargo-equip, the latest known version not newer than the given one is used.
+        "#})
+    )]
+    pub(crate) min_rustc: Option<semver::Version>,
+
+    /// Warn when the bin uses `#[deprecated]` items of the bundled libraries
+    #[structopt(long)]
+    pub(crate) warn_deprecated: bool,
+
+    /// What to output [default: all]  [possible values: all, lib-only]
+    #[structopt(
+        long,
+        value_name("EMIT"),
+        possible_values(Emit::VARIANTS),
+        hide_possible_values(true),
+        conflicts_with_all(&["lib", "entry-point"]),
+        long_help(indoc! {r#"
+            What to output.
+
+            With `--emit lib-only`, the bin is omitted and only the bundled libraries are output along with `pub use __cargo_equip::prelude::*;`, so that the bin can be pasted above them by hand.
+        "#})
+    )]
+    pub(crate) emit: Option<Emit>,
+
+    /// Leave the crate root of the bin as written, importing the bundled crates below it
+    #[structopt(long)]
+    pub(crate) verbatim_bin: bool,
+
+    /// Minify part of the output before emitting [default: none]  [possible values: none, libs, all]
+    #[structopt(
+        long,
+        value_name("MINIFY"),
+        possible_values(Minify::VARIANTS),
+        hide_possible_values(true),
+        long_help(concat!(
+            indoc! {r#"
+                Minifies
+                - each expaned library with `--minify lib`.
+                - the whole code with `--minify all`.
+
+                Not that the minification function is incomplete. Unnecessary spaces may be inserted.
+            "#},
+            ' ',
+        ))
+    )]
+    pub(crate) minify: Option<Minify>,
+
+    /// Use the settings of the profile [built-in: atcoder, codeforces, codingame]
+    #[structopt(
+        long,
+        value_name("NAME"),
+        long_help(concat!(
+            indoc! {r#"
+                Use the settings of the profile.
+
+                Profiles are defined in `workspace.metadata.cargo-equip.profiles` or `package.metadata.cargo-equip.profiles`:
+
+                ```
+                [package.metadata.cargo-equip.profiles.mine]
+                toolchain = "nightly-2022-03-01"
+                exclude = ["num:0.2.1"]
+                max-size = 65536
+                minify = "libs"
+                header = "// https://github.com/me/library"
+                ```
+
+                Options given on the command line take priority. `atcoder`, `codeforces` and `codingame` are built-in.
+            "#},
+            ' ',
+        ))
+    )]
+    pub(crate) profile: Option<String>,
+
+    /// Fail if the output exceeds the size in bytes
+    #[structopt(long, value_name("BYTES"))]
+    pub(crate) max_size: Option<usize>,
+
+    /// Do not resolve `cfg(..)`s
+    #[structopt(long)]
+    pub(crate) no_resolve_cfgs: bool,
+
+    /// Replace `cfg!(..)`s with `true` or `false` where `cfg(..)`s are resolved
+    #[structopt(long, conflicts_with("no-resolve-cfgs"))]
+    pub(crate) fold_cfg_macros: bool,
+
+    /// Resolve `cfg(target_*)`s and check the output for the target triple
+    #[structopt(long, value_name("TRIPLE"))]
+    pub(crate) target: Option<String>,
+
+    /// Do not format the output before emitting
+    #[structopt(long)]
+    pub(crate) no_rustfmt: bool,
+
+    /// Run without accessing the network
+    #[structopt(
+        long,
+        long_help(indoc! {r#"
+            Run without accessing the network.
+
+            `--offline` is passed to every `cargo` invocation, so `cargo metadata` fails if the dependencies are not downloaded yet. With `--mine`, the owners of crates.io packages must have been cached by a previous run.
+        "#})
+    )]
+    pub(crate) offline: bool,
+
+    /// Reuse the output of `cargo metadata` while the manifests and the lockfile are unchanged
+    #[structopt(
+        long,
+        long_help(indoc! {r#"
+            Reuse the output of `cargo metadata` while the manifests and the lockfile are unchanged.
+
+            The output is kept in the cache directory, keyed by the manifest path and `--features`, and invalidated when `Cargo.toml` of any local package or `Cargo.lock` changes. Changes to `.cargo/config.toml` or the registry index are not detected.
+        "#})
+    )]
+    pub(crate) cache_metadata: bool,
+
+    /// Re-expand every library instead of reusing the expansions from the previous runs
+    #[structopt(long)]
+    pub(crate) no_cache: bool,
+
+    /// Do not check the output before emitting
+    #[structopt(long)]
+    pub(crate) no_check: bool,
+
+    /// Check the output even if it is unchanged since the last successful check
+    #[structopt(long, conflicts_with("no-check"))]
+    pub(crate) force_check: bool,
+
+    /// How to check the output [default: check]  [possible values: check, build, build-release]
+    #[structopt(
+        long,
+        value_name("MODE"),
+        possible_values(CheckMode::VARIANTS),
+        hide_possible_values(true),
+        conflicts_with("no-check"),
+        long_help(indoc! {r#"
+            How to check the output.
+
+            `build` and `build-release` run `cargo build` instead of `cargo check`, which also catches errors only reported at code generation or link time.
+        "#})
+    )]
+    pub(crate) check_mode: Option<CheckMode>,
+
+    /// Check the output with each of the toolchains
+    #[structopt(
+        long,
+        value_name("TOOLCHAINS"),
+        use_delimiter(true),
+        conflicts_with("no-check"),
+        long_help(indoc! {r#"
+            Check the output with each of the toolchains, such as `1.42.0,1.70.0,stable`, and print the results as a table.
+
+            Fails if any of them fails.
+        "#})
+    )]
+    pub(crate) check_toolchains: Vec<String>,
+
+    /// Flags appended to `$RUSTFLAGS` when checking the output
+    #[structopt(
+        long,
+        value_name("FLAGS"),
+        allow_hyphen_values(true),
+        conflicts_with("no-check")
+    )]
+    pub(crate) check_rustflags: Option<String>,
+
+    /// Set the environment variable for checking the output
+    #[structopt(long, value_name("KEY=VALUE"), parse(try_from_str = parse_define))]
+    pub(crate) check_env: Vec<(String, String)>,
+
+    /// Check the output without `RUSTC_WRAPPER` or `build.rustc-wrapper` such as sccache
+    #[structopt(long)]
+    pub(crate) no_rustc_wrapper: bool,
+
+    /// Write to the file instead of STDOUT
+    #[structopt(short, long, value_name("PATH"))]
+    pub(crate) output: Option<PathBuf>,
+
+    /// Keep the previous content of the output file as `<PATH>.bak`
+    #[structopt(long)]
+    pub(crate) backup: bool,
+
+    /// Print only errors to STDERR
+    #[structopt(
+        short,
+        long,
+        long_help(indoc! {r#"
+            Print only errors to STDERR.
+
+            Statuses and warnings of cargo-equip are suppressed, and `--quiet` is passed to `cargo check`. Only the output goes to STDOUT in any case.
+        "#})
+    )]
+    pub(crate) quiet: bool,
+
+    /// Re-bundle the code in the regions between `cargo-equip` markers in the file, keeping the rest
+    #[structopt(
+        long,
+        value_name("PATH"),
+        conflicts_with_all(&["output", "vendor", "reproduce"])
+    )]
+    pub(crate) update: Option<PathBuf>,
+
+    /// Print the difference from the file given by `--output` instead of writing to it
+    #[structopt(long, requires("output"))]
+    pub(crate) diff: bool,
+
+    /// Same as `--diff`, but fail if there is any difference
+    #[structopt(long, requires("output"), conflicts_with("diff"))]
+    pub(crate) check_diff: bool,
+
+    /// Print sizes of the bundled crates and time taken by each phase
+    #[structopt(long)]
+    pub(crate) report: bool,
+
+    /// Print why each crate and module is bundled or not
+    #[structopt(
+        long,
+        long_help(indoc! {r#"
+            Print why each crate and module is bundled or not.
+
+            For each dependency, prints the chain of dependencies that pulled it in, or why it is left out. With `--only`, also prints why each module of the crates is kept or removed.
+        "#})
+    )]
+    pub(crate) explain: bool,
+
+    /// Print time taken by each phase
+    #[structopt(long)]
+    pub(crate) time: bool,
+
+    /// Append a comment line recording the bundled crates and the arguments
+    #[structopt(long)]
+    pub(crate) provenance: bool,
+
+    /// Check that the file written with `--provenance` is reproduced with the recorded arguments
+    #[structopt(long, value_name("PATH"))]
+    pub(crate) reproduce: Option<PathBuf>,
+
+    /// Keep running and bundle the buffers sent from an editor through STDIN
+    #[structopt(
+        long,
+        conflicts_with_all(&["src", "output", "backup", "update", "diff", "vendor", "reproduce"]),
+        long_help(indoc! {r#"
+            Keep running and bundle the buffers sent from an editor through STDIN.
+
+            Each request is the path of the buffer's file on a line, the length of the code in bytes on a line, and the code. The code is bundled as `--src <PATH>` with the rest of the arguments.
+
+            Each response is `code <LENGTH>` and the bundled code, `warnings <LENGTH>` and the warnings, then `ok` or `error <LENGTH>` and the error. Each header is on its own line, and the lengths are in bytes. The code is empty on an error. The output of `cargo check` goes to STDERR.
+
+            Exits at the end of STDIN.
+        "#})
+    )]
+    pub(crate) server: bool,
+
+    /// Write the code of each crate after each transformation to the directory, for debugging
+    #[structopt(long, value_name("DIR"))]
+    pub(crate) snapshot_dir: Option<PathBuf>,
+
+    /// Format of errors [default: human]  [possible values: human, json]
+    #[structopt(
+        long,
+        value_name("FMT"),
+        possible_values(ErrorFormat::VARIANTS),
+        hide_possible_values(true),
+        default_value("human"),
+        hide_default_value(true)
+    )]
+    pub(crate) error_format: ErrorFormat,
+
+    /// Write the target and the libraries as a package to the directory instead of bundling them
+    #[structopt(
+        long,
+        value_name("DIR"),
+        conflicts_with("output"),
+        long_help(indoc! {r#"
+            Write the target and the libraries as a package to the directory instead of bundling them.
+
+            Each library is written to `crates/{name}/lib.rs` with a generated `Cargo.toml` and is referred to with a `path` dependency. Modules are inlined, but paths are left as they are.
+        "#})
+    )]
+    pub(crate) vendor: Option<PathBuf>,
+
+    /// [Deprecated] Alias for `--minify`
+    #[structopt(
+        long,
+        value_name("MINIFY"),
+        possible_values(Minify::VARIANTS),
+        default_value("none")
+    )]
+    pub(crate) oneline: Minify,
+
+    /// [Deprecated] No-op
+    #[structopt(long, conflicts_with("no-resolve-cfgs"))]
+    pub(crate) resolve_cfgs: bool,
+
+    /// [Deprecated] No-op
+    #[structopt(long, conflicts_with("no-rustfmt"))]
+    pub(crate) rustfmt: bool,
+
+    /// [Deprecated] No-op
+    #[structopt(long, conflicts_with("no-check"))]
+    pub(crate) check: bool,
+
+    #[structopt(subcommand)]
+    pub(crate) command: Option<EquipCommand>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum EquipCommand {
+    /// Export the code of the libraries in the workspace as a document, organized by module
+    Notebook(OptNotebook),
+
+    /// Show sizes of the modules of the libraries in the workspace and the targets that use them
+    Stats {
+        /// Path to Cargo.toml
+        #[structopt(long, value_name("PATH"))]
+        manifest_path: Option<PathBuf>,
+    },
+
+    /// List the targets that would be affected by changes to a module of a library in the workspace
+    Rdeps {
+        /// Path to Cargo.toml
+        #[structopt(long, value_name("PATH"))]
+        manifest_path: Option<PathBuf>,
+
+        /// Module path (e.g. `::mylib::a`)
+        #[structopt(value_name("MODULE"))]
+        module: String,
+    },
+
+    /// Diagnose the environment and the workspace
+    Doctor {
+        /// Path to Cargo.toml
+        #[structopt(long, value_name("PATH"))]
+        manifest_path: Option<PathBuf>,
+    },
+
+    /// Bundle the target with several strategies and compare the sizes of the outputs
+    BenchSize,
+
+    /// Keep running and bundle with the requests from a Unix socket, reusing the caches
+    #[structopt(long_about(indoc! {r#"
+        Keep running and bundle with the requests from a Unix socket, reusing the caches.
+
+        Requests are JSON-RPC 2.0, one per line. `bundle` takes `args`, which follow the arguments given before `daemon`, and optionally `cwd`, `src` and `code`. `code` is bundled in place of the content of `src`. The result is `{"code": ..., "warnings": ...}`. `shutdown` stops the daemon.
+
+        `--cache-metadata` is always enabled, and the metadata and the expansions are kept in memory. `args` cannot contain `--cargo` or `--src -`.
+    "#}))]
+    Daemon {
+        /// Path to the socket [default: {cache directory}/daemon.sock]
+        #[structopt(long, value_name("PATH"))]
+        socket: Option<PathBuf>,
+    },
+
+    /// Check the `cargo-equip` metadata of the workspace, exiting with non-zero on problems
+    VerifyMetadata {
+        /// Path to Cargo.toml
+        #[structopt(long, value_name("PATH"))]
+        manifest_path: Option<PathBuf>,
+    },
+
+    /// Replace this executable with the latest release on GitHub
+    SelfUpdate {
+        /// Check for a new release without downloading it
+        #[structopt(long)]
+        dry_run: bool,
+    },
+
+    /// Generate a completion script for the shell [possible values: bash, zsh, fish, powershell, elvish]
+    Completions {
+        /// Shell
+        #[structopt(possible_values(&clap::Shell::variants()), hide_possible_values(true))]
+        shell: clap::Shell,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub struct OptNotebook {
+    /// Format of the document [default: markdown]  [possible values: markdown, latex]
+    #[structopt(
+        long,
+        value_name("FORMAT"),
+        possible_values(NotebookFormat::VARIANTS),
+        hide_possible_values(true),
+        default_value("markdown"),
+        hide_default_value(true)
+    )]
+    pub(crate) format: NotebookFormat,
+
+    /// Path to Cargo.toml
+    #[structopt(long, value_name("PATH"))]
+    pub(crate) manifest_path: Option<PathBuf>,
+
+    /// Remove some part [possible values: docs, comments, hidden-modules]
+    #[structopt(
+        long,
+        value_name("REMOVE"),
+        possible_values(Remove::VARIANTS),
+        hide_possible_values(true)
+    )]
+    pub(crate) remove: Vec<Remove>,
+
+    /// Write to the file instead of STDOUT
+    #[structopt(short, long, value_name("PATH"))]
+    pub(crate) output: Option<PathBuf>,
+}
+
+fn parse_rustc_version(s: &str) -> Result<semver::Version, semver::Error> {
+    if s.matches('.').count() == 1 {
+        format!("{}.0", s).parse()
+    } else {
+        s.parse()
+    }
+}
+
+fn parse_define(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `NAME=VALUE`, got {:?}", s))?;
+    Ok((name.to_owned(), value.to_owned()))
+}
+
+impl Opt {
+    pub fn error_format(&self) -> ErrorFormat {
+        match self {
+            Opt::Equip(OptEquip { error_format, .. }) => *error_format,
+            Opt::RustAnalyzerProcMacro {} => ErrorFormat::Human,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+impl ErrorFormat {
+    const VARIANTS: &'static [&'static str] = &["human", "json"];
+}
+
+impl FromStr for ErrorFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, &'static str> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(r#"expected "human", or "json""#),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum User {
+    Github(String),
+    GitlabCom(String),
+}
+
+impl FromStr for User {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, &'static str> {
+        return if let Some(username) = s.strip_prefix("github.com/") {
+            Ok(Self::Github(username.to_owned()))
+        } else if let Some(username) = s.strip_prefix("gitlab.com/") {
+            Ok(Self::GitlabCom(username.to_owned()))
+        } else {
+            Err(MSG)
+        };
+
+        static MSG: &str = indoc! {r"
+            Supported formats:
+            * github.com/{username}
+            * gitlab.com/{username}
+        "};
+    }
+}
+
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "crate::{}", _0)]
+pub struct CrateSinglePath(pub(crate) syn::Ident);
+
+impl FromStr for CrateSinglePath {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, &'static str> {
+        (|| {
+            let syn::Path {
+                leading_colon,
+                segments,
+            } = syn::parse_str(s).map_err(|_| ())?;
+            match (leading_colon, &*segments.into_iter().collect::<Vec<_>>()) {
+                (None, [p1, p2]) if p1.ident == "crate" => Ok(Self(p2.ident.clone())),
+                _ => Err(()),
+            }
+        })()
+        .map_err(|()| "expected `crate::$ident`")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Remove {
+    Docs,
+    Comments,
+    HiddenModules,
+}
+
+impl Remove {
+    const VARIANTS: &'static [&'static str] = &["docs", "comments", "hidden-modules"];
+}
+
+impl FromStr for Remove {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, &'static str> {
+        match s {
+            "docs" => Ok(Self::Docs),
+            "comments" => Ok(Self::Comments),
+            "hidden-modules" => Ok(Self::HiddenModules),
+            _ => Err(r#"expected "docs", "comments", or "hidden-modules""#),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Emit {
+    All,
+    LibOnly,
+}
+
+impl Emit {
+    const VARIANTS: &'static [&'static str] = &["all", "lib-only"];
+}
+
+impl FromStr for Emit {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, &'static str> {
+        match s {
+            "all" => Ok(Self::All),
+            "lib-only" => Ok(Self::LibOnly),
+            _ => Err(r#"expected "all", or "lib-only""#),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CheckMode {
+    Check,
+    Build,
+    BuildRelease,
+}
+
+impl CheckMode {
+    const VARIANTS: &'static [&'static str] = &["check", "build", "build-release"];
+}
+
+impl FromStr for CheckMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, &'static str> {
+        match s {
+            "check" => Ok(Self::Check),
+            "build" => Ok(Self::Build),
+            "build-release" => Ok(Self::BuildRelease),
+            _ => Err(r#"expected "check", "build", or "build-release""#),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Minify {
+    None,
+    Libs,
+    All,
+}
+
+impl Minify {
+    const VARIANTS: &'static [&'static str] = &["none", "libs", "all"];
+}
+
+impl FromStr for Minify {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, &'static str> {
+        match s {
+            "none" => Ok(Self::None),
+            "libs" => Ok(Self::Libs),
+            "all" => Ok(Self::All),
+            _ => Err(r#"expected "none", "libs", or "all""#),
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use crate::{CheckMode, Minify, ATCODER_CRATES, CODINGAME_CRATES};
+use anyhow::{bail, Context as _};
+use cargo_metadata as cm;
+use itertools::Itertools as _;
+use krates::PkgSpec;
+use serde::{de::Error as _, Deserialize, Deserializer};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// `cargo-equip` section in `workspace.metadata` and `package.metadata`.
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Config {
+    pub(crate) check: Option<CheckPolicy>,
+    #[serde(default)]
+    pub(crate) hooks: Hooks,
+    #[serde(default)]
+    pub(crate) profiles: BTreeMap<String, Profile>,
+    /// Names of the modules for bundled crates, keyed by package name.
+    #[serde(default)]
+    pub(crate) rename: BTreeMap<String, String>,
+}
+
+/// `cargo-equip/config.toml` in the config directory of the user, e.g. `~/.config`.
+#[derive(Default, Debug)]
+pub(crate) struct GlobalConfig {
+    /// Paths to the packages that can be used without dependencies, keyed by extern crate names.
+    pub(crate) registry: BTreeMap<String, PathBuf>,
+}
+
+impl GlobalConfig {
+    pub(crate) fn read() -> anyhow::Result<Self> {
+        let path = dirs_next::config_dir()
+            .with_context(|| "could not find the config directory")?
+            .join("cargo-equip")
+            .join("config.toml");
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let dir = path.parent().expect("should not be root");
+
+        let doc = cargo_util::paths::read(&path)?
+            .parse::<toml_edit::Document>()
+            .with_context(|| format!("could not parse `{}`", path.display()))?;
+        let mut registry = BTreeMap::new();
+        if let Some(table) = doc["registry"].as_table() {
+            for (name, value) in table.iter() {
+                let value = value.as_str().with_context(|| {
+                    format!("`{}`: `registry.{}` must be a string", path.display(), name)
+                })?;
+                registry.insert(
+                    name.to_owned(),
+                    cargo_util::paths::normalize_path(&dir.join(value)),
+                );
+            }
+        }
+        Ok(Self { registry })
+    }
+}
+
+/// Shell commands run before and after bundling.
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Hooks {
+    pub(crate) pre_bundle: Option<String>,
+    pub(crate) post_bundle: Option<String>,
+}
+
+/// When to check the output.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum CheckPolicy {
+    Always,
+    Never,
+    /// Skip the check if the output is unchanged since the last successful check.
+    OnChange,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Profile {
+    pub(crate) toolchain: Option<String>,
+    #[serde(default, deserialize_with = "from_strs")]
+    pub(crate) exclude: Vec<PkgSpec>,
+    pub(crate) max_size: Option<usize>,
+    #[serde(default, deserialize_with = "from_str_opt")]
+    pub(crate) minify: Option<Minify>,
+    pub(crate) header: Option<String>,
+    /// Prepends `#![allow(warnings)]` to the output.
+    #[serde(default)]
+    pub(crate) allow_warnings: bool,
+    /// A file appended to the output, such as a fast IO scaffold. Relative to the workspace root.
+    pub(crate) io_template: Option<PathBuf>,
+    pub(crate) check_rustflags: Option<String>,
+    #[serde(default, deserialize_with = "from_str_opt")]
+    pub(crate) check_mode: Option<CheckMode>,
+    pub(crate) target: Option<String>,
+    #[serde(default, deserialize_with = "ident_opt")]
+    pub(crate) entry_point: Option<syn::Ident>,
+    #[serde(default)]
+    pub(crate) validate: Validate,
+}
+
+/// Limits of the judge which the output is checked against.
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Validate {
+    #[serde(default)]
+    pub(crate) ascii_only: bool,
+    #[serde(default)]
+    pub(crate) forbid_tabs: bool,
+    #[serde(default)]
+    pub(crate) forbid_bom: bool,
+    #[serde(default)]
+    pub(crate) forbid_crlf: bool,
+    /// Names of the macros, without `!`.
+    #[serde(default)]
+    pub(crate) forbidden_macros: Vec<String>,
+    /// Fail instead of warning.
+    #[serde(default)]
+    pub(crate) deny: bool,
+}
+
+impl Config {
+    pub(crate) fn read(metadata: &cm::Metadata, package: &cm::Package) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        let workspace_manifest_path = metadata.workspace_root.join("Cargo.toml");
+        for (value, manifest_path, key) in [
+            (
+                &metadata.workspace_metadata,
+                &workspace_manifest_path,
+                "workspace.metadata.cargo-equip",
+            ),
+            (
+                &package.metadata,
+                &package.manifest_path,
+                "package.metadata.cargo-equip",
+            ),
+        ] {
+            if let Some(value) = value.get("cargo-equip") {
+                let Self {
+                    check,
+                    hooks,
+                    profiles,
+                    rename,
+                } = Self::parse(value, manifest_path.as_ref(), key)?;
+                config.check = check.or(config.check);
+                config.hooks.pre_bundle = hooks.pre_bundle.or(config.hooks.pre_bundle);
+                config.hooks.post_bundle = hooks.post_bundle.or(config.hooks.post_bundle);
+                config.profiles.extend(profiles);
+                config.rename.extend(rename);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Parses `value`, which is at `key` in `manifest_path`.
+    ///
+    /// On failure, finds the innermost key that cannot be parsed and reports its line and column.
+    pub(crate) fn parse(
+        value: &serde_json::Value,
+        manifest_path: &Path,
+        key: &str,
+    ) -> anyhow::Result<Self> {
+        let err = match serde_json::from_value(value.clone()) {
+            Ok(config) => return Ok(config),
+            Err(err) => err,
+        };
+        let key = key
+            .split('.')
+            .map(ToOwned::to_owned)
+            .chain(
+                leaf_paths(value)
+                    .into_iter()
+                    .find(|path| serde_json::from_value::<Self>(prune(value, path)).is_err())
+                    .unwrap_or_default(),
+            )
+            .collect::<Vec<_>>();
+        let location = cargo_util::paths::read(manifest_path)
+            .ok()
+            .and_then(|manifest| locate_key(&manifest, &key));
+        let location = match location {
+            Some((line, column)) => format!("{}:{}:{}", manifest_path.display(), line, column),
+            None => manifest_path.display().to_string(),
+        };
+        Err(anyhow::Error::new(err).context(format!(
+            "could not parse `{}` at {}",
+            key.join("."),
+            location,
+        )))
+    }
+
+    pub(crate) fn profile(mut self, name: &str) -> anyhow::Result<Profile> {
+        if let Some(profile) = self.profiles.remove(name) {
+            return Ok(profile);
+        }
+        match name {
+            "atcoder" => Ok(Profile {
+                exclude: parse_specs(ATCODER_CRATES),
+                max_size: Some(512 * 1024),
+                ..Profile::default()
+            }),
+            "codeforces" => Ok(Profile {
+                max_size: Some(64 * 1024),
+                minify: Some(Minify::Libs),
+                allow_warnings: true,
+                ..Profile::default()
+            }),
+            "codingame" => Ok(Profile {
+                exclude: parse_specs(CODINGAME_CRATES),
+                max_size: Some(100_000),
+                ..Profile::default()
+            }),
+            _ => bail!(
+                "no such profile: `{}`. available profiles: {}",
+                name,
+                ["atcoder", "codeforces", "codingame"]
+                    .iter()
+                    .copied()
+                    .chain(self.profiles.keys().map(|s| &**s))
+                    .sorted()
+                    .dedup()
+                    .format(", "),
+            ),
+        }
+    }
+}
+
+/// Paths to the values that are not non-empty objects.
+fn leaf_paths(value: &serde_json::Value) -> Vec<Vec<String>> {
+    match value {
+        serde_json::Value::Object(map) if !map.is_empty() => map
+            .iter()
+            .flat_map(|(key, value)| {
+                leaf_paths(value).into_iter().map(move |mut path| {
+                    path.insert(0, key.clone());
+                    path
+                })
+            })
+            .collect(),
+        _ => vec![vec![]],
+    }
+}
+
+/// `value` with only the entries along `path`.
+fn prune(value: &serde_json::Value, path: &[String]) -> serde_json::Value {
+    match path.split_first() {
+        Some((key, path)) => json!({ key: prune(&value[key], path) }),
+        None => value.clone(),
+    }
+}
+
+/// Finds the 1-based line and column of `key` in a TOML document, or of the table or inline table
+/// closest to it.
+fn locate_key(toml: &str, key: &[String]) -> Option<(usize, usize)> {
+    let split = |key: &str| -> Vec<String> {
+        key.split('.')
+            .map(|k| k.trim().trim_matches(|c| c == '"' || c == '\'').to_owned())
+            .collect()
+    };
+
+    let mut table = vec![];
+    let mut found = None::<(usize, usize, &str)>;
+    for (i, line) in toml.lines().enumerate() {
+        let trimmed = line.trim_start();
+        let keys = if let Some(header) = trimmed.strip_prefix('[') {
+            let header = header.trim_start_matches('[');
+            table = split(&header[..header.find(']').unwrap_or(header.len())]);
+            table.clone()
+        } else if let (Some(eq), false) = (trimmed.find('='), trimmed.starts_with('#')) {
+            itertools::chain(table.clone(), split(&trimmed[..eq])).collect()
+        } else {
+            continue;
+        };
+        let len = keys.iter().zip(key).take_while(|(k1, k2)| k1 == k2).count();
+        if len == keys.len() && found.map_or(true, |(found_len, ..)| len > found_len) {
+            found = Some((len, i, line));
+        }
+    }
+
+    let (len, i, line) = found?;
+    let mut column = line.len() - line.trim_start().len();
+    for k in &key[len..] {
+        match line[column..].find(&**k) {
+            Some(offset) => column += offset,
+            None => break,
+        }
+    }
+    Some((i + 1, column + 1))
+}
+
+fn parse_specs(specs: &[&str]) -> Vec<PkgSpec> {
+    specs.iter().map(|s| s.parse().unwrap()).collect()
+}
+
+fn from_strs<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| s.parse().map_err(D::Error::custom))
+        .collect()
+}
+
+fn from_str_opt<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| s.parse().map_err(D::Error::custom))
+        .transpose()
+}
+
+fn ident_opt<'de, D>(deserializer: D) -> Result<Option<syn::Ident>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| syn::parse_str(&s).map_err(D::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use indoc::indoc;
+    use std::fs;
+
+    #[test]
+    fn parse_reports_location() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest_path = &dir.path().join("Cargo.toml");
+        let manifest = indoc! {r#"
+            [package]
+            name = "a"
+            version = "0.0.0"
+
+            [package.metadata.cargo-equip]
+            check = "always"
+
+            [package.metadata.cargo-equip.profiles.atcoder]
+            toolchain = "1.42.0"
+            max-size = "512KiB"
+
+            [package.metadata.cargo-equip.profiles.codeforces]
+            validate = { ascii-only = true, forbid-tab = true }
+        "#};
+        fs::write(manifest_path, manifest).unwrap();
+        let parse = |value| {
+            let err =
+                Config::parse(&value, manifest_path, "package.metadata.cargo-equip").unwrap_err();
+            format!("{:#}", err).replace(&*manifest_path.to_string_lossy(), "Cargo.toml")
+        };
+
+        assert_eq!(
+            "could not parse `package.metadata.cargo-equip.profiles.atcoder.max-size` at \
+             Cargo.toml:10:1: invalid type: string \"512KiB\", expected usize",
+            parse(serde_json::json!({
+                "check": "always",
+                "profiles": { "atcoder": { "toolchain": "1.42.0", "max-size": "512KiB" } },
+            })),
+        );
+        assert_eq!(
+            "could not parse `package.metadata.cargo-equip.profiles.codeforces.validate.forbid-tab` \
+             at Cargo.toml:13:33: unknown field `forbid-tab`, expected one of `ascii-only`, \
+             `forbid-tabs`, `forbid-bom`, `forbid-crlf`, `forbidden-macros`, `deny`",
+            parse(serde_json::json!({
+                "profiles": {
+                    "codeforces": { "validate": { "ascii-only": true, "forbid-tab": true } },
+                },
+            })),
+        );
+    }
+
+    #[test]
+    fn profile_entry_point() {
+        let config = serde_json::from_value::<Config>(serde_json::json!({
+            "profiles": { "judge": { "entry-point": "solve" } },
+        }))
+        .unwrap();
+        let profile = config.profile("judge").unwrap();
+        assert_eq!(
+            Some("solve"),
+            profile.entry_point.map(|i| i.to_string()).as_deref()
+        );
+
+        assert!(serde_json::from_value::<Config>(serde_json::json!({
+            "profiles": { "judge": { "entry-point": "not an identifier" } },
+        }))
+        .is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use crate::{expansion_cache::Expansion, metadata_cache, provenance, shell::Shell, Context};
+use anyhow::{bail, Context as _};
+use camino::Utf8PathBuf;
+use itertools::Itertools as _;
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// What the daemon keeps in memory between the requests.
+///
+/// The entries are keyed by their paths in the on-disk caches, which are per workspace.
+#[derive(Default, Debug)]
+pub(crate) struct State {
+    pub(crate) metadata: HashMap<PathBuf, metadata_cache::Entry>,
+    pub(crate) expansions: HashMap<Utf8PathBuf, Expansion>,
+}
+
+/// A `bundle` request.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct BundleParams {
+    /// Arguments for `cargo equip`, following the ones given to the daemon.
+    #[serde(default)]
+    args: Vec<String>,
+    /// Defaults to the working directory of the daemon.
+    cwd: Option<PathBuf>,
+    /// Code to bundle in place of the content of `src`.
+    code: Option<String>,
+    src: Option<PathBuf>,
+}
+
+/// `cargo equip daemon`: serves JSON-RPC requests, one per line, over a Unix socket.
+///
+/// Each request runs in this process with `--cache-metadata`. The metadata and the expansions of the
+/// libraries are kept in memory, and the check package is reused from the previous requests.
+pub(crate) fn daemon(
+    args: &[String],
+    socket: Option<PathBuf>,
+    ctx: Context<'_>,
+) -> anyhow::Result<()> {
+    let Context {
+        cwd,
+        cargo_equip_exe,
+        cache_dir,
+        shell,
+    } = ctx;
+
+    let mut common_args = vec![];
+    let mut rest = provenance::strip_output_args(args.iter().cloned()).into_iter();
+    while let Some(arg) = rest.next() {
+        if arg == "--socket" {
+            rest.next();
+        } else if arg != "daemon" && !arg.starts_with("--socket=") {
+            common_args.push(arg);
+        }
+    }
+    if !common_args.iter().any(|a| a == "--cache-metadata") {
+        common_args.push("--cache-metadata".to_owned());
+    }
+
+    let state = Rc::new(RefCell::new(State::default()));
+
+    let socket = match socket {
+        Some(socket) => cwd.join(socket),
+        None => cache_dir.join("daemon.sock"),
+    };
+
+    listen(&socket, shell, |line| {
+        let request = match serde_json::from_str::<serde_json::Value>(line) {
+            Ok(request) => request,
+            Err(err) => return (error(&json!(null), -32700, err, None), false),
+        };
+        let id = request.get("id").cloned().unwrap_or(json!(null));
+        let params = request.get("params").cloned().unwrap_or(json!({}));
+        match request.get("method").and_then(|m| m.as_str()) {
+            Some("bundle") => {
+                let params = match serde_json::from_value::<BundleParams>(params) {
+                    Ok(params) => params,
+                    Err(err) => return (error(&id, -32602, err, None), false),
+                };
+                let response = bundle(
+                    params,
+                    &common_args,
+                    &state,
+                    &cwd,
+                    &cargo_equip_exe,
+                    &cache_dir,
+                );
+                let response = match response {
+                    Ok((Ok(code), warnings)) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "result": { "code": code, "warnings": warnings },
+                    }),
+                    Ok((Err(err), warnings)) => {
+                        error(&id, -32000, format!("{:?}", err), Some(&warnings))
+                    }
+                    Err(err) => error(&id, -32602, err, None),
+                };
+                (response, false)
+            }
+            Some("shutdown") => (json!({ "jsonrpc": "2.0", "id": id, "result": null }), true),
+            _ => (error(&id, -32601, "method not found", None), false),
+        }
+    })
+}
+
+fn bundle(
+    params: BundleParams,
+    common_args: &[String],
+    state: &Rc<RefCell<State>>,
+    cwd: &Path,
+    cargo_equip_exe: &ra_ap_paths::AbsPathBuf,
+    cache_dir: &Path,
+) -> anyhow::Result<(anyhow::Result<String>, String)> {
+    let BundleParams {
+        args,
+        cwd: request_cwd,
+        code,
+        src,
+    } = params;
+    if code.is_some() && src.is_none() {
+        bail!("`code` requires `src`");
+    }
+    // `--cargo` is set once per process.
+    if args
+        .iter()
+        .any(|a| a == "--cargo" || a.starts_with("--cargo="))
+    {
+        bail!("`--cargo` can be given only to the daemon");
+    }
+    let stdin_src = src.as_deref() == Some(Path::new("-"))
+        || args.iter().any(|a| a == "--src=-")
+        || args
+            .iter()
+            .tuple_windows()
+            .any(|(a, b)| a == "--src" && b == "-");
+    if stdin_src {
+        bail!("`--src -` is not available in the daemon; give the code with `code` instead");
+    }
+    let cwd = match request_cwd {
+        Some(request_cwd) => cwd.join(request_cwd),
+        None => cwd.to_owned(),
+    };
+    let args = itertools::chain!(
+        common_args.iter().cloned(),
+        // Always given by the daemon.
+        args.into_iter().filter(|a| a != "--cache-metadata"),
+        src.into_iter()
+            .flat_map(|src| ["--src".to_owned(), src.display().to_string()]),
+    )
+    .collect();
+    crate::bundle_captured(
+        args,
+        code,
+        Some(state.clone()),
+        &cwd,
+        cargo_equip_exe,
+        cache_dir,
+    )
+    .map_err(Into::into)
+}
+
+fn error(
+    id: &serde_json::Value,
+    code: i64,
+    message: impl ToString,
+    warnings: Option<&str>,
+) -> serde_json::Value {
+    let mut error = json!({ "code": code, "message": message.to_string() });
+    if let Some(warnings) = warnings {
+        error["data"] = json!({ "warnings": warnings });
+    }
+    json!({ "jsonrpc": "2.0", "id": id, "error": error })
+}
+
+/// Accepts connections one by one and answers each line with `handle` until it returns `true`.
+#[cfg(unix)]
+fn listen(
+    socket: &Path,
+    shell: &mut Shell,
+    mut handle: impl FnMut(&str) -> (serde_json::Value, bool),
+) -> anyhow::Result<()> {
+    use std::{
+        io::{BufRead as _, BufReader, Write as _},
+        os::unix::net::{UnixListener, UnixStream},
+    };
+
+    if socket.exists() {
+        if UnixStream::connect(socket).is_ok() {
+            bail!("a daemon is already listening on `{}`", socket.display());
+        }
+        cargo_util::paths::remove_file(socket)?;
+    }
+    if let Some(parent) = socket.parent() {
+        cargo_util::paths::create_dir_all(parent)?;
+    }
+    let listener = UnixListener::bind(socket)
+        .with_context(|| format!("could not bind `{}`", socket.display()))?;
+    shell.status("Listening", socket.display())?;
+
+    for stream in listener.incoming() {
+        let mut stream = stream?;
+        for line in BufReader::new(stream.try_clone()?).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (response, shutdown) = handle(&line);
+            if writeln!(stream, "{}", response).is_err() {
+                break;
+            }
+            if shutdown {
+                cargo_util::paths::remove_file(socket)?;
+                shell.status("Finished", "shut down")?;
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn listen(
+    _: &Path,
+    _: &mut Shell,
+    _: impl FnMut(&str) -> (serde_json::Value, bool),
+) -> anyhow::Result<()> {
+    bail!("`cargo equip daemon` is only supported on Unix");
+}
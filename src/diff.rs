@@ -0,0 +1,19 @@
+use similar::TextDiff;
+
+/// Returns a unified diff from `old` to `new`, or `None` if they are the same.
+pub(crate) fn unified_diff(
+    old: &str,
+    new: &str,
+    old_header: &str,
+    new_header: &str,
+) -> Option<String> {
+    if old == new {
+        return None;
+    }
+    Some(
+        TextDiff::from_lines(old, new)
+            .unified_diff()
+            .header(old_header, new_header)
+            .to_string(),
+    )
+}
@@ -0,0 +1,110 @@
+use crate::{
+    config::Config,
+    process::ProcessBuilderExt as _,
+    rust::CodeEdit,
+    shell::Shell,
+    workspace::{self, TargetExt as _},
+    ATCODER_CRATES, CODINGAME_CRATES,
+};
+use anyhow::bail;
+use cargo_util::ProcessBuilder;
+use krates::PkgSpec;
+use std::{env, path::Path};
+
+pub(crate) fn doctor(
+    manifest_path: &Path,
+    cwd: &Path,
+    cargo_equip_mod_name: &syn::Ident,
+    shell: &mut Shell,
+) -> anyhow::Result<()> {
+    let mut problems = 0;
+    let mut report = |result: anyhow::Result<String>, shell: &mut Shell| match result {
+        Ok(found) => shell.status("Ok", found),
+        Err(err) => {
+            problems += 1;
+            shell.warn(format!("{:#}", err))
+        }
+    };
+
+    for name in ["cargo", "rustc", "rustfmt"] {
+        let version = crate::process::cargo_exe().and_then(|cargo_exe| {
+            let exe = cargo_exe
+                .with_file_name(name)
+                .with_extension(env::consts::EXE_EXTENSION);
+            ProcessBuilder::new(exe)
+                .arg("--version")
+                .cwd(cwd)
+                .read_stdout::<String>()
+        });
+        report(version.map(|v| v.trim().to_owned()), shell)?;
+    }
+
+    let metadata = match workspace::cargo_metadata(manifest_path, cwd, &[], false) {
+        Ok(metadata) => metadata,
+        Err(err) => bail!("could not run `cargo metadata`: {}", err),
+    };
+
+    let lockfile = metadata.workspace_root.join("Cargo.lock");
+    report(
+        if lockfile.exists() {
+            Ok(format!("found `{}`", lockfile))
+        } else {
+            Err(anyhow::anyhow!(
+                "`{}` does not exist. run `cargo generate-lockfile` so that the check step uses \
+                 the same versions as the judge",
+                lockfile,
+            ))
+        },
+        shell,
+    )?;
+
+    for (list, flag) in [
+        (ATCODER_CRATES, "--exclude-atcoder-crates"),
+        (CODINGAME_CRATES, "--exclude-codingame-crates"),
+    ] {
+        let specs = list
+            .iter()
+            .map(|s| s.parse::<PkgSpec>().unwrap())
+            .collect::<Vec<_>>();
+        for package in &metadata.packages {
+            if package.source.is_some()
+                && specs.iter().any(|s| s.name == package.name)
+                && !specs.iter().any(|s| s.matches(package))
+            {
+                report(
+                    Err(anyhow::anyhow!(
+                        "`{}` is not excluded by `{}` because the list has another version of it",
+                        package.id,
+                        flag,
+                    )),
+                    shell,
+                )?;
+            }
+        }
+    }
+
+    for package in metadata
+        .packages
+        .iter()
+        .filter(|p| metadata.workspace_members.contains(&p.id))
+    {
+        report(
+            Config::read(&metadata, package)
+                .map(|_| format!("`package.metadata.cargo-equip` of `{}`", package.name)),
+            shell,
+        )?;
+
+        for target in package.targets.iter().filter(|t| t.is_lib()) {
+            let parsed = CodeEdit::new(cargo_equip_mod_name, &target.src_path, || {
+                (target.crate_name(), &package.id.repr)
+            })
+            .map(|_| format!("parsed `{}`", target.src_path));
+            report(parsed, shell)?;
+        }
+    }
+
+    if problems > 0 {
+        bail!("found {} problem(s)", problems);
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use crate::daemon;
+use camino::Utf8PathBuf;
+use cargo_metadata as cm;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+/// Expansions of the libraries from the previous runs, kept in
+/// `target/cargo-equip/expansions`.
+///
+/// Each package has one entry, which is reused if the inputs have the same hash. `cargo equip daemon`
+/// also keeps the entries in memory.
+pub(crate) struct ExpansionCache<'a> {
+    dir: Utf8PathBuf,
+    state: Option<&'a RefCell<daemon::State>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Expansion {
+    hash: String,
+    pub(crate) crate_mod: String,
+    pub(crate) macro_mod: String,
+    pub(crate) prelude_mod: String,
+    pub(crate) features: Vec<String>,
+}
+
+impl<'a> ExpansionCache<'a> {
+    pub(crate) fn new(metadata: &cm::Metadata, state: Option<&'a RefCell<daemon::State>>) -> Self {
+        Self {
+            dir: metadata
+                .target_directory
+                .join("cargo-equip")
+                .join("expansions"),
+            state,
+        }
+    }
+
+    pub(crate) fn hash(inputs: &[&str]) -> String {
+        let inputs = itertools::chain!([env!("CARGO_PKG_VERSION")], inputs.iter().copied());
+        format!("{:x}", md5::compute(inputs.collect::<Vec<_>>().join("\0")))
+    }
+
+    pub(crate) fn get(&self, package_id: &cm::PackageId, hash: &str) -> Option<Expansion> {
+        let path = self.path(package_id);
+        let in_memory = self
+            .state
+            .and_then(|state| state.borrow().expansions.get(&path).cloned());
+        let expansion = match in_memory {
+            Some(expansion) => expansion,
+            None => {
+                let json = cargo_util::paths::read(path.as_ref()).ok()?;
+                let expansion = serde_json::from_str::<Expansion>(&json).ok()?;
+                if let Some(state) = self.state {
+                    state
+                        .borrow_mut()
+                        .expansions
+                        .insert(path, expansion.clone());
+                }
+                expansion
+            }
+        };
+        Some(expansion).filter(|e| e.hash == hash)
+    }
+
+    pub(crate) fn put(
+        &self,
+        package_id: &cm::PackageId,
+        hash: String,
+        crate_mod: &str,
+        macro_mod: &str,
+        prelude_mod: &str,
+        features: &[String],
+    ) -> anyhow::Result<()> {
+        let expansion = Expansion {
+            hash,
+            crate_mod: crate_mod.to_owned(),
+            macro_mod: macro_mod.to_owned(),
+            prelude_mod: prelude_mod.to_owned(),
+            features: features.to_owned(),
+        };
+        let path = self.path(package_id);
+        cargo_util::paths::create_dir_all(&self.dir)?;
+        cargo_util::paths::write(&path, serde_json::to_string(&expansion)?)?;
+        if let Some(state) = self.state {
+            state.borrow_mut().expansions.insert(path, expansion);
+        }
+        Ok(())
+    }
+
+    fn path(&self, package_id: &cm::PackageId) -> Utf8PathBuf {
+        self.dir
+            .join(format!("{:x}.json", md5::compute(&package_id.repr)))
+    }
+}
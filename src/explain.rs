@@ -0,0 +1,147 @@
+use crate::{
+    rust::{self, CodeEdit, Kept},
+    shell::Shell,
+    workspace::TargetExt as _,
+};
+use anyhow::Context as _;
+use cargo_metadata as cm;
+use itertools::Itertools as _;
+use krates::PkgSpec;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+/// Prints why each crate and module is bundled or not.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn explain(
+    metadata: &cm::Metadata,
+    root_package: &cm::Package,
+    root: &cm::Target,
+    libs_to_bundle: &BTreeMap<&cm::PackageId, (&cm::Target, String)>,
+    exclude: &[PkgSpec],
+    unused_deps: &HashSet<String>,
+    unresolved: &[&cm::PackageId],
+    only: &BTreeMap<String, Vec<Vec<String>>>,
+    cargo_equip_mod_name: &syn::Ident,
+    shell: &mut Shell,
+) -> anyhow::Result<()> {
+    let nodes = metadata
+        .resolve
+        .as_ref()
+        .with_context(|| "`resolve` is `null`")?
+        .nodes
+        .iter()
+        .map(|n| (&n.id, n))
+        .collect::<HashMap<_, _>>();
+
+    // Breadth-first so that the shortest chains are shown. Dependencies of crates that are not
+    // bundled are not bundled either.
+    let mut parents = HashMap::new();
+    let mut reachable = vec![];
+    let mut queue = VecDeque::from([&root_package.id]);
+    while let Some(id) = queue.pop_front() {
+        for cm::NodeDep { pkg, .. } in &nodes[id].deps {
+            if *pkg != root_package.id && !parents.contains_key(pkg) {
+                parents.insert(pkg, id);
+                reachable.push(pkg);
+                if libs_to_bundle.contains_key(pkg) {
+                    queue.push_back(pkg);
+                }
+            }
+        }
+    }
+
+    // The lib of the same package as the bin is bundled without being a dependency.
+    if libs_to_bundle.contains_key(&root_package.id) && !root.is_lib() {
+        reachable.insert(0, &root_package.id);
+    }
+
+    let is_direct = |id| parents.get(id) == Some(&&root_package.id);
+    let mut rows = vec![];
+    for id in reachable {
+        let package = &metadata[id];
+        let why = if libs_to_bundle.contains_key(id) {
+            let mut chain = vec![id];
+            while let Some(parent) = parents.get(chain[chain.len() - 1]) {
+                chain.push(parent);
+            }
+            // `chain` ends with the root package unless `id` is the root package itself.
+            if chain.len() > 1 {
+                chain.pop();
+            }
+            let chain = itertools::chain!(
+                [format!("`{}`", root.name)],
+                chain
+                    .iter()
+                    .rev()
+                    .map(|id| format!("`{}`", metadata[id].name)),
+            )
+            .join(" -> ");
+            format!("bundled: {}", chain)
+        } else if exclude.iter().any(|s| s.matches(package)) {
+            "excluded".to_owned()
+        } else if is_direct(id) && unused_deps.contains(&package.name) {
+            "unused according to cargo-udeps".to_owned()
+        } else if unresolved.contains(&id) {
+            "the source was not found".to_owned()
+        } else {
+            "not needed by the target".to_owned()
+        };
+        rows.push((format!("{} v{}", package.name, package.version), why));
+    }
+    print_table(("crate", "why"), &rows, shell)?;
+
+    for (crate_name, only) in only {
+        let (package_id, (krate, _)) = libs_to_bundle
+            .iter()
+            .find(|(_, (krate, _))| krate.crate_name() == *crate_name)
+            .expect("should have been checked");
+        let code = CodeEdit::new(cargo_equip_mod_name, &krate.src_path, || {
+            (krate.crate_name(), &package_id.repr)
+        })?
+        .finish()?;
+        let modules = rust::module_deps(&code)?;
+        let keep = rust::modules_to_keep(&modules, only);
+
+        let display = |path: &[String]| {
+            format!(
+                "`{}`",
+                itertools::chain!(["", crate_name], path.iter().map(|s| &**s)).join("::"),
+            )
+        };
+        let rows = modules
+            .iter()
+            .map(|(path, ..)| {
+                let why = match keep.get(path) {
+                    Some(Kept::Listed) => "kept: listed in `--only`".to_owned(),
+                    Some(Kept::Ancestor(child)) => format!("kept: contains {}", display(child)),
+                    Some(Kept::ReferredFrom(from)) => {
+                        format!("kept: referred to from {}", display(from))
+                    }
+                    None => "removed: not reachable from `--only`".to_owned(),
+                };
+                (display(path), why)
+            })
+            .collect::<Vec<_>>();
+        print_table(("module", "why"), &rows, shell)?;
+    }
+    Ok(())
+}
+
+fn print_table(
+    (head1, head2): (&str, &str),
+    rows: &[(String, String)],
+    shell: &mut Shell,
+) -> anyhow::Result<()> {
+    let err = shell.err();
+    let width = rows
+        .iter()
+        .map(|(s, _)| s.len())
+        .max()
+        .unwrap_or(0)
+        .max(head1.len());
+    writeln!(err)?;
+    writeln!(err, "{:<width$} {}", head1, head2)?;
+    for (col1, col2) in rows {
+        writeln!(err, "{:<width$} {}", col1, col2)?;
+    }
+    Ok(())
+}
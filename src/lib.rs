@@ -3,350 +3,136 @@
 #![recursion_limit = "256"]
 
 mod cargo_udeps;
+mod cli;
+mod config;
+mod daemon;
+mod diff;
+mod doctor;
+mod expansion_cache;
+mod explain;
+mod metadata_cache;
+mod notebook;
 mod process;
+mod provenance;
 mod ra_proc_macro;
+mod report;
 mod rust;
 mod rustfmt;
+mod self_update;
 pub mod shell;
+mod stats;
 mod toolchain;
+mod transform;
+mod validate;
+mod vendor;
+mod verify_metadata;
 mod workspace;
 
+#[cfg(feature = "plugins")]
+pub use crate::transform::{register_transform, Crate, Transform};
+
+pub use crate::cli::{
+    CheckMode, CrateSinglePath, Emit, EquipCommand, ErrorFormat, Minify, Opt, OptEquip,
+    OptNotebook, Remove, User,
+};
+
 use crate::{
+    config::{CheckPolicy, Config, GlobalConfig, Profile},
+    expansion_cache::ExpansionCache,
+    metadata_cache::MetadataCache,
     ra_proc_macro::ProcMacroExpander,
+    report::Report,
     rust::CodeEdit,
     shell::Shell,
     workspace::{MetadataExt as _, PackageExt as _, PackageIdExt as _, TargetExt as _},
 };
-use anyhow::Context as _;
+use anyhow::{anyhow, bail, Context as _};
+use camino::{Utf8Path, Utf8PathBuf};
 use cargo_metadata as cm;
-use indoc::indoc;
 use itertools::{iproduct, Itertools as _};
 use krates::PkgSpec;
-use maplit::{btreeset, hashmap, hashset};
+use maplit::{btreemap, btreeset, hashmap, hashset};
 use petgraph::{
     graph::{Graph, NodeIndex},
     visit::Dfs,
 };
-use prettytable::{cell, format::FormatBuilder, row, Table};
 use quote::quote;
 use ra_ap_paths::{AbsPath, AbsPathBuf};
 use ra_ap_proc_macro_srv as proc_macro_srv;
 use std::{
+    cell::RefCell,
     cmp,
     collections::{BTreeMap, BTreeSet, HashMap, HashSet},
-    fmt::Debug,
+    env,
+    ffi::OsStr,
+    fmt::{self, Debug},
+    io::{self, BufRead as _, Read as _, Write as _},
+    mem,
     path::{Path, PathBuf},
-    str::FromStr,
+    rc::Rc,
 };
-use structopt::{clap::AppSettings, StructOpt};
-
-// We need to prepend " " to `long_help`s.
-// https://github.com/BurntSushi/ripgrep/blob/9eddb71b8e86a04d7048b920b9b50a2e97068d03/crates/core/app.rs#L533-L539
-
-#[allow(clippy::large_enum_variant)]
-#[derive(StructOpt, Debug)]
-#[structopt(
-    author("Ryo Yamashita <qryxip@gmail.com>"),
-    about("Please run as `cargo equip`, not `cargo-equip`."),
-    bin_name("cargo"),
-    global_settings(&[AppSettings::DeriveDisplayOrder, AppSettings::UnifiedHelpMessage])
-)]
-pub enum Opt {
-    #[structopt(
-        about(indoc! {r#"
-
-            A Cargo subcommand to bundle your code into one `.rs` file for competitive programming.
-
-            Use -h for short descriptions and --help for more detials.
-        "#}),
-        author("Ryo Yamashita <qryxip@gmail.com>"),
-        usage(
-            r#"cargo equip [OPTIONS]
-    cargo equip [OPTIONS] --lib
-    cargo equip [OPTIONS] --bin <NAME>
-    cargo equip [OPTIONS] --example <NAME>
-    cargo equip [OPTIONS] --src <PATH>"#,
-        )
-    )]
-    Equip(OptEquip),
-    #[structopt(setting(AppSettings::Hidden))]
-    RustAnalyzerProcMacro {},
-}
-
-#[derive(StructOpt, Debug)]
-pub struct OptEquip {
-    /// Bundle the lib/bin/example target and its dependencies
-    #[structopt(
-        long,
-        value_name("PATH"),
-        conflicts_with_all(&["lib", "bin", "example"]),
-        long_help(indoc! {r#"
-            Bundle the lib/bin/example target and its dependencies.
-
-            This option is intended to be used from editors such as VSCode. Use `--lib`, `--bin` or `--example` for normal usage.
-        "#})
-    )]
-    src: Option<PathBuf>,
-
-    /// Bundle the library and its dependencies
-    #[structopt(long, conflicts_with_all(&["bin", "example"]))]
-    lib: bool,
-
-    /// Bundle the binary and its dependencies
-    #[structopt(long, value_name("NAME"), conflicts_with("example"))]
-    bin: Option<String>,
-
-    /// Bundle the binary example and its dependencies
-    #[structopt(long, value_name("NAME"))]
-    example: Option<String>,
-
-    /// Path to Cargo.toml
-    #[structopt(long, value_name("PATH"))]
-    manifest_path: Option<PathBuf>,
-
-    /// Exclude library crates from bundling
-    #[structopt(long, value_name("SPEC"))]
-    exclude: Vec<PkgSpec>,
-
-    /// Alias for `--exclude {crates available on AtCoder}`
-    #[structopt(
-        long,
-        long_help(Box::leak(
-            format!(
-                "Alias for:\n--exclude {}\n ",
-                ATCODER_CRATES.iter().format("\n          "),
-            )
-            .into_boxed_str(),
-        ))
-    )]
-    exclude_atcoder_crates: bool,
-
-    /// Alias for `--exclude {crates available on CodinGame}`
-    #[structopt(
-        long,
-        long_help(Box::leak(
-            format!(
-                "Alias for:\n--exclude {}\n ",
-                CODINGAME_CRATES.iter().format("\n          "),
-            )
-            .into_boxed_str(),
-        ))
-    )]
-    exclude_codingame_crates: bool,
-
-    /// Do not include license and copyright notices for the users
-    #[structopt(
-        long,
-        value_name("DOMAIN_AND_USERNAME"),
-        long_help(
-            concat!(
-                indoc! {r#"
-                    Do not include license and copyright notices for the users.
-
-                    Supported formats:
-                    * github.com/{username}
-                    * gitlab.com/{username}
-                "#},
-                ' ',
-            )
-        )
-    )]
-    mine: Vec<User>,
-
-    /// `nightly` toolchain for `cargo-udeps`
-    #[structopt(long, value_name("TOOLCHAIN"), default_value("nightly"))]
-    toolchain: String,
-
-    /// Expand the libraries to the module
-    #[structopt(long, value_name("MODULE_PATH"), default_value("crate::__cargo_equip"))]
-    mod_path: CrateSinglePath,
-
-    /// Remove some part [possible values: docs, comments]
-    #[structopt(
-        long,
-        value_name("REMOVE"),
-        possible_values(Remove::VARIANTS),
-        hide_possible_values(true),
-        long_help(concat!(
-            indoc! {r#"
-                Removes
-                * doc comments (`//! ..`, `/// ..`, `/** .. */`, `#[doc = ".."]`) with `--remove docs`.
-                * comments (`// ..`, `/* .. */`) with `--remove comments`.
-
-                ```
-                #[allow(dead_code)]
-                pub mod a {
-                    //! A.
-
-                    /// A.
-                    pub struct A; // aaaaa
-                }
-                ```
-
-                ↓
-
-                ```
-                #[allow(dead_code)]
-                pub mod a {
-                    pub struct A;
-                }
-                ```
-            "#},
-            ' ',
-        ))
-    )]
-    remove: Vec<Remove>,
-
-    /// Minify part of the output before emitting [default: none]  [possible values: none, libs, all]
-    #[structopt(
-        long,
-        value_name("MINIFY"),
-        possible_values(Minify::VARIANTS),
-        hide_possible_values(true),
-        default_value("none"),
-        hide_default_value(true),
-        long_help(concat!(
-            indoc! {r#"
-                Minifies
-                - each expaned library with `--minify lib`.
-                - the whole code with `--minify all`.
-
-                Not that the minification function is incomplete. Unnecessary spaces may be inserted.
-            "#},
-            ' ',
-        ))
-    )]
-    minify: Minify,
-
-    /// Do not resolve `cfg(..)`s
-    #[structopt(long)]
-    no_resolve_cfgs: bool,
-
-    /// Do not format the output before emitting
-    #[structopt(long)]
-    no_rustfmt: bool,
-
-    /// Do not check the output before emitting
-    #[structopt(long)]
-    no_check: bool,
-
-    /// Write to the file instead of STDOUT
-    #[structopt(short, long, value_name("PATH"))]
-    output: Option<PathBuf>,
-
-    /// [Deprecated] Alias for `--minify`
-    #[structopt(
-        long,
-        value_name("MINIFY"),
-        possible_values(Minify::VARIANTS),
-        default_value("none")
-    )]
-    oneline: Minify,
-
-    /// [Deprecated] No-op
-    #[structopt(long, conflicts_with("no_resolve_cfgs"))]
-    resolve_cfgs: bool,
-
-    /// [Deprecated] No-op
-    #[structopt(long, conflicts_with("no_rustfmt"))]
-    rustfmt: bool,
-
-    /// [Deprecated] No-op
-    #[structopt(long, conflicts_with("no_check"))]
-    check: bool,
-}
+use structopt::StructOpt as _;
+use termcolor::NoColor;
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub enum User {
-    Github(String),
-    GitlabCom(String),
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ErrorKind {
+    NoTarget,
+    Bundle,
+    Check,
+    SizeExceeded,
 }
 
-impl FromStr for User {
-    type Err = &'static str;
-
-    fn from_str(s: &str) -> Result<Self, &'static str> {
-        return if let Some(username) = s.strip_prefix("github.com/") {
-            Ok(Self::Github(username.to_owned()))
-        } else if let Some(username) = s.strip_prefix("gitlab.com/") {
-            Ok(Self::GitlabCom(username.to_owned()))
-        } else {
-            Err(MSG)
-        };
-
-        static MSG: &str = indoc! {r"
-            Supported formats:
-            * github.com/{username}
-            * gitlab.com/{username}
-        "};
+impl ErrorKind {
+    pub fn of(err: &anyhow::Error) -> Option<Self> {
+        err.chain()
+            .find_map(|e| e.downcast_ref::<KindedError>())
+            .map(|KindedError { kind, .. }| *kind)
     }
-}
 
-#[derive(Debug, derive_more::Display)]
-#[display(fmt = "crate::{}", _0)]
-pub struct CrateSinglePath(syn::Ident);
-
-impl FromStr for CrateSinglePath {
-    type Err = &'static str;
-
-    fn from_str(s: &str) -> Result<Self, &'static str> {
-        (|| {
-            let syn::Path {
-                leading_colon,
-                segments,
-            } = syn::parse_str(s).map_err(|_| ())?;
-            match (leading_colon, &*segments.into_iter().collect::<Vec<_>>()) {
-                (None, [p1, p2]) if p1.ident == "crate" => Ok(Self(p2.ident.clone())),
-                _ => Err(()),
-            }
-        })()
-        .map_err(|()| "expected `crate::$ident`")
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::NoTarget => 2,
+            Self::Bundle => 3,
+            Self::Check => 4,
+            Self::SizeExceeded => 5,
+        }
     }
-}
 
-#[derive(Clone, Copy, PartialEq, Debug)]
-pub enum Remove {
-    Docs,
-    Comments,
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NoTarget => "no-target",
+            Self::Bundle => "bundle",
+            Self::Check => "check",
+            Self::SizeExceeded => "size-exceeded",
+        }
+    }
 }
 
-impl Remove {
-    const VARIANTS: &'static [&'static str] = &["docs", "comments"];
+#[derive(Debug)]
+struct KindedError {
+    kind: ErrorKind,
+    inner: anyhow::Error,
 }
 
-impl FromStr for Remove {
-    type Err = &'static str;
-
-    fn from_str(s: &str) -> Result<Self, &'static str> {
-        match s {
-            "docs" => Ok(Self::Docs),
-            "comments" => Ok(Self::Comments),
-            _ => Err(r#"expected "docs", or "comments""#),
-        }
+impl fmt::Display for KindedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.inner, f)
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
-pub enum Minify {
-    None,
-    Libs,
-    All,
+impl std::error::Error for KindedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner.source()
+    }
 }
 
-impl Minify {
-    const VARIANTS: &'static [&'static str] = &["none", "libs", "all"];
+trait ResultExt<T> {
+    fn with_kind(self, kind: ErrorKind) -> anyhow::Result<T>;
 }
 
-impl FromStr for Minify {
-    type Err = &'static str;
-
-    fn from_str(s: &str) -> Result<Self, &'static str> {
-        match s {
-            "none" => Ok(Self::None),
-            "libs" => Ok(Self::Libs),
-            "all" => Ok(Self::All),
-            _ => Err(r#"expected "none", "libs", or "all""#),
-        }
+impl<T> ResultExt<T> for anyhow::Result<T> {
+    fn with_kind(self, kind: ErrorKind) -> anyhow::Result<T> {
+        self.map_err(|inner| KindedError { kind, inner }.into())
     }
 }
 
@@ -411,39 +197,95 @@ static CODINGAME_CRATES: &[&str] = &[
 ];
 
 pub fn run(opt: Opt, ctx: Context<'_>) -> anyhow::Result<()> {
-    let opt = match opt {
-        Opt::Equip(opt) => opt,
-        Opt::RustAnalyzerProcMacro {} => return proc_macro_srv::cli::run().map_err(Into::into),
-    };
+    match opt {
+        Opt::Equip(opt) => {
+            let args = env::args_os()
+                .skip(2)
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect();
+            equip(opt, args, ctx)
+        }
+        Opt::RustAnalyzerProcMacro {} => proc_macro_srv::cli::run().map_err(Into::into),
+    }
+}
+
+/// `args` are recorded with `--provenance`.
+fn equip(opt: OptEquip, args: Vec<String>, ctx: Context<'_>) -> anyhow::Result<()> {
     let OptEquip {
         src,
+        src_code,
+        state,
         lib,
         bin,
+        section_header,
         example,
         manifest_path,
+        workspace_root,
+        features,
+        use_registry,
+        non_interactive,
+        cargo,
         exclude,
         exclude_atcoder_crates,
         exclude_codingame_crates,
+        only,
+        exclude_unresolved,
         mine,
         toolchain,
         mod_path: CrateSinglePath(cargo_equip_mod_name),
+        entry_point,
+        allow_nightly,
+        inline_assets,
+        define,
+        strip_debug,
+        strip_debug_asserts,
+        normalize_core_alloc,
+        strip_derives,
+        deny_io,
         remove,
+        min_rustc,
+        warn_deprecated,
+        emit,
+        verbatim_bin,
         minify,
+        profile,
+        max_size,
         no_resolve_cfgs,
+        fold_cfg_macros,
+        target,
         no_rustfmt,
+        offline,
+        cache_metadata,
+        no_cache,
         no_check,
+        force_check,
+        check_mode,
+        check_toolchains,
+        check_rustflags,
+        check_env,
+        no_rustc_wrapper,
         output,
+        backup,
+        quiet,
+        diff,
+        update,
+        snapshot_dir,
+        check_diff,
+        report: print_report,
+        explain,
+        time: print_time,
+        provenance,
+        reproduce,
+        server,
+        error_format: _,
+        vendor,
         oneline: deprecated_oneline_opt,
         resolve_cfgs: deprecated_resolve_cfgs_flag,
         rustfmt: deprecated_rustfmt_flag,
         check: deprecated_check_flag,
+        command,
     } = opt;
 
-    let minify = match (minify, deprecated_oneline_opt) {
-        (Minify::None, oneline) => oneline,
-        (minify, _) => minify,
-    };
-
     let exclude = {
         let mut exclude = exclude;
         if exclude_atcoder_crates {
@@ -461,6 +303,127 @@ pub fn run(opt: Opt, ctx: Context<'_>) -> anyhow::Result<()> {
         cache_dir,
         shell,
     } = ctx;
+    shell.set_quiet(quiet);
+
+    if let Some(cargo) = cargo {
+        let cargo_exe = which::which_in(&cargo, env::var_os("PATH"), &cwd)
+            .with_context(|| format!("could not find `{}`", cargo.display()))?;
+        process::set_cargo_exe(cargo_exe)?;
+    }
+
+    match command {
+        Some(EquipCommand::Notebook(opt)) => {
+            return run_notebook(opt, &cwd, &cargo_equip_mod_name, shell);
+        }
+        Some(EquipCommand::Stats { manifest_path }) => {
+            let manifest_path = if let Some(manifest_path) = manifest_path {
+                cwd.join(manifest_path.strip_prefix(".").unwrap_or(&manifest_path))
+            } else {
+                workspace::locate_project(&cwd)?
+            };
+            let metadata = workspace::cargo_metadata(&manifest_path, &cwd, &[], false)?;
+            let stats = stats::stats(&metadata, &cargo_equip_mod_name, shell)?;
+            write!(shell.out(), "{}", stats)?;
+            return Ok(());
+        }
+        Some(EquipCommand::Rdeps {
+            manifest_path,
+            module,
+        }) => {
+            let manifest_path = if let Some(manifest_path) = manifest_path {
+                cwd.join(manifest_path.strip_prefix(".").unwrap_or(&manifest_path))
+            } else {
+                workspace::locate_project(&cwd)?
+            };
+            let metadata = workspace::cargo_metadata(&manifest_path, &cwd, &[], false)?;
+            for target in stats::rdeps(&metadata, &module, &cargo_equip_mod_name, shell)? {
+                writeln!(shell.out(), "{}", target)?;
+            }
+            return Ok(());
+        }
+        Some(EquipCommand::Doctor { manifest_path }) => {
+            let manifest_path = if let Some(manifest_path) = manifest_path {
+                cwd.join(manifest_path.strip_prefix(".").unwrap_or(&manifest_path))
+            } else {
+                workspace::locate_project(&cwd)?
+            };
+            return doctor::doctor(&manifest_path, &cwd, &cargo_equip_mod_name, shell);
+        }
+        Some(EquipCommand::BenchSize) => {
+            let ctx = Context {
+                cwd,
+                cargo_equip_exe,
+                cache_dir,
+                shell,
+            };
+            return bench_size(&args, ctx);
+        }
+        Some(EquipCommand::Daemon { socket }) => {
+            let ctx = Context {
+                cwd,
+                cargo_equip_exe,
+                cache_dir,
+                shell,
+            };
+            return daemon::daemon(&args, socket, ctx);
+        }
+        Some(EquipCommand::VerifyMetadata { manifest_path }) => {
+            let manifest_path = if let Some(manifest_path) = manifest_path {
+                cwd.join(manifest_path.strip_prefix(".").unwrap_or(&manifest_path))
+            } else {
+                workspace::locate_project(&cwd)?
+            };
+            return verify_metadata::verify_metadata(&manifest_path, &cwd, shell);
+        }
+        Some(EquipCommand::SelfUpdate { dry_run }) => {
+            return self_update::self_update(cargo_equip_exe.as_ref(), dry_run, &cwd, shell);
+        }
+        Some(EquipCommand::Completions {
+            shell: target_shell,
+        }) => {
+            Opt::clap().gen_completions_to("cargo-equip", target_shell, &mut shell.out());
+            return Ok(());
+        }
+        None => {}
+    }
+
+    if let Some(reproduce) = reproduce {
+        let ctx = Context {
+            cwd,
+            cargo_equip_exe,
+            cache_dir,
+            shell,
+        };
+        return run_reproduce(&reproduce, ctx);
+    }
+
+    if server {
+        let ctx = Context {
+            cwd,
+            cargo_equip_exe,
+            cache_dir,
+            shell,
+        };
+        return serve(&args, ctx);
+    }
+
+    if let Some(src) = &src {
+        if src != Path::new("-") && (lib || !bin.is_empty() || example.is_some()) {
+            bail!("`--src` other than `-` cannot be used with `--lib`, `--bin` or `--example`");
+        }
+        if bin.len() > 1 {
+            bail!("`--src -` cannot be used with multiple `--bin`s");
+        }
+    }
+
+    if bin.len() > 1
+        && (update.is_some() || vendor.is_some() || snapshot_dir.is_some() || use_registry)
+    {
+        bail!(
+            "`--update`, `--vendor`, `--snapshot-dir` and `--use-registry` cannot be used with \
+             multiple `--bin`s"
+        );
+    }
 
     if deprecated_resolve_cfgs_flag {
         shell.warn("`--resolve-cfgs` is deprecated. `#[cfg(..)]`s are resolved by default")?;
@@ -472,45 +435,361 @@ pub fn run(opt: Opt, ctx: Context<'_>) -> anyhow::Result<()> {
         shell.warn("`--check` is deprecated. the output is checked by default")?;
     }
 
+    // `--src path/to/script.rs` with an embedded manifest
+    let script = match src.as_deref().filter(|src| *src != Path::new("-")) {
+        Some(src) => {
+            let src = cwd.join(src);
+            let code = cargo_util::paths::read(&src)?;
+            rust::extract_embedded_manifest(&code)
+                .map(|(manifest, code)| {
+                    workspace::script_package(&src, &manifest, &code, &cache_dir)
+                })
+                .transpose()?
+        }
+        None => None,
+    };
+    let (manifest_path, src) = if let Some((manifest_path, main_path)) = script {
+        (Some(manifest_path), Some(main_path))
+    } else {
+        (manifest_path, src)
+    };
+
     let manifest_path = if let Some(manifest_path) = manifest_path {
         cwd.join(manifest_path.strip_prefix(".").unwrap_or(&manifest_path))
+    } else if let Some(workspace_root) = workspace_root {
+        workspace::workspace_root_manifest(&cwd.join(workspace_root))?
     } else {
         workspace::locate_project(&cwd)?
     };
 
-    let metadata = workspace::cargo_metadata(&manifest_path, &cwd)?;
+    let mut report = Report::default();
+
+    let metadata = report.time("metadata", || {
+        cargo_metadata_or_standalone(
+            &manifest_path,
+            &cwd,
+            &features,
+            offline,
+            |package| {
+                if let Some(bin) = bin.first() {
+                    package.targets.iter().any(|t| t.is_bin() && t.name == *bin)
+                } else if let Some(example) = &example {
+                    package
+                        .targets
+                        .iter()
+                        .any(|t| t.is_example() && t.name == *example)
+                } else if let Some(src) = src.as_ref().filter(|src| *src != Path::new("-")) {
+                    let src = cwd.join(src);
+                    package.targets.iter().any(|t| t.src_path == src)
+                } else {
+                    package.manifest_path == manifest_path
+                }
+            },
+            &cache_dir,
+            cache_metadata,
+            state.as_deref(),
+            shell,
+        )
+    })?;
 
     let (root, root_package) = if lib {
         metadata.lib_target()
-    } else if let Some(bin) = bin {
-        metadata.bin_target_by_name(&bin)
-    } else if let Some(example) = example {
-        metadata.example_target_by_name(&example)
-    } else if let Some(src) = src {
+    } else if let Some(bin) = bin.first() {
+        metadata.bin_target_by_name(bin)
+    } else if let Some(example) = &example {
+        metadata.example_target_by_name(example)
+    } else if let Some(src) = src.as_ref().filter(|src| *src != Path::new("-")) {
         metadata.target_by_src_path(&cwd.join(src))
     } else {
-        metadata.exactly_one_target()
-    }?;
+        let targets = metadata.root_targets();
+        if targets.len() > 1
+            && !non_interactive
+            && atty::is(atty::Stream::Stdin)
+            && atty::is(atty::Stream::Stderr)
+        {
+            pick_target(&targets, shell)
+        } else {
+            metadata.exactly_one_target()
+        }
+    }
+    .with_kind(ErrorKind::NoTarget)?;
 
+    if let Some(name) = bin.first().or(example.as_ref()) {
+        if root.name != *name {
+            shell.warn(format!("no target named `{}`. using `{}`", name, root.name))?;
+        }
+    }
+
+    // `--src -` and `--server`: the source is written next to the original one so that `mod`s are
+    // resolved.
+    let src_code = if src.as_deref() == Some(Path::new("-")) {
+        let mut code = "".to_owned();
+        io::stdin().read_to_string(&mut code)?;
+        Some(code)
+    } else {
+        src_code
+    };
+    let stdin_src = if let Some(code) = src_code {
+        let dir = root.src_path.parent().expect("should not be root");
+        let file = tempfile::Builder::new()
+            .prefix(".cargo-equip-stdin-")
+            .suffix(".rs")
+            .tempfile_in(dir)?
+            .into_temp_path();
+        cargo_util::paths::write(&file, code)?;
+        Some(file)
+    } else {
+        None
+    };
+    let stdin_target;
+    let root = if let Some(stdin_src) = &stdin_src {
+        let mut target = root.clone();
+        target.src_path = Utf8PathBuf::from_path_buf(stdin_src.to_path_buf())
+            .map_err(|p| anyhow!("`{}` is not valid UTF-8", p.display()))?;
+        stdin_target = target;
+        &stdin_target
+    } else {
+        root
+    };
+
+    // `--use-registry`: the target is moved into a package that depends on the registered crates.
+    let registry_deps = if use_registry && !root.is_lib() {
+        let registry = GlobalConfig::read()?.registry;
+        let code = cargo_util::paths::read(root.src_path.as_ref())?;
+        rust::collect_paths(&code)?
+            .into_iter()
+            .flat_map(|segments| segments.into_iter().next())
+            .filter(|name| {
+                metadata
+                    .dep_lib_by_extern_crate_name(&root_package.id, name)
+                    .is_none()
+            })
+            .flat_map(|name| {
+                let path = registry.get(&name)?.clone();
+                Some((name, path))
+            })
+            .collect::<BTreeMap<_, _>>()
+    } else {
+        BTreeMap::new()
+    };
+    let registry_metadata;
+    let (metadata, root, root_package) = if registry_deps.is_empty() {
+        (&metadata, root, root_package)
+    } else {
+        for (name, path) in &registry_deps {
+            shell.status(
+                "Using",
+                format!("`{}` in the registry (`{}`)", name, path.display()),
+            )?;
+        }
+        let manifest_path =
+            workspace::registry_package(&metadata, root_package, root, &registry_deps, &cache_dir)?;
+        registry_metadata = report.time("metadata", || {
+            workspace::cargo_metadata(&manifest_path, &cwd, &features, offline)
+        })?;
+        let (root, root_package) = registry_metadata.exactly_one_target()?;
+        (&registry_metadata, root, root_package)
+    };
+
+    let emit = emit.unwrap_or(Emit::All);
+    if emit == Emit::LibOnly && root.is_lib() {
+        bail!("`--emit lib-only` cannot be used for a lib crate");
+    }
+    if emit == Emit::LibOnly && bin.len() > 1 {
+        bail!("`--emit lib-only` cannot be used with multiple `--bin`s");
+    }
+
+    // Multiple `--bin`s are bundled into one packet, sharing the expansion of the libraries.
+    let targets = if bin.len() > 1 {
+        bin.iter()
+            .map(|name| {
+                let (target, package) = metadata.bin_target_by_name(name)?;
+                if package.id != root_package.id {
+                    bail!(
+                        "`{}` is not in `{}`. multiple `--bin`s must be in one package",
+                        target.name,
+                        root_package.name,
+                    );
+                }
+                Ok(target)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+    } else {
+        vec![root]
+    };
+
+    let mut config = Config::read(metadata, root_package)?;
+    let hooks = mem::take(&mut config.hooks);
+    let renames = mem::take(&mut config.rename);
+
+    let artifact = update.as_ref().or(output.as_ref()).map(|p| cwd.join(p));
+    let hook_envs = itertools::chain!(
+        [
+            ("CARGO_EQUIP_PACKAGE", OsStr::new(&root_package.name)),
+            ("CARGO_EQUIP_TARGET", OsStr::new(&root.name)),
+            ("CARGO_EQUIP_SRC", root.src_path.as_ref()),
+            (
+                "CARGO_EQUIP_MANIFEST_PATH",
+                root_package.manifest_path.as_ref(),
+            ),
+        ],
+        artifact
+            .as_ref()
+            .map(|p| ("CARGO_EQUIP_OUTPUT", p.as_ref())),
+    )
+    .collect::<Vec<_>>();
+    if let Some(pre_bundle) = &hooks.pre_bundle {
+        process::run_hook("pre-bundle", pre_bundle, &hook_envs, &cwd, shell)?;
+    }
+
+    let check_policy = if no_check {
+        CheckPolicy::Never
+    } else if force_check {
+        CheckPolicy::Always
+    } else {
+        config.check.unwrap_or(CheckPolicy::OnChange)
+    };
+
+    let profile = if let Some(profile) = &profile {
+        config.profile(profile)?
+    } else {
+        Profile::default()
+    };
+
+    let exclude = {
+        let mut exclude = exclude;
+        exclude.extend(profile.exclude);
+        exclude
+    };
+    let toolchain = toolchain
+        .or(profile.toolchain)
+        .unwrap_or_else(|| "nightly".to_owned());
+    let minify = minify
+        .or_else(|| (deprecated_oneline_opt != Minify::None).then(|| deprecated_oneline_opt))
+        .or(profile.minify)
+        .unwrap_or(Minify::None);
+    let max_size = max_size.or(profile.max_size);
+    let check_rustflags = check_rustflags.or(profile.check_rustflags);
+    let check_mode = check_mode
+        .or(profile.check_mode)
+        .unwrap_or(CheckMode::Check);
+    let target = target.or(profile.target);
+    let entry_point = entry_point.or(profile.entry_point);
+    let target_info = target
+        .as_deref()
+        .map(|triple| {
+            cfg_expr::targets::get_builtin_target_by_triple(triple)
+                .with_context(|| format!("unknown target triple: `{}`", triple))
+        })
+        .transpose()?;
+
+    let started = Report::start("resolve");
+    let unused_deps;
+    let renamed_libs;
+    let mut unresolved = vec![];
     let libs_to_bundle = {
-        let unused_deps = &if root.is_lib() {
+        unused_deps = if root.is_lib() {
             hashset!()
         } else {
-            match cargo_udeps::cargo_udeps(root_package, root, &toolchain, shell) {
-                Ok(unused_deps) => unused_deps,
-                Err(warning) => {
-                    shell.warn(warning)?;
-                    hashset!()
-                }
+            // For a packet, only the dependencies which no bin uses.
+            let mut unused_deps = None::<HashSet<_>>;
+            for target in &targets {
+                let found = match cargo_udeps::cargo_udeps(
+                    root_package,
+                    target,
+                    &toolchain,
+                    &features,
+                    offline,
+                    shell,
+                ) {
+                    Ok(found) => found,
+                    Err(warning) => {
+                        shell.warn(warning)?;
+                        hashset!()
+                    }
+                };
+                unused_deps = Some(match unused_deps {
+                    Some(unused_deps) => &unused_deps & &found,
+                    None => found,
+                });
             }
+            unused_deps.unwrap_or_default()
         };
-        let mut libs_to_bundle =
-            metadata.libs_to_bundle(&root_package.id, root.is_example(), unused_deps, &exclude)?;
+        let mut libs_to_bundle = metadata.libs_to_bundle(
+            &root_package.id,
+            root.is_example(),
+            &unused_deps,
+            &exclude,
+            shell,
+        )?;
         if root.is_lib() {
             libs_to_bundle.insert(&root_package.id, (root, root.crate_name()));
         }
+        if exclude_unresolved {
+            libs_to_bundle.retain(|package_id, (krate, _)| {
+                let found = krate.src_path.exists();
+                if !found {
+                    unresolved.push((*package_id, &krate.src_path));
+                }
+                found
+            });
+            for (package_id, src_path) in &unresolved {
+                shell.warn(format!(
+                    "could not find the source of `{}` (`{}`). leaving it unbundled",
+                    package_id, src_path,
+                ))?;
+            }
+        }
+        renamed_libs = rename_libs(metadata, &mut libs_to_bundle, &renames)?;
         libs_to_bundle
     };
+    report.finish(started);
+
+    let only = {
+        let mut modules = BTreeMap::<_, Vec<_>>::new();
+        for module in &only {
+            let (crate_name, path) = rust::parse_module_path(module);
+            if !libs_to_bundle
+                .values()
+                .any(|(krate, _)| krate.crate_name() == crate_name)
+            {
+                bail!(
+                    "`--only {}`: `{}` is not going to be bundled",
+                    module,
+                    crate_name
+                );
+            }
+            modules.entry(crate_name).or_default().push(path);
+        }
+        modules
+    };
+
+    if explain {
+        explain::explain(
+            metadata,
+            root_package,
+            root,
+            &libs_to_bundle,
+            &exclude,
+            &unused_deps,
+            &unresolved.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            &only,
+            &cargo_equip_mod_name,
+            shell,
+        )?;
+    }
+
+    if let Some(vendor) = vendor {
+        return vendor::vendor(
+            metadata,
+            root_package,
+            root,
+            &libs_to_bundle,
+            &cargo_equip_mod_name,
+            &cwd.join(vendor),
+            shell,
+        );
+    }
 
     let error_message = |head: &str| {
         let mut msg = head.to_owned();
@@ -542,64 +821,849 @@ pub fn run(opt: Opt, ctx: Context<'_>) -> anyhow::Result<()> {
         msg
     };
 
-    let code = bundle(
-        &metadata,
+    let bundled = bundle(
+        metadata,
         if root.is_lib() {
             RootCrate::Lib(root_package, root)
         } else {
-            RootCrate::BinLike(root_package, root)
+            RootCrate::BinLike(root_package, &targets)
         },
         &libs_to_bundle,
+        &renamed_libs,
+        &features,
+        &only,
         &mine,
         &cargo_equip_mod_name,
+        entry_point.as_ref(),
+        allow_nightly,
+        inline_assets,
+        &define,
+        strip_debug,
+        strip_debug_asserts,
+        normalize_core_alloc,
+        &strip_derives,
         !no_resolve_cfgs,
+        fold_cfg_macros,
+        target_info,
         &remove,
+        warn_deprecated,
+        emit,
+        verbatim_bin,
         minify,
         !no_rustfmt,
         &cargo_equip_exe,
         &cache_dir,
+        offline,
+        no_cache,
+        snapshot_dir.map(|d| cwd.join(d)).as_deref(),
+        state.as_deref(),
+        &mut report,
         shell,
     )
-    .with_context(|| error_message("could not bundle the code"))?;
+    .with_context(|| error_message("could not bundle the code"))
+    .with_kind(ErrorKind::Bundle)?;
 
-    if !no_check {
-        workspace::cargo_check_using_current_lockfile_and_cache(
-            &metadata,
-            root_package,
-            root,
-            &exclude,
-            &code,
-        )
-        .with_context(|| error_message("the bundled code was not valid"))?;
+    let mut bundled = bundled;
+
+    if let Some(io_template) = &profile.io_template {
+        let path = metadata.workspace_root.as_std_path().join(io_template);
+        let template = cargo_util::paths::read(&path)
+            .with_context(|| format!("could not read the IO template `{}`", path.display()))?;
+        bundled.libs = format!("{}\n{}", bundled.libs, template);
+    }
+
+    if let Some(min_rustc) = &min_rustc {
+        let ((major, minor), probe) = rust::min_rustc_probe(min_rustc)
+            .with_context(|| format!("`--min-rustc {}`: the version is too old", min_rustc))?;
+        if (major, minor) != (min_rustc.major, min_rustc.minor) {
+            shell.warn(format!(
+                "no API is known to be stabilized in rustc {}.{}. requiring {}.{} instead",
+                min_rustc.major, min_rustc.minor, major, minor,
+            ))?;
+        }
+        for code in &mut bundled.bins {
+            *code = rust::insert_after_inner_attrs(code, &probe)?;
+        }
+    }
+
+    if profile.allow_warnings {
+        for code in &mut bundled.bins {
+            *code = format!("#![allow(warnings)]\n\n{}", code);
+        }
+    }
+
+    let code = bundled.join(
+        &bin.iter().map(|s| &**s).collect::<Vec<_>>(),
+        &section_header,
+    )?;
+
+    let code = if let Some(header) = &profile.header {
+        format!("{}\n{}", header.trim_end(), code)
+    } else {
+        code
+    };
+
+    let code = if let Some(update) = &update {
+        let path = cwd.join(update);
+        let old = provenance::Provenance::strip_from(&cargo_util::paths::read(&path)?);
+        rust::update_regions(&old, &code)
+            .with_context(|| format!("could not update `{}`", path.display()))?
+    } else {
+        code
+    };
+    let output = update.or(output);
+
+    if deny_io {
+        let found = rust::find_io_apis(&code)?;
+        if !found.is_empty() {
+            bail!(
+                "the output uses APIs that are banned or useless on judges:\n{}",
+                found
+                    .iter()
+                    .map(|(pos, mod_path, api)| format!(
+                        "- `{}` at {}:{} in `{}`",
+                        api,
+                        pos.line,
+                        pos.column + 1,
+                        mod_path,
+                    ))
+                    .format("\n"),
+            );
+        }
+    }
+
+    let excluded_crate_names = metadata
+        .packages
+        .iter()
+        .filter(|p| exclude.iter().any(|s| s.matches(p)))
+        .flat_map(|p| p.lib_like_target())
+        .map(|t| t.crate_name())
+        .collect::<HashSet<_>>();
+    let mut unbundled = BTreeMap::<_, Vec<_>>::new();
+    for (pos, name) in rust::find_extern_crate_refs(&code)? {
+        if !["std", "core", "alloc", "proc_macro", "test"].contains(&&*name)
+            && !excluded_crate_names.contains(&name)
+        {
+            unbundled
+                .entry(name)
+                .or_default()
+                .push(format!("{}:{}", pos.line, pos.column + 1));
+        }
+    }
+    for (name, positions) in unbundled {
+        shell.warn(format!(
+            "the output refers to `{}`, which is neither bundled nor excluded, at {}",
+            name,
+            positions.join(", "),
+        ))?;
+    }
+
+    let findings = validate::validate(&code, &profile.validate, shell)?;
+    if findings > 0 && profile.validate.deny {
+        bail!("the output does not meet the limits of the judge");
+    }
+
+    if check_policy != CheckPolicy::Never {
+        // Each bin of a packet is checked with the libraries.
+        for (i, &root) in targets.iter().enumerate() {
+            let code_to_check = &if emit == Emit::LibOnly {
+                format!("{}\nfn main() {{}}\n", code)
+            } else if targets.len() > 1 {
+                shell.status("Checking", format!("`{}`", root.name))?;
+                bundled.program(i)
+            } else {
+                code.clone()
+            };
+            let check = |toolchain: Option<&str>, shell: &mut Shell| {
+                workspace::cargo_check_using_current_lockfile_and_cache(
+                    metadata,
+                    root_package,
+                    root,
+                    |package_id| {
+                        exclude.iter().any(|s| s.matches(&metadata[package_id]))
+                            || unresolved.iter().any(|(id, _)| *id == package_id)
+                    },
+                    code_to_check,
+                    &cwd,
+                    toolchain,
+                    check_rustflags.as_deref(),
+                    &check_env,
+                    no_rustc_wrapper,
+                    target.as_deref(),
+                    check_mode,
+                    check_policy == CheckPolicy::Always,
+                    shell,
+                )
+            };
+            report
+                .time("check", || {
+                    if check_toolchains.is_empty() {
+                        check(allow_nightly.then(|| &*toolchain), shell)
+                    } else {
+                        check_with_toolchains(&check_toolchains, check, shell)
+                    }
+                })
+                .with_context(|| error_message("the bundled code was not valid"))
+                .with_kind(ErrorKind::Check)?;
+        }
+    }
+
+    let code = if provenance {
+        let provenance =
+            provenance::Provenance::new(args, libs_to_bundle.keys().map(|id| id.mask_path()));
+        provenance.append_to(code)?
+    } else {
+        code
+    };
+
+    if let Some(max_size) = max_size {
+        if code.len() > max_size {
+            return Err(anyhow!(
+                "the output is {} bytes, which exceeds the limit of {} bytes",
+                code.len(),
+                max_size,
+            ))
+            .with_kind(ErrorKind::SizeExceeded);
+        }
+    }
+
+    if print_report {
+        report.print(code.len(), shell)?;
+    } else if print_time {
+        report.print_time(shell)?;
+    }
+
+    write_output(&code, output, backup, diff, check_diff, &cwd, shell)?;
+
+    if let Some(post_bundle) = &hooks.post_bundle {
+        process::run_hook("post-bundle", post_bundle, &hook_envs, &cwd, shell)?;
+    }
+    Ok(())
+}
+
+/// Checks the output with each toolchain and prints the results as a table.
+fn check_with_toolchains(
+    toolchains: &[String],
+    check: impl Fn(Option<&str>, &mut Shell) -> anyhow::Result<()>,
+    shell: &mut Shell,
+) -> anyhow::Result<()> {
+    let mut results = vec![];
+    for toolchain in toolchains {
+        shell.status("Checking", format!("with `{}`", toolchain))?;
+        results.push((toolchain, check(Some(toolchain), shell).is_ok()));
     }
 
+    let err = shell.err();
+    let width = toolchains
+        .iter()
+        .map(|t| t.len())
+        .max()
+        .unwrap_or(0)
+        .max("toolchain".len());
+    writeln!(err)?;
+    writeln!(err, "{:<width$} result", "toolchain")?;
+    for (toolchain, ok) in &results {
+        writeln!(
+            err,
+            "{:<width$} {}",
+            toolchain,
+            if *ok { "ok" } else { "failed" },
+        )?;
+    }
+
+    let failed = results
+        .iter()
+        .filter(|(_, ok)| !ok)
+        .map(|(toolchain, _)| format!("`{}`", toolchain))
+        .collect::<Vec<_>>();
+    if !failed.is_empty() {
+        bail!("the check failed with {}", failed.join(", "));
+    }
+    Ok(())
+}
+
+/// The output file is replaced by renaming a temporary file so that it is never left truncated.
+fn write_output(
+    code: &str,
+    output: Option<PathBuf>,
+    backup: bool,
+    diff: bool,
+    check_diff: bool,
+    cwd: &Path,
+    shell: &mut Shell,
+) -> anyhow::Result<()> {
     if let Some(output) = output {
         let output = cwd.join(output);
-        cargo_util::paths::write(&output, code)
+        if diff || check_diff {
+            let old = if output.exists() {
+                cargo_util::paths::read(&output)?
+            } else {
+                "".to_owned()
+            };
+            let path = output.display().to_string();
+            if let Some(diff) = diff::unified_diff(&old, code, &path, &path) {
+                write!(shell.out(), "{}", diff)?;
+                if check_diff {
+                    bail!("the bundled code differs from `{}`", path);
+                }
+            }
+            return Ok(());
+        }
+        // Devices and FIFOs such as `/dev/null` are written in place, and a symlink is kept.
+        let output = match output.metadata() {
+            Ok(metadata) if !metadata.is_file() => {
+                return cargo_util::paths::write(&output, code);
+            }
+            Ok(_) => output.canonicalize()?,
+            Err(_) => output,
+        };
+        let dir = output.parent().expect("should be absolute");
+        let mut file = tempfile::Builder::new()
+            .prefix(".cargo-equip-")
+            .suffix(".rs")
+            .tempfile_in(dir)
+            .with_context(|| format!("could not create a temporary file in `{}`", dir.display()))?;
+        file.write_all(code.as_ref())?;
+        if output.exists() {
+            let permissions = std::fs::metadata(&output)?.permissions();
+            file.as_file().set_permissions(permissions)?;
+            if backup {
+                let mut bak = output.clone().into_os_string();
+                bak.push(".bak");
+                cargo_util::paths::copy(&output, bak)?;
+            }
+        }
+        file.persist(&output)
+            .with_context(|| format!("could not write `{}`", output.display()))?;
+        Ok(())
     } else {
         write!(shell.out(), "{}", code)?;
         Ok(())
     }
 }
 
+/// Runs `cargo metadata`, falling back to a standalone copy of the package that has the target if
+/// it fails for the workspace, e.g. because other members have unresolvable dependencies.
+#[allow(clippy::too_many_arguments)]
+fn cargo_metadata_or_standalone(
+    manifest_path: &Path,
+    cwd: &Path,
+    features: &[String],
+    offline: bool,
+    has_target: impl Fn(&cm::Package) -> bool,
+    cache_dir: &Path,
+    cache_metadata: bool,
+    state: Option<&RefCell<daemon::State>>,
+    shell: &mut Shell,
+) -> anyhow::Result<cm::Metadata> {
+    let metadata_cache = MetadataCache::new(cache_dir, state);
+    if cache_metadata {
+        if let Some(metadata) = metadata_cache.get(manifest_path, features, offline) {
+            return Ok(metadata);
+        }
+    }
+
+    let err = match workspace::cargo_metadata(manifest_path, cwd, features, offline) {
+        Ok(metadata) => {
+            if cache_metadata {
+                metadata_cache.put(manifest_path, features, offline, &metadata)?;
+            }
+            return Ok(metadata);
+        }
+        Err(err) => err,
+    };
+
+    let no_deps = match workspace::cargo_metadata_no_deps(manifest_path, cwd, offline) {
+        Ok(no_deps) => no_deps,
+        Err(_) => return Err(err.into()),
+    };
+    let package = match *no_deps
+        .packages
+        .iter()
+        .filter(|p| no_deps.workspace_members.contains(&p.id) && has_target(p))
+        .collect::<Vec<_>>()
+    {
+        [package] => package,
+        _ => return Err(err.into()),
+    };
+
+    shell.warn(format!(
+        "could not resolve the workspace. bundling `{}` as a standalone package outside of it\n\n{}",
+        package.name,
+        err.to_string().trim_end(),
+    ))?;
+    let manifest_path = workspace::standalone_package(&no_deps, package, cache_dir)?;
+    workspace::cargo_metadata(&manifest_path, cwd, features, offline).with_context(|| {
+        format!(
+            "could not resolve `{}` outside of the workspace",
+            package.name
+        )
+    })
+}
+
+/// Bundles the target with each strategy in addition to the rest of `args`, and prints the sizes.
+fn bench_size(args: &[String], ctx: Context<'_>) -> anyhow::Result<()> {
+    const STRATEGIES: &[(&str, &[&str])] = &[
+        ("as-is", &[]),
+        ("remove docs,comments", &["--remove", "docs", "comments"]),
+        (
+            "remove docs,comments + minify libs",
+            &["--remove", "docs", "comments", "--minify", "libs"],
+        ),
+        (
+            "remove docs,comments + minify libs + strip Debug",
+            &[
+                "--remove",
+                "docs",
+                "comments",
+                "--minify",
+                "libs",
+                "--strip-derives",
+                "Debug",
+            ],
+        ),
+        (
+            "remove docs,comments + minify all",
+            &["--remove", "docs", "comments", "--minify", "all"],
+        ),
+    ];
+
+    let Context {
+        cwd,
+        cargo_equip_exe,
+        cache_dir,
+        shell,
+    } = ctx;
+
+    let mut common_args = vec![];
+    let mut rest = provenance::strip_output_args(args.iter().cloned())
+        .into_iter()
+        .peekable();
+    while let Some(arg) = rest.next() {
+        match &*arg {
+            "bench-size" => {}
+            "--remove" => {
+                rest.peeking_take_while(|a| !a.starts_with('-'))
+                    .for_each(drop);
+            }
+            "--minify" | "--oneline" | "--strip-derives" => {
+                rest.next();
+            }
+            _ if arg.starts_with("--remove=")
+                || arg.starts_with("--minify=")
+                || arg.starts_with("--oneline=")
+                || arg.starts_with("--strip-derives=") => {}
+            _ => common_args.push(arg),
+        }
+    }
+
+    let mut rows = vec![];
+    for (name, strategy) in STRATEGIES {
+        let output = tempfile::Builder::new()
+            .prefix("cargo-equip-")
+            .suffix(".rs")
+            .tempfile()?
+            .into_temp_path();
+
+        let args = itertools::chain!(
+            common_args.iter().cloned(),
+            strategy.iter().map(|&s| s.to_owned()),
+        )
+        .collect::<Vec<_>>();
+        let opt = OptEquip::from_iter_safe(itertools::chain!(
+            ["equip".to_owned()],
+            args.iter().cloned(),
+            ["--output".to_owned(), output.display().to_string()],
+        ))?;
+        shell.status("Bundling", format!("with `{}`", name))?;
+        equip(
+            opt,
+            args,
+            Context {
+                cwd: cwd.clone(),
+                cargo_equip_exe: cargo_equip_exe.clone(),
+                cache_dir: cache_dir.clone(),
+                shell,
+            },
+        )
+        .with_context(|| format!("could not bundle with `{}`", name))?;
+
+        let code = cargo_util::paths::read(&output)?;
+        output.close()?;
+        rows.push((name, code.len(), code.lines().count()));
+    }
+
+    let width = rows
+        .iter()
+        .map(|(name, ..)| name.len())
+        .max()
+        .unwrap_or(0)
+        .max("strategy".len());
+    let base = rows[0].1.max(1);
+    let mut out = format!(
+        "{:<width$} {:>9} {:>7} {:>6}\n",
+        "strategy", "bytes", "lines", "ratio",
+    );
+    for (name, bytes, lines) in rows {
+        out += &format!(
+            "{:<width$} {:>9} {:>7} {:>5}%\n",
+            name,
+            bytes,
+            lines,
+            bytes * 100 / base,
+        );
+    }
+    write!(shell.out(), "{}", out)?;
+    Ok(())
+}
+
+/// `--server`: bundles each request from STDIN as `--src` with the rest of `args`.
+fn serve(args: &[String], ctx: Context<'_>) -> anyhow::Result<()> {
+    let Context {
+        cwd,
+        cargo_equip_exe,
+        cache_dir,
+        shell,
+    } = ctx;
+
+    let mut common_args = vec![];
+    let mut rest = provenance::strip_output_args(args.iter().cloned()).into_iter();
+    while let Some(arg) = rest.next() {
+        if arg == "--src" {
+            rest.next();
+        } else if arg != "--server" && !arg.starts_with("--src=") {
+            common_args.push(arg);
+        }
+    }
+
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    loop {
+        let mut path = "".to_owned();
+        if stdin.read_line(&mut path)? == 0 {
+            return Ok(());
+        }
+        let path = path.trim_end_matches(&['\r', '\n'][..]).to_owned();
+        let mut len = "".to_owned();
+        stdin.read_line(&mut len)?;
+        // A malformed request is answered with an error, and the next line starts a new request.
+        let len = match len.trim().parse::<usize>() {
+            Ok(len) => len,
+            Err(_) => {
+                let error = format!("expected the length of the code, got {:?}", len);
+                respond(shell, "", "", Some(&error))?;
+                continue;
+            }
+        };
+        let mut code = vec![0; len];
+        stdin.read_exact(&mut code)?;
+        let code = match String::from_utf8(code) {
+            Ok(code) => code,
+            Err(_) => {
+                respond(shell, "", "", Some("the code is not valid UTF-8"))?;
+                continue;
+            }
+        };
+
+        let args =
+            itertools::chain!(common_args.iter().cloned(), ["--src".to_owned(), path]).collect();
+        let (result, warnings) =
+            bundle_captured(args, Some(code), None, &cwd, &cargo_equip_exe, &cache_dir)?;
+        match result {
+            Ok(code) => respond(shell, &code, &warnings, None)?,
+            Err(err) => respond(shell, "", &warnings, Some(&format!("{:?}", err)))?,
+        }
+    }
+
+    fn respond(
+        shell: &mut Shell,
+        code: &str,
+        warnings: &str,
+        error: Option<&str>,
+    ) -> io::Result<()> {
+        let out = shell.out();
+        write!(out, "code {}\n{}", code.len(), code)?;
+        write!(out, "warnings {}\n{}", warnings.len(), warnings)?;
+        match error {
+            None => writeln!(out, "ok")?,
+            Some(error) => write!(out, "error {}\n{}", error.len(), error)?,
+        }
+        out.flush()
+    }
+}
+
+/// Bundles with `args` and returns the result and the warnings, for `--server` and `daemon`.
+fn bundle_captured(
+    args: Vec<String>,
+    src_code: Option<String>,
+    state: Option<Rc<RefCell<daemon::State>>>,
+    cwd: &Path,
+    cargo_equip_exe: &AbsPathBuf,
+    cache_dir: &Path,
+) -> io::Result<(anyhow::Result<String>, String)> {
+    let output = tempfile::Builder::new()
+        .prefix("cargo-equip-")
+        .suffix(".rs")
+        .tempfile()?
+        .into_temp_path();
+    let warnings = Rc::new(RefCell::new(vec![]));
+    let result = (|| {
+        let mut opt = OptEquip::from_iter_safe(itertools::chain!(
+            ["equip".to_owned()],
+            args.iter().cloned(),
+            ["--output".to_owned(), output.display().to_string()],
+        ))?;
+        opt.src_code = src_code;
+        opt.state = state;
+        let mut shell = Shell::from_writers(
+            Box::new(io::sink()),
+            Box::new(NoColor::new(SharedBuffer(warnings.clone()))),
+        );
+        shell.set_hide_statuses(true);
+        equip(
+            opt,
+            args,
+            Context {
+                cwd: cwd.to_owned(),
+                cargo_equip_exe: cargo_equip_exe.clone(),
+                cache_dir: cache_dir.to_owned(),
+                shell: &mut shell,
+            },
+        )?;
+        cargo_util::paths::read(&output)
+    })();
+    output.close()?;
+    let warnings = String::from_utf8_lossy(&warnings.borrow()).into_owned();
+    return Ok((result, warnings));
+
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
+
+fn warn_if_large_asset(path: &Utf8Path, size: usize, shell: &mut Shell) -> anyhow::Result<()> {
+    const LARGE_ASSET_SIZE: usize = 16 * 1024;
+
+    if size > LARGE_ASSET_SIZE {
+        shell.warn(format!("inlined `{}`, which is {} bytes", path, size))?;
+    }
+    Ok(())
+}
+
+fn pick_target<'cm>(
+    targets: &[(&'cm cm::Target, &'cm cm::Package)],
+    shell: &mut Shell,
+) -> anyhow::Result<(&'cm cm::Target, &'cm cm::Package)> {
+    writeln!(shell.err(), "Which target do you want to bundle?")?;
+    for (i, (target, _)) in targets.iter().enumerate() {
+        writeln!(shell.err(), "{:>4}) {}", i + 1, target.display_name())?;
+    }
+
+    loop {
+        write!(shell.err(), "number or name: ")?;
+        shell.err().flush()?;
+
+        let mut input = "".to_owned();
+        if io::stdin().read_line(&mut input)? == 0 {
+            bail!("no target was selected");
+        }
+        let input = input.trim();
+
+        let found = if let Ok(i) = input.parse::<usize>() {
+            targets
+                .get(i.wrapping_sub(1))
+                .into_iter()
+                .copied()
+                .collect()
+        } else if let Some(&target) = targets.iter().find(|(t, _)| t.name == input) {
+            vec![target]
+        } else {
+            targets
+                .iter()
+                .filter(|(t, _)| !input.is_empty() && t.name.contains(input))
+                .copied()
+                .collect()
+        };
+
+        match *found {
+            [target] => return Ok(target),
+            [] => writeln!(shell.err(), "no such target: {:?}", input)?,
+            _ => writeln!(
+                shell.err(),
+                "ambiguous: {}",
+                found.iter().map(|(t, _)| t.display_name()).format(", "),
+            )?,
+        }
+    }
+}
+
+fn run_reproduce(path: &Path, ctx: Context<'_>) -> anyhow::Result<()> {
+    let path = &ctx.cwd.join(path);
+    let expected = cargo_util::paths::read(path)?;
+    let provenance = provenance::Provenance::read(&expected)
+        .with_context(|| format!("`{}` does not have a provenance comment", path.display()))?;
+
+    if provenance.version != env!("CARGO_PKG_VERSION") {
+        ctx.shell.warn(format!(
+            "`{}` was written by cargo-equip v{}, but this is v{}",
+            path.display(),
+            provenance.version,
+            env!("CARGO_PKG_VERSION"),
+        ))?;
+    }
+
+    let output = tempfile::Builder::new()
+        .prefix("cargo-equip-")
+        .suffix(".rs")
+        .tempfile()?
+        .into_temp_path();
+
+    let opt = OptEquip::from_iter_safe(
+        ["equip"]
+            .iter()
+            .map(|&s| s.to_owned())
+            .chain(provenance.args.iter().cloned())
+            .chain(["--output".to_owned(), output.display().to_string()]),
+    )?;
+    let Context {
+        cwd,
+        cargo_equip_exe,
+        cache_dir,
+        shell,
+    } = ctx;
+    equip(
+        opt,
+        provenance.args,
+        Context {
+            cwd,
+            cargo_equip_exe,
+            cache_dir,
+            shell,
+        },
+    )?;
+
+    let actual = cargo_util::paths::read(&output)?;
+    output.close()?;
+
+    let name = path.display().to_string();
+    if let Some(diff) = diff::unified_diff(&expected, &actual, &name, &name) {
+        write!(shell.out(), "{}", diff)?;
+        bail!("`{}` was not reproduced", name);
+    }
+    shell.status("Reproduced", &name)?;
+    Ok(())
+}
+
+fn run_notebook(
+    opt: OptNotebook,
+    cwd: &Path,
+    cargo_equip_mod_name: &syn::Ident,
+    shell: &mut Shell,
+) -> anyhow::Result<()> {
+    let OptNotebook {
+        format,
+        manifest_path,
+        remove,
+        output,
+    } = opt;
+
+    let manifest_path = if let Some(manifest_path) = manifest_path {
+        cwd.join(manifest_path.strip_prefix(".").unwrap_or(&manifest_path))
+    } else {
+        workspace::locate_project(cwd)?
+    };
+
+    let metadata = workspace::cargo_metadata(&manifest_path, cwd, &[], false)?;
+
+    let doc = notebook::notebook(&metadata, cargo_equip_mod_name, format, &remove, shell)?;
+
+    if let Some(output) = output {
+        cargo_util::paths::write(cwd.join(output), doc)
+    } else {
+        write!(shell.out(), "{}", doc)?;
+        Ok(())
+    }
+}
+
+/// Applies `rename` in the config to the names of the modules for the crates.
+///
+/// Returns the original names and the new ones.
+fn rename_libs(
+    metadata: &cm::Metadata,
+    libs_to_bundle: &mut BTreeMap<&cm::PackageId, (&cm::Target, String)>,
+    renames: &BTreeMap<String, String>,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut renamed = BTreeMap::new();
+    for (package_id, (_, pseudo_extern_crate_name)) in &mut *libs_to_bundle {
+        if let Some(rename) = renames.get(&metadata[package_id].name) {
+            if syn::parse_str::<syn::Ident>(rename).is_err() {
+                bail!("`rename`: `{}` is not a valid identifier", rename);
+            }
+            let original = mem::replace(pseudo_extern_crate_name, rename.clone());
+            renamed.insert(original, rename.clone());
+        }
+    }
+    if let Some((name, _)) = libs_to_bundle
+        .values()
+        .map(|(_, name)| name)
+        .counts()
+        .into_iter()
+        .find(|&(_, n)| n > 1)
+    {
+        bail!("`rename`: multiple crates would be named `{}`", name);
+    }
+    Ok(renamed)
+}
+
 #[allow(clippy::too_many_arguments)]
 fn bundle(
     metadata: &cm::Metadata,
     root_crate: RootCrate<'_>,
     libs_to_bundle: &BTreeMap<&cm::PackageId, (&cm::Target, String)>,
+    renamed_libs: &BTreeMap<String, String>,
+    features: &[String],
+    only: &BTreeMap<String, Vec<Vec<String>>>,
     mine: &[User],
     cargo_equip_mod_name: &syn::Ident,
+    entry_point: Option<&syn::Ident>,
+    allow_nightly: bool,
+    inline_assets: bool,
+    defines: &[(String, String)],
+    strip_debug: bool,
+    strip_debug_asserts: bool,
+    normalize_core_alloc: bool,
+    strip_derives: &[String],
     resolve_cfgs: bool,
+    fold_cfg_macros: bool,
+    target: Option<&cfg_expr::targets::TargetInfo>,
     remove: &[Remove],
+    warn_deprecated: bool,
+    emit: Emit,
+    verbatim_bin: bool,
     minify: Minify,
     rustfmt: bool,
     cargo_equip_exe: &AbsPath,
     cache_dir: &Path,
+    offline: bool,
+    no_cache: bool,
+    snapshot_dir: Option<&Path>,
+    state: Option<&RefCell<daemon::State>>,
+    report: &mut Report,
     shell: &mut Shell,
-) -> anyhow::Result<String> {
+) -> anyhow::Result<Bundled> {
     let cargo_check_message_format_json = |toolchain: &str, shell: &mut Shell| -> _ {
         let (package, krate) = root_crate.split();
-        workspace::cargo_check_message_format_json(toolchain, metadata, package, krate, shell)
+        workspace::cargo_check_message_format_json(
+            toolchain, metadata, package, krate, features, offline, shell,
+        )
     };
 
     let cargo_messages_for_out_dirs = &libs_to_bundle
@@ -630,7 +1694,20 @@ fn bundle(
             libs_to_bundle.contains_key(p)
         });
 
-    let macro_expander = has_proc_macro
+    if let Some(package_id) = libs_to_bundle
+        .keys()
+        .find(|p| metadata[p].has_proc_macro() && !proc_macro_crate_dylibs.contains_key(*p))
+    {
+        bail!(
+            "`{}` is a proc-macro crate but its compiled dylib was not found. proc-macro crates \
+             cannot be bundled as source; they are expanded with rust-analyzer's proc-macro \
+             server instead. make sure `cargo check` succeeds for it, or exclude it with \
+             `--exclude` and expand it yourself",
+            package_id,
+        );
+    }
+
+    let mut macro_expander = has_proc_macro
         .then(|| ProcMacroExpander::spawn(cargo_equip_exe, proc_macro_crate_dylibs))
         .transpose()?;
 
@@ -659,28 +1736,101 @@ fn bundle(
         .map(|node| (&node.id, node))
         .collect::<HashMap<_, _>>();
 
-    let mut code = if let Some((_, bin_target)) = root_crate.bin_like() {
+    // The bins with `cargo_equip::skip` are left as they are.
+    let mut codes = vec![];
+    let mut skipped = vec![];
+    for bin_target in root_crate.bins() {
         let code = cargo_util::paths::read(bin_target.src_path.as_ref())?;
-        if rust::find_skip_attribute(&code)? {
+        let skip = rust::find_skip_attribute(bin_target.src_path.as_str(), &code)?;
+        if skip {
             shell.status("Found", "`#![cfg_attr(cargo_equip, cargo_equip::skip)]`")?;
-            return Ok(code);
         }
-        code
-    } else {
-        "".to_owned()
+        codes.push(code);
+        skipped.push(skip);
+    }
+    if skipped == [true] {
+        return Ok(Bundled {
+            bins: codes,
+            libs: "".to_owned(),
+        });
+    }
+    if codes.is_empty() {
+        codes.push("".to_owned());
+        skipped.push(false);
+    }
+
+    // Paths in the bins to the bundled libraries, without the extern crate names.
+    let bin_paths = {
+        let bin_package = root_crate.package();
+        let mut bin_paths = vec![];
+        for (code, _) in codes.iter().zip(&skipped).filter(|(_, skip)| !**skip) {
+            bin_paths.extend(
+                rust::collect_paths(code)?
+                    .into_iter()
+                    .flat_map(|segments| {
+                        let (extern_crate_name, rest) = segments.split_first()?;
+                        let lib_package = metadata
+                            .dep_lib_by_extern_crate_name(&bin_package.id, extern_crate_name)?;
+                        Some((&lib_package.id, rest.to_owned()))
+                    })
+                    .filter(|(package_id, _)| libs_to_bundle.contains_key(package_id)),
+            );
+        }
+        bin_paths
     };
 
     shell.status("Bundling", "the code")?;
 
-    if let Some((bin_package, bin_target)) = root_crate.bin_like() {
-        code = rust::process_bin(
+    let preprocess =
+        |edit: &mut CodeEdit<'_>, src_path: &Utf8Path, shell: &mut Shell| -> anyhow::Result<()> {
+            if inline_assets {
+                for (path, size) in edit.inline_assets(src_path)? {
+                    warn_if_large_asset(&path, size, shell)?;
+                }
+            }
+            if !defines.is_empty() {
+                edit.define_env_vars(defines)?;
+            }
+            if strip_debug || strip_debug_asserts {
+                edit.strip_debug(strip_debug, strip_debug_asserts)?;
+            }
+            if normalize_core_alloc {
+                edit.normalize_core_alloc()?;
+            }
+            Ok(())
+        };
+
+    let transforms = transform::transforms(
+        resolve_cfgs,
+        fold_cfg_macros,
+        strip_derives,
+        remove.contains(&Remove::Docs),
+        remove.contains(&Remove::Comments),
+    );
+
+    let mut bin_extern_crate_renames = BTreeMap::new();
+
+    for ((bin_target, code), _) in root_crate
+        .bins()
+        .iter()
+        .zip(&mut codes)
+        .zip(&skipped)
+        .filter(|(_, skip)| !**skip)
+    {
+        let bin_package = root_crate.package();
+        let started = Report::start("expand");
+        let bin_features = &resolve_nodes[&bin_package.id].features;
+        let (bin_code, renames) = rust::process_bin(
             cargo_equip_mod_name,
             &bin_target.src_path,
-            { macro_expander }.as_mut(),
+            macro_expander.as_mut(),
             |extern_crate_name| {
                 metadata
                     .dep_lib_by_extern_crate_name(&bin_package.id, extern_crate_name)
-                    .map(|_| extern_crate_name.to_owned())
+                    .map(|lib_package| match libs_to_bundle.get(&lib_package.id) {
+                        Some((_, pseudo_extern_crate_name)) => pseudo_extern_crate_name.clone(),
+                        None => extern_crate_name.to_owned(),
+                    })
             },
             |extern_crate_name| {
                 matches!(
@@ -688,22 +1838,71 @@ fn bundle(
                     Some(lib_package) if libs_to_bundle.contains_key(&lib_package.id)
                 )
             },
+            entry_point.map(ToString::to_string).as_deref(),
+            |edit| {
+                preprocess(edit, &bin_target.src_path, shell)?;
+                if resolve_cfgs {
+                    edit.resolve_feature_cfgs(bin_features)?;
+                }
+                Ok(())
+            },
             || (bin_target.crate_name(), &bin_package.id.repr),
         )?;
+        *code = bin_code;
+        bin_extern_crate_renames.extend(renames);
+        report.finish(started);
+        report.krate(bin_target.crate_name(), &[code]);
+        write_snapshot(snapshot_dir, None, "1-expanded", code)?;
     }
 
+    let started = Report::start("parse");
     let libs = libs_to_bundle
         .iter()
         .map(|(pkg, (krate, pseudo_extern_crate_name))| {
+            // Every `mod` file is read here. Unused modules are removed afterwards with `--only`
+            // and `--remove hidden-modules`, since the references between modules are only known
+            // after inlining them.
             let mut edit = CodeEdit::new(cargo_equip_mod_name, &krate.src_path, || {
                 (krate.crate_name(), &pkg.repr)
             })?;
             if let Some(out_dir) = out_dirs.get(pkg) {
                 edit.expand_includes(out_dir)?;
             }
+            preprocess(&mut edit, &krate.src_path, shell)?;
+            if let Some(only) = only.get(&krate.crate_name()) {
+                edit.retain_modules(only)?;
+            }
+            let used = bin_paths
+                .iter()
+                .filter(|(package_id, _)| package_id == pkg)
+                .map(|(_, path)| path.clone())
+                .collect::<Vec<_>>();
+            if warn_deprecated {
+                for (path, note) in rust::deprecated_items(edit.code()?)? {
+                    if used.iter().any(|p| p.starts_with(&path)) {
+                        let path = itertools::chain!([krate.crate_name()], path).join("::");
+                        shell.warn(match note {
+                            Some(note) => format!("`{}` is deprecated: {}", path, note),
+                            None => format!("`{}` is deprecated", path),
+                        })?;
+                    }
+                }
+            }
+            if remove.contains(&Remove::HiddenModules) {
+                for path in edit.remove_unreachable_hidden_modules(&used)? {
+                    tracing::debug!(package = %pkg, "removed `{}`", path.join("::"));
+                }
+            }
+            write_snapshot(
+                snapshot_dir,
+                Some(pseudo_extern_crate_name),
+                "1-expanded",
+                edit.code()?,
+            )?;
             Ok((*pkg, (*krate, &**pseudo_extern_crate_name, edit)))
         })
         .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+    report.finish(started);
 
     let (graph, indices) = normal_non_host_dep_graph(&resolve_nodes, libs_to_bundle);
 
@@ -758,14 +1957,20 @@ fn bundle(
         libs_with_local_inner_macros
     };
 
+    let mut nightly_features = BTreeSet::new();
+    let cache = ExpansionCache::new(metadata, state);
+
+    let started = Report::start("expand");
     let libs = libs
         .into_iter()
         .map(
             |(lib_package, (lib_target, pseudo_extern_crate_name, mut edit))| {
                 let lib_package: &cm::Package = &metadata[lib_package];
+                let source_len = edit.source_len();
+                tracing::debug!(package = %lib_package.id, "expanding `{}`", pseudo_extern_crate_name);
 
                 if let Some(names) = proc_macro_names.get(&lib_package.id) {
-                    debug_assert_eq!(["proc-macro".to_owned()], *lib_target.kind);
+                    debug_assert!(lib_target.is_proc_macro());
                     let names = names
                         .iter()
                         .map(|name| {
@@ -816,19 +2021,59 @@ fn bundle(
                     ));
                 }
 
-                let cm::Node { features, .. } = resolve_nodes[&lib_package.id];
+                let cm::Node { features, deps, .. } = resolve_nodes[&lib_package.id];
+
+                // Snapshots are written only when the crate is actually expanded.
+                let hash = (!no_cache && snapshot_dir.is_none())
+                    .then(|| -> anyhow::Result<_> {
+                        let deps = deps
+                            .iter()
+                            .map(|cm::NodeDep { name, pkg, .. }| {
+                                (name, libs_to_bundle.get(pkg).map(|(_, name)| name))
+                            })
+                            .collect::<Vec<_>>();
+                        Ok(ExpansionCache::hash(&[
+                            edit.code()?,
+                            pseudo_extern_crate_name,
+                            &cargo_equip_mod_name.to_string(),
+                            &features.join(","),
+                            &format!("{:?}", deps),
+                            &format!("{:?}", libs_with_local_inner_macros[&lib_package.id]),
+                            &transforms.iter().map(|t| t.name()).join(","),
+                            &format!(
+                                "{:?}",
+                                (
+                                    target.map(|t| &t.triple),
+                                    resolve_cfgs,
+                                    fold_cfg_macros,
+                                    strip_derives,
+                                    remove,
+                                    allow_nightly,
+                                ),
+                            ),
+                        ]))
+                    })
+                    .transpose()?;
+                if let Some(expansion) = hash.as_ref().and_then(|h| cache.get(&lib_package.id, h)) {
+                    tracing::debug!(package = %lib_package.id, "reusing the previous expansion");
+                    nightly_features.extend(expansion.features);
+                    report.pruned(source_len, expansion.crate_mod.len());
+                    return Ok((
+                        pseudo_extern_crate_name,
+                        (
+                            lib_package,
+                            expansion.crate_mod,
+                            expansion.macro_mod,
+                            expansion.prelude_mod,
+                        ),
+                    ));
+                }
 
                 let translate_extern_crate_name = |dst: &_| -> _ {
                     let dst_package =
                         metadata.dep_lib_by_extern_crate_name(&lib_package.id, dst)?;
-                    let (_, dst_pseudo_extern_crate_name) =
-                        libs_to_bundle.get(&dst_package.id).unwrap_or_else(|| {
-                            panic!(
-                                "missing `extern_crate_name` for `{}`. generated one should be \
-                                 given beforehead. this is a bug",
-                                dst_package.id,
-                            );
-                        });
+                    // Excluded or unresolved crates are left as they are.
+                    let (_, dst_pseudo_extern_crate_name) = libs_to_bundle.get(&dst_package.id)?;
                     Some(dst_pseudo_extern_crate_name.clone())
                 };
 
@@ -836,38 +2081,82 @@ fn bundle(
                 edit.translate_extern_crate_paths(translate_extern_crate_name)?;
                 edit.process_extern_crates_in_lib(translate_extern_crate_name, shell)?;
                 let macro_mod_content = edit.modify_declarative_macros(pseudo_extern_crate_name)?;
+                let extern_crate_name_translation = metadata
+                    .libs_with_extern_crate_names(
+                        &lib_package.id,
+                        &libs_to_bundle.keys().copied().collect(),
+                    )?
+                    .into_iter()
+                    .map(|(package_id, extern_crate_name)| {
+                        let (_, pseudo_extern_crate_name) =
+                            libs_to_bundle.get(package_id).with_context(|| {
+                                "could not translate pseudo extern crate names. this is a bug"
+                            })?;
+                        Ok((extern_crate_name, pseudo_extern_crate_name.clone()))
+                    })
+                    .collect::<anyhow::Result<_>>()?;
                 let prelude_mod_content = edit.resolve_pseudo_prelude(
                     pseudo_extern_crate_name,
                     &libs_with_local_inner_macros[&lib_package.id],
-                    &{
-                        metadata
-                            .libs_with_extern_crate_names(
-                                &lib_package.id,
-                                &libs_to_bundle.keys().copied().collect(),
-                            )?
-                            .into_iter()
-                            .map(|(package_id, extern_crate_name)| {
-                                let (_, pseudo_extern_crate_name) =
-                                libs_to_bundle.get(package_id).with_context(|| {
-                                    "could not translate pseudo extern crate names. this is a bug"
-                                })?;
-                                Ok((extern_crate_name, pseudo_extern_crate_name.clone()))
-                            })
-                            .collect::<anyhow::Result<_>>()?
-                    },
+                    &extern_crate_name_translation,
                 )?;
-                if resolve_cfgs {
-                    edit.resolve_cfgs(features)?;
-                }
-                if remove.contains(&Remove::Docs) {
-                    edit.allow_missing_docs();
-                    edit.erase_docs()?;
+                write_snapshot(
+                    snapshot_dir,
+                    Some(pseudo_extern_crate_name),
+                    "2-translated",
+                    edit.code()?,
+                )?;
+                let mut code = edit.finish()?;
+                for transform in &transforms {
+                    let file = &syn::parse_file(&code)?;
+                    let krate = transform::Crate {
+                        name: pseudo_extern_crate_name,
+                        cargo_equip_mod_name,
+                        code: &code,
+                        file,
+                        renames: &extern_crate_name_translation,
+                        features,
+                        target,
+                    };
+                    if let Some(new_code) = transform.transform(&krate).with_context(|| {
+                        format!("`{}` failed on `{}`", transform.name(), lib_package.id)
+                    })? {
+                        code = new_code;
+                    }
                 }
-                if remove.contains(&Remove::Comments) {
-                    edit.erase_comments()?;
+                let mut edit = CodeEdit::from_code(cargo_equip_mod_name, &code)
+                    .with_context(|| "broke the code during the transformations")?;
+
+                let features = edit.take_features()?;
+                for feature in &features {
+                    if !allow_nightly {
+                        bail!(
+                            "`{}` uses `#![feature({})]`. run with `--allow-nightly` to bundle it",
+                            lib_package.id,
+                            feature,
+                        );
+                    }
+                    nightly_features.insert(feature.clone());
                 }
 
                 let crate_mod_content = edit.finish()?;
+                report.pruned(source_len, crate_mod_content.len());
+                if let Some(hash) = hash {
+                    cache.put(
+                        &lib_package.id,
+                        hash,
+                        &crate_mod_content,
+                        &macro_mod_content,
+                        &prelude_mod_content,
+                        &features,
+                    )?;
+                }
+                write_snapshot(
+                    snapshot_dir,
+                    Some(pseudo_extern_crate_name),
+                    "3-resolved",
+                    &crate_mod_content,
+                )?;
 
                 Ok((
                     pseudo_extern_crate_name,
@@ -881,7 +2170,20 @@ fn bundle(
             },
         )
         .collect::<anyhow::Result<Vec<(&str, (&cm::Package, String, String, String))>>>()?;
+    report.finish(started);
 
+    for (pseudo_extern_crate_name, (_, crate_mod, macro_mod, prelude_mod)) in &libs {
+        report.krate(
+            *pseudo_extern_crate_name,
+            &[crate_mod, macro_mod, prelude_mod],
+        );
+    }
+
+    let started = Report::start("emit");
+    if emit == Emit::LibOnly {
+        codes = vec!["".to_owned()];
+    }
+    let mut libs_code = "".to_owned();
     if !libs.is_empty() {
         if !root_crate.package().authors.is_empty() {
             shell.warn(
@@ -890,15 +2192,29 @@ fn bundle(
             shell.warn("instead, add `--mine github.com/{your username}` to the arguments")?;
         }
 
-        code = rust::insert_prelude_for_main_crate(&code, cargo_equip_mod_name)?;
-
-        code =
-            rust::allow_unused_imports_for_seemingly_proc_macros(&code, |mod_name, item_name| {
-                matches!(
-                    libs_using_proc_macros.get(item_name), Some(pseudo_extern_crate_names)
-                    if pseudo_extern_crate_names.contains(mod_name)
+        for (code, _) in codes.iter_mut().zip(&skipped).filter(|(_, skip)| !**skip) {
+            *code = if emit == Emit::LibOnly {
+                format!("pub use {}::prelude::*;\n", cargo_equip_mod_name)
+            } else if verbatim_bin {
+                format!(
+                    "{}\npub use {}::prelude::*;\n",
+                    rust::insert_prelude_for_main_crate(code, cargo_equip_mod_name, false)?,
+                    cargo_equip_mod_name,
                 )
-            })?;
+            } else {
+                rust::insert_prelude_for_main_crate(code, cargo_equip_mod_name, true)?
+            };
+
+            *code = rust::allow_unused_imports_for_seemingly_proc_macros(
+                code,
+                |mod_name, item_name| {
+                    matches!(
+                        libs_using_proc_macros.get(item_name), Some(pseudo_extern_crate_names)
+                        if pseudo_extern_crate_names.contains(mod_name)
+                    )
+                },
+            )?;
+        }
 
         let doc = &{
             fn list_packages<'a>(
@@ -907,43 +2223,38 @@ fn bundle(
                 cargo_equip_mod_name: &syn::Ident,
                 contents: impl Iterator<Item = (Option<&'a str>, &'a cm::Package)>,
             ) {
-                let mut table = Table::new();
-
-                *table.get_format() = FormatBuilder::new()
-                    .column_separator(' ')
-                    .borders(' ')
-                    .build();
+                let mut table = vec![];
 
                 let contents = contents.collect::<Vec<_>>();
                 let any_from_local_filesystem = contents.iter().any(|(_, p)| p.source.is_none());
 
                 for (pseudo_extern_crate_name, package) in contents {
-                    let mut row = row![format!("- `{}`", package.id.mask_path())];
+                    let mut row = vec![format!("- `{}`", package.id.mask_path())];
 
                     if any_from_local_filesystem {
-                        row.add_cell(if package.source.is_some() {
-                            cell!("")
+                        row.push(if package.source.is_some() {
+                            "".to_owned()
                         } else if let Some(repository) = &package.repository {
-                            cell!(format!("published in {}", repository))
+                            format!("published in {}", repository)
                         } else {
-                            cell!("published in **missing**")
+                            "published in **missing**".to_owned()
                         });
                     }
 
-                    row.add_cell(if let Some(license) = &package.license {
-                        cell!(format!("licensed under `{}`", license))
+                    row.push(if let Some(license) = &package.license {
+                        format!("licensed under `{}`", license)
                     } else {
-                        cell!("licensed under **missing**")
+                        "licensed under **missing**".to_owned()
                     });
 
                     if let Some(pseudo_extern_crate_name) = pseudo_extern_crate_name {
-                        row.add_cell(cell!(format!(
+                        row.push(format!(
                             "as `crate::{}::crates::{}`",
                             cargo_equip_mod_name, pseudo_extern_crate_name,
-                        )));
+                        ));
                     }
 
-                    table.add_row(row);
+                    table.push(row);
                 }
 
                 if !table.is_empty() {
@@ -951,10 +2262,7 @@ fn bundle(
                         *doc += "\n";
                     }
                     *doc += &format!(" # {}\n\n", title);
-                    for line in table.to_string().lines() {
-                        *doc += line.trim_end();
-                        *doc += "\n";
-                    }
+                    *doc += &render_table(&table);
                 }
             }
 
@@ -986,7 +2294,7 @@ fn bundle(
                 .map(|(_, (lib_package, _, _, _))| {
                     shell.status("Checking", format!("the license of `{}`", lib_package.id))?;
                     lib_package
-                        .read_license_text(mine, cache_dir)
+                        .read_license_text(mine, cache_dir, offline)
                         .map(|license_text| {
                             license_text.map(|license_text| (&lib_package.id, license_text))
                         })
@@ -1023,14 +2331,26 @@ fn bundle(
             doc
         };
 
-        code += "\n";
-        code += &match root_crate {
+        libs_code += "\n";
+        libs_code += &match root_crate {
             RootCrate::BinLike(..) => {
                 "// The following code was expanded by `cargo-equip`.\n".to_owned()
             }
             RootCrate::Lib(..) => format!("use {}::prelude::*;\n", cargo_equip_mod_name),
         };
-        code += "\n";
+        libs_code += "\n";
+
+        // Crates which only re-export another bundled crate are emitted as aliases. Their macro
+        // modules are kept since `#[macro_use] extern crate` may refer to them.
+        let facades = libs
+            .iter()
+            .map(|(name, (_, crate_mod, macro_mod, prelude_mod))| {
+                let target =
+                    rust::facade_target(cargo_equip_mod_name, crate_mod, macro_mod, prelude_mod)?;
+                Ok(target.map(|target| (*name, target)))
+            })
+            .flat_map(Result::transpose)
+            .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
 
         let crate_mods = libs
             .iter()
@@ -1044,55 +2364,85 @@ fn bundle(
 
         let prelude_mods = libs
             .iter()
+            .filter(|(name, _)| !facades.contains_key(name))
             .map(|(name, (_, _, _, content))| (*name, &**content))
             .collect::<Vec<_>>();
 
-        let render_mods = |code: &mut String, mods: &[(&str, &str)]| -> anyhow::Result<()> {
+        let mut render_mods = |code: &mut String,
+                               mods: &[(&str, &str)],
+                               aliases: &BTreeMap<&str, String>|
+         -> anyhow::Result<()> {
             if minify == Minify::Libs {
                 for (pseudo_extern_crate_name, mod_content) in mods {
+                    if let Some(target) = aliases.get(pseudo_extern_crate_name) {
+                        *code +=
+                            &format!("        {}\n", rust::region_begin(pseudo_extern_crate_name));
+                        *code += &format!(
+                            "        pub use self::{} as {};\n",
+                            target, pseudo_extern_crate_name
+                        );
+                        *code +=
+                            &format!("        {}\n", rust::region_end(pseudo_extern_crate_name));
+                        continue;
+                    }
+                    let minified = rustminify::minify_file(&rust::parse_file(mod_content)?);
+                    report.minified(mod_content.len(), minified.len());
+                    *code += &format!("        {}\n", rust::region_begin(pseudo_extern_crate_name));
                     *code += "        pub mod ";
                     *code += pseudo_extern_crate_name;
                     *code += " {";
-                    *code += &rustminify::minify_file(&rust::parse_file(mod_content)?);
+                    *code += &minified;
                     *code += "}\n";
+                    *code += &format!("        {}\n", rust::region_end(pseudo_extern_crate_name));
                 }
             } else {
                 for (i, (pseudo_extern_crate_name, mod_content)) in mods.iter().enumerate() {
                     if i > 0 {
                         *code += "\n";
                     }
+                    *code += &format!("        {}\n", rust::region_begin(pseudo_extern_crate_name));
+                    if let Some(target) = aliases.get(pseudo_extern_crate_name) {
+                        *code += &format!(
+                            "        pub use self::{} as {};\n",
+                            target, pseudo_extern_crate_name
+                        );
+                        *code +=
+                            &format!("        {}\n", rust::region_end(pseudo_extern_crate_name));
+                        continue;
+                    }
                     *code += "        pub mod ";
                     *code += pseudo_extern_crate_name;
                     *code += " {\n";
                     *code += &rust::indent_code(mod_content, 3);
                     *code += "    }\n";
+                    *code += &format!("        {}\n", rust::region_end(pseudo_extern_crate_name));
                 }
             }
             Ok(())
         };
 
         for doc in doc.lines() {
-            code += "///";
-            if !code.is_empty() {
-                code += " ";
+            libs_code += "///";
+            if !libs_code.is_empty() {
+                libs_code += " ";
             }
-            code += doc;
-            code += "\n";
+            libs_code += doc;
+            libs_code += "\n";
         }
         if minify == Minify::Libs {
-            code += "#[cfg_attr(any(), rustfmt::skip)]\n";
-        }
-        code += "#[allow(unused)]\n";
-        code += &format!("mod {} {{\n", cargo_equip_mod_name);
-        code += "    pub(crate) mod crates {\n";
-        render_mods(&mut code, &crate_mods)?;
-        code += "    }\n";
-        code += "\n";
-        code += "    pub(crate) mod macros {\n";
-        render_mods(&mut code, &macro_mods)?;
-        code += "    }\n";
-        code += "\n";
-        code += "    pub(crate) mod prelude {";
+            libs_code += "#[cfg_attr(any(), rustfmt::skip)]\n";
+        }
+        libs_code += "#[allow(unused)]\n";
+        libs_code += &format!("mod {} {{\n", cargo_equip_mod_name);
+        libs_code += "    pub(crate) mod crates {\n";
+        render_mods(&mut libs_code, &crate_mods, &facades)?;
+        libs_code += "    }\n";
+        libs_code += "\n";
+        libs_code += "    pub(crate) mod macros {\n";
+        render_mods(&mut libs_code, &macro_mods, &btreemap!())?;
+        libs_code += "    }\n";
+        libs_code += "\n";
+        libs_code += "    pub(crate) mod prelude {";
         match root_crate {
             RootCrate::BinLike(..) => {
                 let prelude_for_main = {
@@ -1110,50 +2460,166 @@ fn bundle(
                         parts => Some(format!("{{{}}}", parts.iter().format(","))),
                     };
 
+                    // Renamed crates are still available under the names the bin uses.
+                    let bin_package = root_crate.package();
+                    let pseudo_extern_crate_name = |extern_crate_name: &str| {
+                        metadata
+                            .dep_lib_by_extern_crate_name(&bin_package.id, extern_crate_name)
+                            .and_then(|lib_package| libs_to_bundle.get(&lib_package.id))
+                            .map_or(extern_crate_name, |(_, name)| name)
+                            .to_owned()
+                    };
+
+                    let parts = itertools::chain!(
+                        ["crates::*".to_owned()],
+                        renamed_libs
+                            .iter()
+                            .filter(
+                                |(original, rename)| pseudo_extern_crate_name(original) == **rename
+                            )
+                            .map(|(original, rename)| format!(
+                                "crates::{} as {}",
+                                rename, original
+                            )),
+                        bin_extern_crate_renames.iter().map(|(rename, name)| {
+                            format!("crates::{} as {}", pseudo_extern_crate_name(name), rename)
+                        }),
+                        local_macro_uses_in_main_crate.map(|uses| format!("macros::{}", uses)),
+                    )
+                    .collect::<Vec<_>>();
+
                     format!(
                         "pub use crate::{}::{};",
                         cargo_equip_mod_name,
-                        if let Some(local_macro_uses_in_main_crate) = local_macro_uses_in_main_crate
-                        {
-                            format!("{{crates::*,macros::{}}}", local_macro_uses_in_main_crate)
-                        } else {
-                            "crates::*".to_owned()
+                        match &*parts {
+                            [part] => part.clone(),
+                            parts => format!("{{{}}}", parts.iter().format(",")),
                         }
                     )
                 };
-                code += &if minify == Minify::Libs {
+                libs_code += &if minify == Minify::Libs {
                     prelude_for_main
                 } else {
                     format!("\n    {}\n    ", prelude_for_main)
                 };
             }
             RootCrate::Lib(_, krate) => {
-                code += &format!("pub use crate::{}::crates::", cargo_equip_mod_name);
-                code += &krate.crate_name();
-                code += ";";
+                let (_, pseudo_extern_crate_name) = &libs_to_bundle[&root_crate.package().id];
+                libs_code += &format!("pub use crate::{}::crates::", cargo_equip_mod_name);
+                libs_code += pseudo_extern_crate_name;
+                if *pseudo_extern_crate_name != krate.crate_name() {
+                    libs_code += &format!(" as {}", krate.crate_name());
+                }
+                libs_code += ";";
             }
         }
-        code += "}\n";
-        code += "\n";
-        code += "    mod preludes {\n";
-        render_mods(&mut code, &prelude_mods)?;
-        code += "    }\n";
-        code += "}\n";
+        libs_code += "}\n";
+        libs_code += "\n";
+        libs_code += "    mod preludes {\n";
+        render_mods(&mut libs_code, &prelude_mods, &btreemap!())?;
+        libs_code += "    }\n";
+        libs_code += "}\n";
     }
 
-    if minify == Minify::All {
-        code = rustminify::minify_file(&rust::parse_file(&code)?);
+    // A single bin is processed together with the libraries.
+    let (mut bins, mut libs_code) = if let [code] = &*codes {
+        (vec![format!("{}{}", code, libs_code)], "".to_owned())
+    } else {
+        (codes, libs_code)
+    };
+    let mut parts = bins
+        .iter_mut()
+        .zip(&skipped)
+        .filter(|(_, skip)| !**skip)
+        .map(|(code, _)| (code, true))
+        .chain((!libs_code.is_empty()).then(|| (&mut libs_code, false)))
+        .collect::<Vec<_>>();
+
+    for (code, is_bin) in &mut parts {
+        if !libs.is_empty() {
+            **code = rust::dedup_uses(code)?;
+        }
+        if *is_bin && !nightly_features.is_empty() {
+            **code = rust::insert_features(code, &nightly_features)?;
+        }
+        if minify == Minify::All {
+            let minified = rustminify::minify_file(&rust::parse_file(code)?);
+            report.minified(code.len(), minified.len());
+            **code = minified;
+        }
     }
+    report.finish(started);
 
     if rustfmt {
-        code = rustfmt::rustfmt(
-            &metadata.workspace_root,
-            &code,
-            &root_crate.package().edition,
-        )?;
+        for (code, _) in &mut parts {
+            **code = report.time("format", || {
+                rustfmt::rustfmt(
+                    &metadata.workspace_root,
+                    code,
+                    &root_crate.package().edition,
+                )
+            })?;
+        }
     }
 
-    Ok(code)
+    if !libs_code.is_empty() {
+        libs_code = format!("\n{}", libs_code.trim_start());
+    }
+    let bundled = Bundled {
+        bins,
+        libs: libs_code,
+    };
+
+    if let Some(snapshot_dir) = snapshot_dir {
+        cargo_util::paths::create_dir_all(snapshot_dir)?;
+        cargo_util::paths::write(snapshot_dir.join("output.rs"), bundled.program(0))?;
+    }
+    Ok(bundled)
+}
+
+/// Renders `table` with the columns left-aligned and separated by a space, in the same layout as
+/// `prettytable-rs` with `' '` as the borders and the column separator.
+fn render_table(table: &[Vec<String>]) -> String {
+    let widths = (0..table.iter().map(Vec::len).max().unwrap_or(0))
+        .map(|i| {
+            table
+                .iter()
+                .flat_map(|row| row.get(i))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+    let mut output = "".to_owned();
+    for row in table {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:<width$}", cell))
+            .join(" ");
+        output += format!(" {}", line).trim_end();
+        output += "\n";
+    }
+    output
+}
+
+/// Writes `{snapshot_dir}/{stage}.rs` for the root crate and
+/// `{snapshot_dir}/crates/{pseudo_extern_crate_name}/{stage}.rs` for the libraries.
+fn write_snapshot(
+    snapshot_dir: Option<&Path>,
+    pseudo_extern_crate_name: Option<&str>,
+    stage: &str,
+    code: &str,
+) -> anyhow::Result<()> {
+    if let Some(snapshot_dir) = snapshot_dir {
+        let dir = match pseudo_extern_crate_name {
+            Some(name) => snapshot_dir.join("crates").join(name),
+            None => snapshot_dir.to_owned(),
+        };
+        cargo_util::paths::create_dir_all(&dir)?;
+        cargo_util::paths::write(dir.join(stage).with_extension("rs"), code)?;
+    }
+    Ok(())
 }
 
 fn normal_non_host_dep_graph<'cm>(
@@ -1188,16 +2654,18 @@ fn normal_non_host_dep_graph<'cm>(
     (graph, indices)
 }
 
+/// A lib, or one or more bins or examples in one package.
 #[derive(Clone, Copy)]
 enum RootCrate<'cm> {
-    BinLike(&'cm cm::Package, &'cm cm::Target),
+    BinLike(&'cm cm::Package, &'cm [&'cm cm::Target]),
     Lib(&'cm cm::Package, &'cm cm::Target),
 }
 
 impl<'cm> RootCrate<'cm> {
     fn split(self) -> (&'cm cm::Package, &'cm cm::Target) {
         match self {
-            RootCrate::BinLike(p, t) | RootCrate::Lib(p, t) => (p, t),
+            RootCrate::BinLike(p, ts) => (p, ts[0]),
+            RootCrate::Lib(p, t) => (p, t),
         }
     }
 
@@ -1207,10 +2675,97 @@ impl<'cm> RootCrate<'cm> {
         }
     }
 
-    fn bin_like(self) -> Option<(&'cm cm::Package, &'cm cm::Target)> {
+    fn bins(self) -> &'cm [&'cm cm::Target] {
         match self {
-            RootCrate::BinLike(p, t) => Some((p, t)),
-            RootCrate::Lib(..) => None,
+            RootCrate::BinLike(_, ts) => ts,
+            RootCrate::Lib(..) => &[],
         }
     }
 }
+
+/// Output of `bundle`: the code of each bin, and the libraries shared by them.
+///
+/// For a single bin or a lib, everything is in `bins[0]`.
+struct Bundled {
+    bins: Vec<String>,
+    libs: String,
+}
+
+impl Bundled {
+    /// The whole output. With more than one bin, each is preceded by `section_header` and wrapped in
+    /// `mod bin_{name}`. A `fn main` runs the bin named by the first argument, or the first bin,
+    /// and the libraries follow them once.
+    fn join(&self, names: &[&str], section_header: &str) -> anyhow::Result<String> {
+        if let [code] = &*self.bins {
+            return Ok(format!("{}{}", code, self.libs));
+        }
+        let mut output = "".to_owned();
+        let mut arms = vec![];
+        for (code, name) in self.bins.iter().zip(names) {
+            let mod_name = format!("bin_{}", name.replace('-', "_"));
+            let (code, returns_value) = rust::publish_main(code)?;
+            let code = rust::insert_after_inner_attrs(&code, "use super::*;\n")?;
+            output += &format!(
+                "{}\nmod {} {{\n{}}}\n\n",
+                section_header.replace("{bin}", name),
+                mod_name,
+                code,
+            );
+            arms.push(if returns_value {
+                format!(
+                    "{{\n            if let Err(err) = {}::main() {{\n                \
+                     eprintln!(\"Error: {{:?}}\", err);\n                \
+                     ::std::process::exit(1);\n            }}\n        }}",
+                    mod_name,
+                )
+            } else {
+                format!("{}::main()", mod_name)
+            });
+        }
+        output += "fn main() {\n    match ::std::env::args().nth(1).as_deref() {\n";
+        for (name, arm) in names.iter().zip(&arms).skip(1) {
+            output += &format!("        Some({:?}) => {},\n", name, arm);
+        }
+        output += &format!("        _ => {},\n    }}\n}}\n", arms[0]);
+        output += &self.libs;
+        Ok(output)
+    }
+
+    /// The `i`-th bin with the libraries, which is checked.
+    fn program(&self, i: usize) -> String {
+        format!("{}{}", self.bins[i], self.libs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn render_table() {
+        // The rows of the example in README, which was rendered with `prettytable-rs`.
+        let table = [
+            vec![
+                "- `mic 0.0.0 (path+███████████████████████████████████████████)`",
+                "published in https://github.com/qryxip/mic",
+                "licensed under `CC0-1.0`",
+                "as `crate::__cargo_equip::crates::mic`",
+            ],
+            vec![
+                "- `qryxip-competitive-input 0.0.0 (git+https://github.com/qryxip/competitive-programming-library#dadeb6e4685a86f25b4e5c8079f56337321aa12e)`",
+                "",
+                "licensed under `CC0-1.0`",
+                "as `crate::__cargo_equip::crates::input`",
+            ],
+            vec![
+                "- `qryxip-competitive-partition-point 0.0.0 (git+https://github.com/qryxip/competitive-programming-library#dadeb6e4685a86f25b4e5c8079f56337321aa12e)`",
+                "",
+                "licensed under `CC0-1.0`",
+                "as `crate::__cargo_equip::crates::partition_point`",
+            ],
+        ];
+        let table = table
+            .iter()
+            .map(|row| row.iter().map(|&cell| cell.to_owned()).collect())
+            .collect::<Vec<_>>();
+        insta::assert_snapshot!(super::render_table(&table));
+    }
+}
@@ -2,16 +2,37 @@
 #![warn(rust_2018_idioms)]
 
 use anyhow::{anyhow, Context as _};
-use cargo_equip::{shell::Shell, Context, Opt};
+use cargo_equip::{shell::Shell, Context, ErrorFormat, ErrorKind, Opt};
 use ra_ap_paths::AbsPathBuf;
-use std::{convert::TryFrom as _, env};
+use std::{convert::TryFrom as _, env, io};
 use structopt::{clap, StructOpt};
+use tracing_subscriber::{filter::Targets, fmt::format::FmtSpan, prelude::*};
 
 fn main() {
     let mut shell = Shell::new();
 
+    if let Ok(directives) = env::var("CARGO_EQUIP_LOG") {
+        match directives.parse::<Targets>() {
+            Ok(targets) => tracing_subscriber::registry()
+                .with(
+                    tracing_subscriber::fmt::layer()
+                        .with_span_events(FmtSpan::CLOSE)
+                        .with_ansi(atty::is(atty::Stream::Stderr))
+                        .with_writer(io::stderr),
+                )
+                .with(targets)
+                .init(),
+            Err(err) => {
+                let _ = shell.warn(format!("ignoring invalid `$CARGO_EQUIP_LOG`: {}", err));
+            }
+        }
+    }
+
+    let mut error_format = ErrorFormat::Human;
+
     let result = (|| {
         let opt = Opt::from_iter_safe(env::args_os())?;
+        error_format = opt.error_format();
 
         let ctx = Context {
             cwd: env::current_dir().with_context(|| "could not get the current direcotry")?,
@@ -32,15 +53,29 @@ fn main() {
     })();
 
     if let Err(err) = result {
-        exit_with_error(err, &mut shell);
+        exit_with_error(err, error_format, &mut shell);
     }
 }
 
-fn exit_with_error(err: anyhow::Error, shell: &mut Shell) -> ! {
+fn exit_with_error(err: anyhow::Error, error_format: ErrorFormat, shell: &mut Shell) -> ! {
     if let Some(err) = err.downcast_ref::<clap::Error>() {
         err.exit();
     }
 
+    let kind = ErrorKind::of(&err);
+    let code = kind.map_or(1, ErrorKind::exit_code);
+
+    if error_format == ErrorFormat::Json {
+        let report = serde_json::json!({
+            "kind": kind.map_or("other", ErrorKind::name),
+            "exit_code": code,
+            "message": err.to_string(),
+            "causes": err.chain().skip(1).map(ToString::to_string).collect::<Vec<_>>(),
+        });
+        let _ = writeln!(shell.err(), "{}", report);
+        std::process::exit(code);
+    }
+
     let _ = shell.error(&err);
 
     for cause in err.chain().skip(1) {
@@ -54,5 +89,5 @@ fn exit_with_error(err: anyhow::Error, shell: &mut Shell) -> ! {
         }
     }
 
-    std::process::exit(1);
+    std::process::exit(code);
 }
@@ -0,0 +1,304 @@
+use crate::daemon;
+use cargo_metadata as cm;
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+};
+
+/// Outputs of `cargo metadata` from the previous runs, kept in `{cache directory}/metadata`.
+///
+/// Each entry is reused while the manifests of the local packages, the lockfile, and the directories
+/// where Cargo discovers targets and workspace members are unchanged. `cargo equip daemon` also
+/// keeps the entries in memory.
+pub(crate) struct MetadataCache<'a> {
+    dir: PathBuf,
+    state: Option<&'a RefCell<daemon::State>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Entry {
+    inputs: Vec<(PathBuf, String)>,
+    metadata: cm::Metadata,
+}
+
+impl<'a> MetadataCache<'a> {
+    pub(crate) fn new(cache_dir: &Path, state: Option<&'a RefCell<daemon::State>>) -> Self {
+        Self {
+            dir: cache_dir.join("metadata"),
+            state,
+        }
+    }
+
+    pub(crate) fn get(
+        &self,
+        manifest_path: &Path,
+        features: &[String],
+        offline: bool,
+    ) -> Option<cm::Metadata> {
+        let path = self.path(manifest_path, features, offline);
+        let in_memory = self
+            .state
+            .and_then(|state| state.borrow().metadata.get(&path).cloned());
+        let entry = match in_memory {
+            Some(entry) => entry,
+            None => {
+                let json = cargo_util::paths::read(&path).ok()?;
+                let entry = serde_json::from_str::<Entry>(&json).ok()?;
+                if let Some(state) = self.state {
+                    state.borrow_mut().metadata.insert(path, entry.clone());
+                }
+                entry
+            }
+        };
+        let Entry { inputs, metadata } = entry;
+        let targets_exist = metadata
+            .packages
+            .iter()
+            .filter(|p| p.source.is_none())
+            .flat_map(|p| &p.targets)
+            .all(|t| t.src_path.exists());
+        (targets_exist && inputs.iter().all(|(path, hash)| input_hash(path) == *hash))
+            .then(|| metadata)
+    }
+
+    pub(crate) fn put(
+        &self,
+        manifest_path: &Path,
+        features: &[String],
+        offline: bool,
+        metadata: &cm::Metadata,
+    ) -> anyhow::Result<()> {
+        let workspace_root = metadata.workspace_root.as_std_path();
+        let local_packages = metadata.packages.iter().filter(|p| p.source.is_none());
+        let inputs = itertools::chain!(
+            local_packages
+                .clone()
+                .map(|p| p.manifest_path.clone().into_std_path_buf()),
+            [workspace_root.join("Cargo.lock")],
+            local_packages.flat_map(|p| {
+                let dir = p.manifest_path.parent().unwrap().as_std_path();
+                TARGET_DIRS.iter().map(move |d| dir.join(d))
+            }),
+            member_glob_dirs(workspace_root),
+        )
+        .map(|path| {
+            let hash = input_hash(&path);
+            (path, hash)
+        })
+        .collect();
+        let entry = Entry {
+            inputs,
+            metadata: metadata.clone(),
+        };
+        let path = self.path(manifest_path, features, offline);
+        cargo_util::paths::create_dir_all(&self.dir)?;
+        cargo_util::paths::write(&path, serde_json::to_string(&entry)?)?;
+        if let Some(state) = self.state {
+            state.borrow_mut().metadata.insert(path, entry);
+        }
+        Ok(())
+    }
+
+    fn path(&self, manifest_path: &Path, features: &[String], offline: bool) -> PathBuf {
+        let cargo_exe = crate::process::cargo_exe().unwrap_or_default();
+        let key = format!(
+            "{}\0{}\0{}\0{}\0{}",
+            env!("CARGO_PKG_VERSION"),
+            cargo_exe.display(),
+            manifest_path.display(),
+            offline,
+            features.join(","),
+        );
+        self.dir.join(format!("{:x}.json", md5::compute(key)))
+    }
+}
+
+/// Directories where Cargo discovers targets.
+const TARGET_DIRS: &[&str] = &["src", "src/bin", "examples", "tests", "benches"];
+
+/// Directories that contain the glob members of the workspace, e.g. `crates` for `crates/*`.
+fn member_glob_dirs(workspace_root: &Path) -> Vec<PathBuf> {
+    let manifest = cargo_util::paths::read(&workspace_root.join("Cargo.toml"))
+        .ok()
+        .and_then(|s| s.parse::<toml_edit::Document>().ok());
+    let members = manifest
+        .as_ref()
+        .and_then(|m| m["workspace"]["members"].as_array());
+    members
+        .into_iter()
+        .flat_map(|members| members.iter())
+        .flat_map(|member| member.as_str())
+        .filter(|member| member.contains(&['*', '?', '['][..]))
+        .map(|member| {
+            let dir = member
+                .split('/')
+                .take_while(|s| !s.contains(&['*', '?', '['][..]))
+                .collect::<PathBuf>();
+            workspace_root.join(dir)
+        })
+        .collect()
+}
+
+/// Hash of the content of a file or the entry names of a directory, or an empty string if the path
+/// does not exist.
+fn input_hash(path: &Path) -> String {
+    if path.is_dir() {
+        let mut names = match path.read_dir() {
+            Ok(entries) => entries
+                .flatten()
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .filter(|name| !name.starts_with('.'))
+                .collect::<Vec<_>>(),
+            Err(_) => return "".to_owned(),
+        };
+        names.sort();
+        return format!("{:x}", md5::compute(names.join("/")));
+    }
+    cargo_util::paths::read_bytes(path)
+        .map(|content| format!("{:x}", md5::compute(content)))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MetadataCache;
+    use crate::daemon;
+    use std::{cell::RefCell, fs, path::Path};
+
+    #[test]
+    fn invalidated_by_manifests_and_lockfile() {
+        let root = tempfile::tempdir().unwrap();
+        let root = &root.path().canonicalize().unwrap();
+        let write = |path: &str, content: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write(
+            "Cargo.toml",
+            "[package]\nname = \"bin\"\nversion = \"0.0.0\"\n\n\
+             [dependencies]\nlib = { path = \"lib\" }\n\n[workspace]\n",
+        );
+        write("src/main.rs", "fn main() {}\n");
+        write(
+            "lib/Cargo.toml",
+            "[package]\nname = \"lib\"\nversion = \"0.0.0\"\n",
+        );
+        write("lib/src/lib.rs", "");
+
+        let cache = MetadataCache::new(&root.join("cache"), None);
+        let manifest_path = &root.join("Cargo.toml");
+        let put = || {
+            let metadata =
+                crate::workspace::cargo_metadata(manifest_path, root, &[], true).unwrap();
+            cache.put(manifest_path, &[], true, &metadata).unwrap();
+        };
+        let get = || cache.get(manifest_path, &[], true);
+        let append = |path: &Path, content: &str| {
+            let mut s = fs::read_to_string(path).unwrap();
+            s += content;
+            fs::write(path, s).unwrap();
+        };
+
+        put();
+        assert!(get().is_some());
+        assert!(cache.get(manifest_path, &[], false).is_none());
+
+        append(&root.join("lib").join("Cargo.toml"), "\n");
+        assert!(get().is_none());
+        put();
+        assert!(get().is_some());
+
+        append(manifest_path, "\n");
+        assert!(get().is_none());
+        put();
+        assert!(get().is_some());
+
+        append(&root.join("Cargo.lock"), "\n");
+        assert!(get().is_none());
+    }
+
+    #[test]
+    fn invalidated_by_new_targets_and_members() {
+        let root = tempfile::tempdir().unwrap();
+        let root = &root.path().canonicalize().unwrap();
+        let write = |path: &str, content: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write("Cargo.toml", "[workspace]\nmembers = [\"crates/*\"]\n");
+        write(
+            "crates/a/Cargo.toml",
+            "[package]\nname = \"a\"\nversion = \"0.0.0\"\n",
+        );
+        write("crates/a/src/main.rs", "fn main() {}\n");
+
+        let cache = MetadataCache::new(&root.join("cache"), None);
+        let manifest_path = &root.join("Cargo.toml");
+        let put = || {
+            let metadata =
+                crate::workspace::cargo_metadata(manifest_path, root, &[], true).unwrap();
+            cache.put(manifest_path, &[], true, &metadata).unwrap();
+        };
+        let get = || cache.get(manifest_path, &[], true);
+
+        put();
+        assert!(get().is_some());
+        write("crates/a/src/.main.rs.swp", "");
+        assert!(get().is_some());
+
+        write("crates/a/src/bin/d.rs", "fn main() {}\n");
+        assert!(get().is_none());
+        put();
+        let bins = get().unwrap().packages[0]
+            .targets
+            .iter()
+            .map(|t| t.name.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(["a", "d"], *bins);
+
+        fs::remove_file(root.join("crates/a/src/bin/d.rs")).unwrap();
+        assert!(get().is_none());
+        put();
+
+        write(
+            "crates/b/Cargo.toml",
+            "[package]\nname = \"b\"\nversion = \"0.0.0\"\n",
+        );
+        write("crates/b/src/lib.rs", "");
+        assert!(get().is_none());
+        put();
+        assert_eq!(2, get().unwrap().packages.len());
+    }
+
+    #[test]
+    fn kept_in_memory_by_the_daemon() {
+        let root = tempfile::tempdir().unwrap();
+        let root = &root.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[package]\nname = \"bin\"\nversion = \"0.0.0\"\n",
+        )
+        .unwrap();
+        fs::write(root.join("src").join("main.rs"), "fn main() {}\n").unwrap();
+
+        let state = RefCell::new(daemon::State::default());
+        let cache = MetadataCache::new(&root.join("cache"), Some(&state));
+        let manifest_path = &root.join("Cargo.toml");
+        let metadata = crate::workspace::cargo_metadata(manifest_path, root, &[], true).unwrap();
+        cache.put(manifest_path, &[], true, &metadata).unwrap();
+
+        fs::remove_dir_all(root.join("cache")).unwrap();
+        assert!(cache.get(manifest_path, &[], true).is_some());
+        assert!(MetadataCache::new(&root.join("cache"), None)
+            .get(manifest_path, &[], true)
+            .is_none());
+
+        fs::write(root.join("src").join("lib.rs"), "").unwrap();
+        assert!(cache.get(manifest_path, &[], true).is_none());
+    }
+}
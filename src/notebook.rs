@@ -0,0 +1,114 @@
+use crate::{
+    rust::{self, CodeEdit},
+    shell::Shell,
+    workspace::TargetExt as _,
+    Remove,
+};
+use cargo_metadata as cm;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NotebookFormat {
+    Markdown,
+    Latex,
+}
+
+impl NotebookFormat {
+    pub(crate) const VARIANTS: &'static [&'static str] = &["markdown", "latex"];
+}
+
+impl FromStr for NotebookFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, &'static str> {
+        match s {
+            "markdown" => Ok(Self::Markdown),
+            "latex" => Ok(Self::Latex),
+            _ => Err(r#"expected "markdown", or "latex""#),
+        }
+    }
+}
+
+pub(crate) fn notebook(
+    metadata: &cm::Metadata,
+    cargo_equip_mod_name: &syn::Ident,
+    format: NotebookFormat,
+    remove: &[Remove],
+    shell: &mut Shell,
+) -> anyhow::Result<String> {
+    let mut libs = metadata
+        .packages
+        .iter()
+        .filter(|p| metadata.workspace_members.contains(&p.id))
+        .flat_map(|p| p.targets.iter().filter(|t| t.is_lib()).map(move |t| (p, t)))
+        .collect::<Vec<_>>();
+    libs.sort_by_key(|(_, t)| t.crate_name());
+
+    let mut doc = match format {
+        NotebookFormat::Markdown => "".to_owned(),
+        NotebookFormat::Latex => "\\documentclass{article}\n\
+                                  \\usepackage{listings}\n\
+                                  \\lstset{basicstyle=\\ttfamily\\footnotesize,breaklines=true}\n\
+                                  \\begin{document}\n"
+            .to_owned(),
+    };
+
+    for (package, krate) in libs {
+        shell.status(
+            "Reading",
+            format!("`{}` from `{}`", krate.crate_name(), package.id),
+        )?;
+
+        let mut edit = CodeEdit::new(cargo_equip_mod_name, &krate.src_path, || {
+            (krate.crate_name(), &package.id.repr)
+        })?;
+        if remove.contains(&Remove::HiddenModules) {
+            edit.remove_unreachable_hidden_modules(&[])?;
+        }
+        if remove.contains(&Remove::Docs) {
+            edit.erase_docs()?;
+        }
+        if remove.contains(&Remove::Comments) {
+            edit.erase_comments()?;
+        }
+        let code = edit.finish()?;
+
+        let crate_name = krate.crate_name();
+        match format {
+            NotebookFormat::Markdown => {
+                if !doc.is_empty() {
+                    doc += "\n";
+                }
+                doc += &format!("# {}\n", crate_name);
+            }
+            NotebookFormat::Latex => doc += &format!("\n\\section{{{}}}\n", escape(&crate_name)),
+        }
+        for (path, content) in rust::split_into_modules(&code)? {
+            let path = itertools::chain!([&crate_name], &path)
+                .map(|s| &**s)
+                .collect::<Vec<_>>()
+                .join("::");
+            match format {
+                NotebookFormat::Markdown => {
+                    doc += &format!("\n## `{}`\n\n```rust\n{}```\n", path, content);
+                }
+                NotebookFormat::Latex => {
+                    doc += &format!(
+                        "\n\\subsection{{\\texttt{{{}}}}}\n\n\\begin{{lstlisting}}\n{}\\end{{lstlisting}}\n",
+                        escape(&path),
+                        content,
+                    );
+                }
+            }
+        }
+    }
+
+    if format == NotebookFormat::Latex {
+        doc += "\n\\end{document}\n";
+    }
+    return Ok(doc);
+
+    fn escape(s: &str) -> String {
+        s.replace('_', "\\_")
+    }
+}
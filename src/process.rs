@@ -1,8 +1,35 @@
-use anyhow::Context as _;
-use cargo_util::ProcessError;
-use std::{env, fmt, io, path::PathBuf, process::Stdio};
+use crate::shell::Shell;
+use anyhow::{bail, Context as _};
+use cargo_util::{ProcessBuilder, ProcessError};
+use once_cell::sync::OnceCell;
+use std::{
+    env,
+    ffi::OsStr,
+    fmt, io,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+static CARGO_EXE: OnceCell<PathBuf> = OnceCell::new();
+
+/// `equip` can run more than once in a process (`bench-size`, `--server` and `daemon`), so setting
+/// the same path again is allowed.
+pub(crate) fn set_cargo_exe(cargo_exe: PathBuf) -> anyhow::Result<()> {
+    let current = CARGO_EXE.get_or_init(|| cargo_exe.clone());
+    if *current != cargo_exe {
+        bail!(
+            "`--cargo` is already `{}` in this process, not `{}`",
+            current.display(),
+            cargo_exe.display(),
+        );
+    }
+    Ok(())
+}
 
 pub(crate) fn cargo_exe() -> anyhow::Result<PathBuf> {
+    if let Some(cargo_exe) = CARGO_EXE.get() {
+        return Ok(cargo_exe.clone());
+    }
     env::var_os("CARGO")
         .with_context(|| {
             "missing `$CARGO`. run this program with `cargo equip`, not `cargo-equip equip`"
@@ -10,6 +37,43 @@ pub(crate) fn cargo_exe() -> anyhow::Result<PathBuf> {
         .map(Into::into)
 }
 
+/// Runs a hook command with `sh -c` (`cmd /C` on Windows).
+///
+/// The standard output of the command is redirected to the standard error so that it is not mixed
+/// with the output of cargo-equip.
+pub(crate) fn run_hook(
+    name: &str,
+    command: &str,
+    envs: &[(&str, &OsStr)],
+    cwd: &Path,
+    shell: &mut Shell,
+) -> anyhow::Result<()> {
+    let mut proc = if cfg!(windows) {
+        let mut proc = ProcessBuilder::new("cmd");
+        proc.arg("/C");
+        proc
+    } else {
+        let mut proc = ProcessBuilder::new("sh");
+        proc.arg("-c");
+        proc
+    };
+    proc.arg(command).cwd(cwd);
+    for (key, val) in envs {
+        proc.env(key, val);
+    }
+    shell.status("Running", format!("`{}` hook: {}", name, command))?;
+
+    let status = proc
+        .build_command()
+        .stdout(io::stderr())
+        .status()
+        .with_context(|| format!("could not execute the `{}` hook", name))?;
+    if !status.success() {
+        bail!("the `{}` hook failed ({})", name, status);
+    }
+    Ok(())
+}
+
 pub(crate) trait ProcessBuilderExt: fmt::Display {
     fn try_inspect(&mut self, f: impl FnOnce(&Self) -> io::Result<()>) -> io::Result<&mut Self> {
         f(self)?;
@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+
+const PREFIX: &str = "// cargo-equip-provenance: ";
+
+/// A comment line at the end of the output.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Provenance {
+    pub(crate) version: String,
+    pub(crate) args: Vec<String>,
+    pub(crate) options_hash: String,
+    pub(crate) crates: Vec<String>,
+}
+
+impl Provenance {
+    pub(crate) fn new(
+        args: impl IntoIterator<Item = String>,
+        crates: impl IntoIterator<Item = String>,
+    ) -> Self {
+        let args = strip_output_args(args);
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            options_hash: format!("{:x}", md5::compute(args.join("\0"))),
+            args,
+            crates: crates.into_iter().collect(),
+        }
+    }
+
+    pub(crate) fn read(code: &str) -> Option<Self> {
+        let line = code.lines().rev().find(|l| l.starts_with(PREFIX))?;
+        serde_json::from_str(&line[PREFIX.len()..]).ok()
+    }
+
+    /// Removes the comment line, which does not describe the code after `--update`.
+    pub(crate) fn strip_from(code: &str) -> String {
+        code.split_inclusive('\n')
+            .filter(|l| !l.starts_with(PREFIX))
+            .collect()
+    }
+
+    pub(crate) fn append_to(&self, mut code: String) -> serde_json::Result<String> {
+        if !code.is_empty() && !code.ends_with('\n') {
+            code += "\n";
+        }
+        code += PREFIX;
+        code += &serde_json::to_string(self)?;
+        code += "\n";
+        Ok(code)
+    }
+}
+
+/// Removes the arguments that only decide where and how the output is written.
+pub(crate) fn strip_output_args(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut args = args.into_iter();
+    let mut stripped = vec![];
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "-o" | "--output" => {
+                args.next();
+            }
+            "--diff" | "--check-diff" | "--backup" | "--report" | "--time" => {}
+            "--reproduce" | "--update" | "--snapshot-dir" | "--section-header" => {
+                args.next();
+            }
+            _ if arg.starts_with("--output=")
+                || arg.starts_with("-o")
+                || arg.starts_with("--update=")
+                || arg.starts_with("--snapshot-dir=")
+                || arg.starts_with("--section-header=") => {}
+            _ => stripped.push(arg),
+        }
+    }
+    stripped
+}
@@ -1,3 +1,4 @@
+use crate::workspace::TargetExt as _;
 use anyhow::{anyhow, bail, Context as _};
 use cargo_metadata as cm;
 use itertools::chain;
@@ -19,7 +20,7 @@ pub(crate) fn list_proc_macro_dylibs<P: FnMut(&cm::PackageId) -> bool>(
             cm::Message::CompilerArtifact(artifact) => Some(artifact),
             _ => None,
         })
-        .filter(|cm::Artifact { target, .. }| *target.kind == ["proc-macro".to_owned()])
+        .filter(|cm::Artifact { target, .. }| target.is_proc_macro())
         .filter(|cm::Artifact { package_id, .. }| filter(package_id))
         .flat_map(
             |cm::Artifact {
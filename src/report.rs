@@ -0,0 +1,122 @@
+use crate::shell::Shell;
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+/// Sizes and timings collected for `--report` and `--time`.
+#[derive(Default, Debug)]
+pub(crate) struct Report {
+    phases: Vec<(&'static str, Duration)>,
+    crates: Vec<(String, usize, usize)>,
+    pruning: (usize, usize),
+    minification: (usize, usize),
+}
+
+/// A phase in progress. It is also a `tracing` span for `$CARGO_EQUIP_LOG`.
+pub(crate) struct Started {
+    phase: &'static str,
+    instant: Instant,
+    _span: tracing::span::EnteredSpan,
+}
+
+impl Report {
+    pub(crate) fn start(phase: &'static str) -> Started {
+        Started {
+            phase,
+            instant: Instant::now(),
+            _span: tracing::info_span!("phase", name = phase).entered(),
+        }
+    }
+
+    pub(crate) fn finish(&mut self, started: Started) {
+        self.phase(started.phase, started.instant.elapsed());
+    }
+
+    pub(crate) fn time<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let started = Self::start(phase);
+        let ret = f();
+        self.finish(started);
+        ret
+    }
+
+    fn phase(&mut self, phase: &'static str, elapsed: Duration) {
+        if let Some((_, total)) = self.phases.iter_mut().find(|(p, _)| *p == phase) {
+            *total += elapsed;
+        } else {
+            self.phases.push((phase, elapsed));
+        }
+    }
+
+    pub(crate) fn krate(&mut self, name: impl Into<String>, contents: &[&str]) {
+        let lines = contents.iter().map(|s| s.lines().count()).sum();
+        let bytes = contents.iter().map(|s| s.len()).sum();
+        self.crates.push((name.into(), lines, bytes));
+    }
+
+    pub(crate) fn pruned(&mut self, before: usize, after: usize) {
+        self.pruning.0 += before;
+        self.pruning.1 += after;
+    }
+
+    pub(crate) fn minified(&mut self, before: usize, after: usize) {
+        self.minification.0 += before;
+        self.minification.1 += after;
+    }
+
+    pub(crate) fn print(&self, output: usize, shell: &mut Shell) -> io::Result<()> {
+        let err = shell.err();
+
+        writeln!(err)?;
+        let width = self
+            .crates
+            .iter()
+            .map(|(name, _, _)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max("crate".len());
+        writeln!(err, "{:<width$} {:>8} {:>10}", "crate", "lines", "bytes")?;
+        for (name, lines, bytes) in &self.crates {
+            writeln!(err, "{:<width$} {:>8} {:>10}", name, lines, bytes)?;
+        }
+
+        writeln!(err)?;
+        for (title, (before, after)) in [
+            ("pruning", self.pruning),
+            ("minification", self.minification),
+        ] {
+            if before > 0 {
+                writeln!(
+                    err,
+                    "{:<13} {:>10} -> {:>10} bytes ({} bytes saved)",
+                    title,
+                    before,
+                    after,
+                    before.saturating_sub(after),
+                )?;
+            }
+        }
+        writeln!(err, "{:<13} {:>10} bytes", "output", output)?;
+
+        self.print_time(shell)
+    }
+
+    pub(crate) fn print_time(&self, shell: &mut Shell) -> io::Result<()> {
+        let err = shell.err();
+
+        writeln!(err)?;
+        for (phase, elapsed) in &self.phases {
+            writeln!(err, "{:<13} {:>10.3}s", phase, elapsed.as_secs_f64())?;
+        }
+        writeln!(
+            err,
+            "{:<13} {:>10.3}s",
+            "total",
+            self.phases
+                .iter()
+                .map(|(_, elapsed)| *elapsed)
+                .sum::<Duration>()
+                .as_secs_f64(),
+        )
+    }
+}
@@ -4,12 +4,12 @@ use camino::{Utf8Path, Utf8PathBuf};
 use fixedbitset::FixedBitSet;
 use if_chain::if_chain;
 use itertools::Itertools as _;
-use maplit::btreemap;
-use proc_macro2::{LineColumn, Span, TokenStream, TokenTree};
+use maplit::{btreemap, btreeset};
+use proc_macro2::{LineColumn, Spacing, Span, TokenStream, TokenTree};
 use quote::{quote, ToTokens};
 use std::{
     borrow::Cow,
-    collections::{BTreeMap, BTreeSet, VecDeque},
+    collections::{btree_map, BTreeMap, BTreeSet, VecDeque},
     env, mem,
     ops::Range,
     str,
@@ -29,18 +29,18 @@ use syn::{
     ForeignItemMacro, ForeignItemStatic, ForeignItemType, Ident, ImplItemConst, ImplItemMacro,
     ImplItemMethod, ImplItemType, Item, ItemConst, ItemEnum, ItemExternCrate, ItemFn,
     ItemForeignMod, ItemImpl, ItemMacro, ItemMacro2, ItemMod, ItemStatic, ItemStruct, ItemTrait,
-    ItemTraitAlias, ItemType, ItemUnion, ItemUse, LifetimeDef, Lit, LitStr, Local, Macro, Meta,
-    MetaList, MetaNameValue, NestedMeta, PatBox, PatIdent, PatLit, PatMacro, PatOr, PatPath,
-    PatRange, PatReference, PatRest, PatSlice, PatStruct, PatTuple, PatTupleStruct, PatType,
-    PatWild, PathSegment, Receiver, Token, TraitItemConst, TraitItemMacro, TraitItemMethod,
-    TraitItemType, TypeParam, UseGroup, UseName, UsePath, UseRename, UseTree, Variadic, Variant,
-    VisRestricted,
+    ItemTraitAlias, ItemType, ItemUnion, ItemUse, LifetimeDef, Lit, LitByteStr, LitStr, Local,
+    Macro, Meta, MetaList, MetaNameValue, NestedMeta, PatBox, PatIdent, PatLit, PatMacro, PatOr,
+    PatPath, PatRange, PatReference, PatRest, PatSlice, PatStruct, PatTuple, PatTupleStruct,
+    PatType, PatWild, PathSegment, Receiver, Stmt, Token, TraitItemConst, TraitItemMacro,
+    TraitItemMethod, TraitItemType, TypeParam, UseGroup, UseName, UsePath, UseRename, UseTree,
+    Variadic, Variant, VisRestricted,
 };
 
-pub(crate) fn find_skip_attribute(code: &str) -> anyhow::Result<bool> {
+pub(crate) fn find_skip_attribute(path: &str, code: &str) -> anyhow::Result<bool> {
     let syn::File { attrs, .. } = syn::parse_file(code)
-        .map_err(|e| anyhow!("{:?}", e))
-        .with_context(|| "could not parse the code")?;
+        .map_err(|e| anyhow!("{}", render_syn_error(path, code, &e)))
+        .with_context(|| format!("could not parse `{}`", path))?;
 
     Ok(attrs
         .iter()
@@ -139,15 +139,125 @@ fn replace_ranges(code: &str, replacements: BTreeMap<(LineColumn, LineColumn), S
     ret
 }
 
+/// Returns the pseudo extern crate name of the crate if the library only consists of
+/// `pub use another_bundled_crate::*;`.
+pub(crate) fn facade_target(
+    cargo_equip_mod_name: &syn::Ident,
+    crate_mod_content: &str,
+    macro_mod_content: &str,
+    prelude_mod_content: &str,
+) -> anyhow::Result<Option<String>> {
+    if !macro_mod_content.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let items = parse_file(crate_mod_content)?
+        .items
+        .into_iter()
+        .filter(|item| {
+            !matches!(
+                item,
+                Item::Use(ItemUse {
+                    vis: syn::Visibility::Inherited,
+                    ..
+                })
+            )
+        })
+        .collect::<Vec<_>>();
+    let extern_crate_name = match &*items {
+        [Item::Use(ItemUse {
+            vis: syn::Visibility::Public(_),
+            tree: UseTree::Path(UsePath { ident, tree, .. }),
+            ..
+        })] if matches!(**tree, UseTree::Glob(_)) => ident.to_string(),
+        _ => return Ok(None),
+    };
+
+    for item in parse_file(prelude_mod_content)?.items {
+        if let Item::Use(ItemUse { tree, .. }) = item {
+            let mut segments = vec![];
+            let mut tree = &tree;
+            while let UseTree::Path(UsePath {
+                ident, tree: next, ..
+            }) = tree
+            {
+                segments.push(ident.to_string());
+                tree = next;
+            }
+            if let UseTree::Rename(UseRename { ident, rename, .. }) = tree {
+                if *rename == extern_crate_name
+                    && segments
+                        == [
+                            "crate".to_owned(),
+                            cargo_equip_mod_name.to_string(),
+                            "crates".to_owned(),
+                        ]
+                {
+                    return Ok(Some(ident.to_string()));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Extracts an embedded manifest from a single-file package, either in a frontmatter
+/// (`---cargo` .. `---`) or in a ```` ```cargo ```` block in the leading `//!` comments.
+///
+/// Returns the manifest and the code without the frontmatter.
+pub(crate) fn extract_embedded_manifest(code: &str) -> Option<(String, String)> {
+    let lines = code.split_inclusive('\n').collect::<Vec<_>>();
+    let start = usize::from(
+        lines
+            .first()
+            .map_or(false, |l| l.starts_with("#!") && !l.starts_with("#![")),
+    );
+
+    // Frontmatter
+    let opening = lines.get(start)?.trim_end();
+    let dashes = opening.len() - opening.trim_start_matches('-').len();
+    if dashes >= 3 && matches!(opening[dashes..].trim(), "" | "cargo") {
+        let end = (start + 1..lines.len()).find(|&i| lines[i].trim_end() == &opening[..dashes])?;
+        let manifest = lines[start + 1..end].concat();
+        let code = itertools::chain!(&lines[..start], &lines[end + 1..])
+            .copied()
+            .collect();
+        return Some((manifest, code));
+    }
+
+    // `//! ```cargo`
+    let doc = lines[start..]
+        .iter()
+        .map(|l| l.trim())
+        .take_while(|l| l.starts_with("//!"))
+        .map(|l| {
+            l["//!".len()..]
+                .strip_prefix(' ')
+                .unwrap_or(&l["//!".len()..])
+        })
+        .collect::<Vec<_>>();
+    let opening = doc.iter().position(|l| l.trim() == "```cargo")?;
+    let closing = opening + 1 + doc[opening + 1..].iter().position(|l| l.trim() == "```")?;
+    let manifest = doc[opening + 1..closing]
+        .iter()
+        .map(|l| format!("{}\n", l))
+        .collect();
+    Some((manifest, code.to_owned()))
+}
+
+/// Inserts `use`s of the prelude into the modules of the main crate, except for the crate root
+/// unless `at_crate_root` is `true`.
 pub(crate) fn insert_prelude_for_main_crate(
     code: &str,
     cargo_equip_mod_name: &Ident,
+    at_crate_root: bool,
 ) -> syn::Result<String> {
     let file = &syn::parse_file(code)?;
     let mut replacements = btreemap!();
     Visitor {
         replacements: &mut replacements,
         cargo_equip_mod_name,
+        at_crate_root,
     }
     .visit_file(file);
     return Ok(replace_ranges(code, replacements));
@@ -155,11 +265,12 @@ pub(crate) fn insert_prelude_for_main_crate(
     struct Visitor<'a> {
         replacements: &'a mut BTreeMap<(LineColumn, LineColumn), String>,
         cargo_equip_mod_name: &'a Ident,
+        at_crate_root: bool,
     }
 
     impl Visitor<'_> {
         fn visit_items(&mut self, items: &[Item], crate_root: bool) {
-            if let Some(first) = items.first() {
+            if let Some(first) = items.first().filter(|_| !crate_root || self.at_crate_root) {
                 let pos = first.span().start();
                 self.replacements.insert(
                     (pos, pos),
@@ -191,6 +302,290 @@ pub(crate) fn insert_prelude_for_main_crate(
     }
 }
 
+/// Removes `use` items whose every imported path is already imported in the same module.
+pub(crate) fn dedup_uses(code: &str) -> syn::Result<String> {
+    let file = &syn::parse_file(code)?;
+    let mut replacements = btreemap!();
+    visit_items(&file.items, &mut replacements);
+    return Ok(replace_ranges(code, replacements));
+
+    fn visit_items(items: &[Item], replacements: &mut BTreeMap<(LineColumn, LineColumn), String>) {
+        let mut imported = BTreeSet::new();
+        for item in items {
+            match item {
+                Item::Use(item_use) => {
+                    let ItemUse {
+                        attrs,
+                        vis,
+                        leading_colon,
+                        tree,
+                        ..
+                    } = item_use;
+                    let prefix = format!(
+                        "{} {} {}",
+                        quote!(#(#attrs)*),
+                        vis.to_token_stream(),
+                        leading_colon.to_token_stream(),
+                    );
+                    let mut leaves = vec![];
+                    flatten_use_tree(tree, &mut "".to_owned(), &mut leaves);
+                    let leaves = leaves
+                        .into_iter()
+                        .map(|leaf| format!("{}{}", prefix, leaf))
+                        .collect::<Vec<_>>();
+                    if leaves.iter().all(|leaf| imported.contains(leaf)) {
+                        replacements.insert(
+                            (item_use.span().start(), item_use.span().end()),
+                            "".to_owned(),
+                        );
+                    } else {
+                        imported.extend(leaves);
+                    }
+                }
+                Item::Mod(ItemMod {
+                    content: Some((_, items)),
+                    ..
+                }) => visit_items(items, replacements),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn flatten_use_tree(tree: &UseTree, prefix: &mut String, acc: &mut Vec<String>) {
+    match tree {
+        UseTree::Path(UsePath { ident, tree, .. }) => {
+            let len = prefix.len();
+            *prefix += &format!("{}::", ident);
+            flatten_use_tree(tree, prefix, acc);
+            prefix.truncate(len);
+        }
+        UseTree::Name(UseName { ident }) => acc.push(format!("{}{}", prefix, ident)),
+        UseTree::Rename(UseRename { ident, rename, .. }) => {
+            acc.push(format!("{}{} as {}", prefix, ident, rename));
+        }
+        UseTree::Glob(_) => acc.push(format!("{}*", prefix)),
+        UseTree::Group(UseGroup { items, .. }) => {
+            for tree in items {
+                flatten_use_tree(tree, prefix, acc);
+            }
+        }
+    }
+}
+
+/// Lists the paths in the code, including the ones imported with `use`, as segments.
+pub(crate) fn collect_paths(code: &str) -> syn::Result<Vec<Vec<String>>> {
+    let file = &syn::parse_file(code)?;
+    let mut visitor = Visitor { paths: vec![] };
+    visitor.visit_file(file);
+    return Ok(visitor.paths);
+
+    struct Visitor {
+        paths: Vec<Vec<String>>,
+    }
+
+    impl Visit<'_> for Visitor {
+        fn visit_item_use(&mut self, i: &ItemUse) {
+            let mut leaves = vec![];
+            flatten_use_tree(&i.tree, &mut "".to_owned(), &mut leaves);
+            for leaf in leaves {
+                let leaf = leaf.split(" as ").next().unwrap_or_default();
+                self.paths.push(
+                    leaf.split("::")
+                        .filter(|s| *s != "*" && *s != "self")
+                        .map(ToOwned::to_owned)
+                        .collect(),
+                );
+            }
+        }
+
+        fn visit_path(&mut self, i: &syn::Path) {
+            self.paths
+                .push(i.segments.iter().map(|s| s.ident.to_string()).collect());
+            visit::visit_path(self, i);
+        }
+
+        fn visit_macro(&mut self, i: &Macro) {
+            self.visit_path(&i.path);
+            self.visit_tokens(i.tokens.clone());
+        }
+    }
+
+    impl Visitor {
+        /// Finds `a::b::c`s in macro arguments.
+        fn visit_tokens(&mut self, tokens: TokenStream) {
+            let tts = tokens.into_iter().collect::<Vec<_>>();
+            let mut i = 0;
+            while i < tts.len() {
+                match &tts[i] {
+                    TokenTree::Group(group) => self.visit_tokens(group.stream()),
+                    TokenTree::Ident(ident) => {
+                        let mut path = vec![ident.to_string()];
+                        while let Some(
+                            [TokenTree::Punct(p1), TokenTree::Punct(p2), TokenTree::Ident(ident)],
+                        ) = tts.get(i + 1..i + 4)
+                        {
+                            if !(p1.as_char() == ':'
+                                && p1.spacing() == Spacing::Joint
+                                && p2.as_char() == ':')
+                            {
+                                break;
+                            }
+                            path.push(ident.to_string());
+                            i += 3;
+                        }
+                        if path.len() > 1 {
+                            self.paths.push(path);
+                        }
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Finds uses of APIs that are banned or useless on judges: file system, environment variables,
+/// network, processes, and threads.
+pub(crate) fn find_io_apis(code: &str) -> syn::Result<Vec<(LineColumn, String, String)>> {
+    const APIS: &[&str] = &[
+        "std::env",
+        "std::fs",
+        "std::net",
+        "std::os",
+        "std::process::Command",
+        "std::thread",
+    ];
+
+    let file = &syn::parse_file(code)?;
+    let mut visitor = Visitor {
+        mod_path: vec!["crate".to_owned()],
+        found: vec![],
+    };
+    visitor.visit_file(file);
+    return Ok(visitor.found);
+
+    struct Visitor {
+        mod_path: Vec<String>,
+        found: Vec<(LineColumn, String, String)>,
+    }
+
+    impl Visitor {
+        fn check(&mut self, path: &str, pos: LineColumn) {
+            let path = path.trim_start_matches("::");
+            if let Some(api) = APIS
+                .iter()
+                .find(|api| path == **api || path.starts_with(&format!("{}::", api)))
+            {
+                self.found
+                    .push((pos, self.mod_path.join("::"), (*api).to_owned()));
+            }
+        }
+    }
+
+    impl Visit<'_> for Visitor {
+        fn visit_item_mod(&mut self, i: &ItemMod) {
+            self.mod_path.push(i.ident.to_string());
+            visit::visit_item_mod(self, i);
+            self.mod_path.pop();
+        }
+
+        fn visit_item_use(&mut self, i: &ItemUse) {
+            let mut leaves = vec![];
+            flatten_use_tree(&i.tree, &mut "".to_owned(), &mut leaves);
+            for leaf in leaves {
+                self.check(&leaf, i.span().start());
+            }
+        }
+
+        fn visit_path(&mut self, i: &syn::Path) {
+            let path = i.segments.iter().map(|s| s.ident.to_string()).join("::");
+            self.check(&path, i.span().start());
+            visit::visit_path(self, i);
+        }
+    }
+}
+
+/// Finds `::$name`s and `extern crate $name`s.
+pub(crate) fn find_extern_crate_refs(code: &str) -> syn::Result<Vec<(LineColumn, String)>> {
+    let file = &syn::parse_file(code)?;
+    let mut visitor = Visitor { found: vec![] };
+    visitor.visit_file(file);
+    return Ok(visitor.found);
+
+    struct Visitor {
+        found: Vec<(LineColumn, String)>,
+    }
+
+    impl Visit<'_> for Visitor {
+        fn visit_item_extern_crate(&mut self, i: &ItemExternCrate) {
+            if i.ident != "self" {
+                self.found.push((i.span().start(), i.ident.to_string()));
+            }
+        }
+
+        fn visit_item_use(&mut self, i: &ItemUse) {
+            if i.leading_colon.is_some() {
+                let mut leaves = vec![];
+                flatten_use_tree(&i.tree, &mut "".to_owned(), &mut leaves);
+                for name in leaves
+                    .iter()
+                    .flat_map(|l| l.split([':', ' ']).next())
+                    .unique()
+                {
+                    self.found.push((i.span().start(), name.to_owned()));
+                }
+            }
+        }
+
+        fn visit_path(&mut self, i: &syn::Path) {
+            if_chain! {
+                if i.leading_colon.is_some();
+                if let Some(first) = i.segments.first();
+                then {
+                    self.found.push((i.span().start(), first.ident.to_string()));
+                }
+            }
+            visit::visit_path(self, i);
+        }
+    }
+}
+
+/// Finds calls of the macros, including ones in the arguments of other macros.
+pub(crate) fn find_macro_calls(
+    code: &str,
+    names: &[String],
+) -> anyhow::Result<Vec<(LineColumn, String)>> {
+    let tokens = code
+        .parse::<TokenStream>()
+        .map_err(|e| anyhow!("{:?}", e))
+        .with_context(|| "could not tokenize the output")?;
+    let mut found = vec![];
+    visit(tokens, names, &mut found);
+    return Ok(found);
+
+    fn visit(tokens: TokenStream, names: &[String], found: &mut Vec<(LineColumn, String)>) {
+        let tts = tokens.into_iter().collect::<Vec<_>>();
+        for (i, tt) in tts.iter().enumerate() {
+            match tt {
+                TokenTree::Ident(ident) => {
+                    if_chain! {
+                        if let Some(TokenTree::Punct(punct)) = tts.get(i + 1);
+                        if punct.as_char() == '!';
+                        if names.iter().any(|n| ident == n);
+                        then {
+                            found.push((ident.span().start(), ident.to_string()));
+                        }
+                    }
+                }
+                TokenTree::Group(group) => visit(group.stream(), names, found),
+                _ => {}
+            }
+        }
+    }
+}
+
 pub(crate) fn allow_unused_imports_for_seemingly_proc_macros(
     code: &str,
     mut seemingly_proc_macro: impl FnMut(&str, &str) -> bool,
@@ -264,6 +659,133 @@ pub(crate) fn allow_unused_imports_for_seemingly_proc_macros(
     }
 }
 
+/// `name`, `core::name`, `std::name`, `::core::name`, or `::std::name`.
+/// Evaluates a `cfg` predicate. `None` means that it depends on the environment of the judge.
+fn eval_cfg(
+    expr: &cfg_expr::Expression,
+    features: &[String],
+    target: Option<&cfg_expr::targets::TargetInfo>,
+) -> Option<bool> {
+    expr.eval(|pred| match pred {
+        cfg_expr::Predicate::Test | cfg_expr::Predicate::ProcMacro => Some(false),
+        cfg_expr::Predicate::Flag("cargo_equip") => Some(true),
+        cfg_expr::Predicate::Feature(feature) => Some(features.contains(&(*feature).to_owned())),
+        cfg_expr::Predicate::Target(pred) => target.map(|target| pred.matches(target)),
+        _ => None,
+    })
+}
+
+fn is_builtin_macro(path: &syn::Path, name: &str) -> bool {
+    let segments = path
+        .segments
+        .iter()
+        .map(|PathSegment { ident, arguments }| arguments.is_empty().then(|| ident.to_string()))
+        .collect::<Option<Vec<_>>>();
+    match segments.as_deref() {
+        Some([ident]) => path.leading_colon.is_none() && ident == name,
+        Some([krate, ident]) => (krate == "core" || krate == "std") && ident == name,
+        _ => false,
+    }
+}
+
+/// `include_str!("..")` and `include_bytes!("..")`.
+fn visit_asset_includes(
+    file: &syn::File,
+    mut f: impl FnMut((LineColumn, LineColumn), LitStr, bool),
+) {
+    visit_builtin_macro_calls(
+        file,
+        &["include_str", "include_bytes"],
+        |range, name, tokens| {
+            if let Ok(lit) = syn::parse2(tokens.clone()) {
+                f(range, lit, name == "include_bytes");
+            }
+        },
+    );
+}
+
+/// Calls of the builtin macros, including ones in arguments of other macros (e.g.
+/// `println!("{}", env!(".."))`).
+fn visit_builtin_macro_calls(
+    file: &syn::File,
+    names: &[&str],
+    mut f: impl FnMut((LineColumn, LineColumn), &str, &TokenStream),
+) {
+    return Visitor(names, &mut f).visit_file(file);
+
+    struct Visitor<'a, F>(&'a [&'a str], F);
+
+    impl<F: FnMut((LineColumn, LineColumn), &str, &TokenStream)> Visit<'_> for Visitor<'_, F> {
+        fn visit_macro(&mut self, i: &Macro) {
+            if !self.on_macro((i.span().start(), i.span().end()), &i.path, &i.tokens) {
+                self.visit_token_stream(i.tokens.clone());
+            }
+        }
+    }
+
+    impl<F: FnMut((LineColumn, LineColumn), &str, &TokenStream)> Visitor<'_, F> {
+        fn on_macro(
+            &mut self,
+            range: (LineColumn, LineColumn),
+            path: &syn::Path,
+            tokens: &TokenStream,
+        ) -> bool {
+            if let Some(name) = self.0.iter().find(|name| is_builtin_macro(path, name)) {
+                (self.1)(range, name, tokens);
+                true
+            } else {
+                false
+            }
+        }
+
+        fn visit_token_stream(&mut self, tokens: TokenStream) {
+            let tts = tokens.into_iter().collect::<Vec<_>>();
+            let is_colon2 = |j: usize| {
+                matches!(
+                    tts.get(j..j + 2),
+                    Some([TokenTree::Punct(p1), TokenTree::Punct(p2)])
+                        if p1.as_char() == ':' && p1.spacing() == Spacing::Joint && p2.as_char() == ':'
+                )
+            };
+
+            let mut i = 0;
+            while i < tts.len() {
+                let mut j = i;
+                if is_colon2(j) {
+                    j += 2;
+                }
+                let mut is_path = false;
+                while let Some(TokenTree::Ident(_)) = tts.get(j) {
+                    is_path = true;
+                    j += 1;
+                    if !is_colon2(j) {
+                        break;
+                    }
+                    j += 2;
+                }
+                if_chain! {
+                    if is_path;
+                    if let Some([TokenTree::Punct(bang), TokenTree::Group(group)]) = tts.get(j..j + 2);
+                    if bang.as_char() == '!';
+                    if let Ok(path) = syn::parse2(tts[i..j].iter().cloned().collect());
+                    then {
+                        let range = (tts[i].span().start(), group.span().end());
+                        if !self.on_macro(range, &path, &group.stream()) {
+                            self.visit_token_stream(group.stream());
+                        }
+                        i = j + 2;
+                        continue;
+                    }
+                }
+                if let TokenTree::Group(group) = &tts[i] {
+                    self.visit_token_stream(group.stream());
+                }
+                i += 1;
+            }
+        }
+    }
+}
+
 fn set_span(mask: &mut [FixedBitSet], span: Span, p: bool) {
     let i1 = span.start().line - 1;
     if span.start().line == span.end().line {
@@ -282,100 +804,614 @@ fn set_span(mask: &mut [FixedBitSet], span: Span, p: bool) {
     }
 }
 
-pub(crate) fn parse_file(code: &str) -> anyhow::Result<syn::File> {
-    syn::parse_file(code)
-        .map_err(|e| anyhow!("{}", e))
-        .with_context(|| "broke the code during modification")
+/// Inserts `#![feature(..)]` for the features that are not enabled in the root module yet.
+pub(crate) fn insert_features(code: &str, features: &BTreeSet<String>) -> anyhow::Result<String> {
+    let enabled = parse_file(code)?
+        .attrs
+        .iter()
+        .filter(|a| matches!(a.style, AttrStyle::Inner(_)))
+        .flat_map(|a| match a.parse_meta() {
+            Ok(Meta::List(MetaList { path, nested, .. })) if path.is_ident("feature") => {
+                nested.into_iter().collect()
+            }
+            _ => vec![],
+        })
+        .flat_map(|meta| match meta {
+            NestedMeta::Meta(Meta::Path(path)) => Some(path.to_token_stream().to_string()),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+
+    let features = features.difference(&enabled).collect::<Vec<_>>();
+    if features.is_empty() {
+        return Ok(code.to_owned());
+    }
+    Ok(format!(
+        "#![feature({})]\n{}",
+        features.iter().format(", "),
+        code,
+    ))
 }
 
-pub(crate) fn process_bin<'cm>(
-    cargo_equip_mod_name: &Ident,
-    src_path: &Utf8Path,
-    proc_macro_expander: Option<&mut ProcMacroExpander<'_>>,
-    translate_extern_crate_name: impl FnMut(&str) -> Option<String>,
-    is_lib_to_bundle: impl FnMut(&str) -> bool,
-    context: impl FnOnce() -> (String, &'cm str),
-) -> anyhow::Result<String> {
-    let mut edit = CodeEdit::new(cargo_equip_mod_name, src_path, context)?;
-    if let Some(proc_macro_expander) = proc_macro_expander {
-        edit.expand_proc_macros(proc_macro_expander)?;
+/// Expressions using APIs stabilized in each version of rustc.
+const RUSTC_PROBES: &[((u64, u64), &str)] = &[
+    ((1, 36), "std::mem::MaybeUninit::<u8>::uninit()"),
+    ((1, 40), "Some(Some(0u8)).flatten()"),
+    ((1, 50), "true.then(|| ())"),
+    ((1, 53), "u32::BITS"),
+    ((1, 62), "true.then_some(())"),
+    ((1, 66), "std::hint::black_box(())"),
+    ((1, 70), "Some(0u8).is_some_and(|_| true)"),
+    ((1, 80), "std::sync::LazyLock::new(|| ())"),
+];
+
+/// Generates a function that compiles only with rustc `version` or later.
+///
+/// The function uses an API stabilized in the latest known version not newer than `version`, which
+/// is returned with it.
+pub(crate) fn min_rustc_probe(version: &semver::Version) -> Option<((u64, u64), String)> {
+    let (probe_version, expr) = RUSTC_PROBES
+        .iter()
+        .rev()
+        .find(|(v, _)| *v <= (version.major, version.minor))?;
+    let (major, minor) = probe_version;
+    let code = format!(
+        "/// Fails to compile with rustc older than {major}.{minor}.\n\
+         #[allow(dead_code)]\n\
+         fn __cargo_equip_requires_rustc_{major}_{minor}() {{\n    let _ = {expr};\n}}\n",
+        major = major,
+        minor = minor,
+        expr = expr,
+    );
+    Some((*probe_version, code))
+}
+
+/// Inserts `item` after the inner attributes at the crate root.
+pub(crate) fn insert_after_inner_attrs(code: &str, item: &str) -> anyhow::Result<String> {
+    let end = parse_file(code)?
+        .attrs
+        .iter()
+        .filter(|a| matches!(a.style, AttrStyle::Inner(_)))
+        .map(|a| a.span().end())
+        .max();
+    let pos = match end {
+        Some(LineColumn { line, column }) => {
+            let start = code
+                .match_indices('\n')
+                .nth(line.wrapping_sub(2))
+                .map_or(0, |(i, _)| i + 1);
+            let line = code[start..].lines().next().unwrap_or_default();
+            let pos = start
+                + line
+                    .char_indices()
+                    .nth(column)
+                    .map_or(line.len(), |(i, _)| i);
+            pos + code[pos..].find('\n').map_or(code.len() - pos, |i| i + 1)
+        }
+        None => 0,
+    };
+    Ok(format!("{}{}\n{}", &code[..pos], item, &code[pos..]))
+}
+
+/// Makes `fn main` at the crate root `pub(crate)`, so that a packet can call it from outside the
+/// module of the bin. Also returns whether it returns a value.
+pub(crate) fn publish_main(code: &str) -> anyhow::Result<(String, bool)> {
+    let file = parse_file(code)?;
+    let ItemFn { vis, sig, .. } = file
+        .items
+        .iter()
+        .find_map(|item| match item {
+            Item::Fn(item_fn) if item_fn.sig.ident == "main" => Some(item_fn),
+            _ => None,
+        })
+        .with_context(|| "could not find `fn main` at the crate root")?;
+    let returns_value = !matches!(sig.output, syn::ReturnType::Default);
+    if !matches!(vis, syn::Visibility::Inherited) {
+        return Ok((code.to_owned(), returns_value));
     }
-    edit.translate_extern_crate_paths(translate_extern_crate_name)?;
-    edit.process_extern_crate_in_bin(is_lib_to_bundle)?;
-    edit.finish()
+    let pos = sig.span().start();
+    let code = replace_ranges(code, btreemap!((pos, pos) => "pub(crate) ".to_owned()));
+    Ok((code, returns_value))
 }
 
-pub(crate) struct CodeEdit<'opt> {
-    cargo_equip_mod_name: &'opt Ident,
-    has_local_inner_macros_attr: bool,
-    string: String,
-    file: syn::File,
-    replacements: BTreeMap<(LineColumn, LineColumn), String>,
+/// Renders `err` with the line it points to and a caret under the span.
+fn render_syn_error(path: &str, code: &str, err: &syn::Error) -> String {
+    let (start, end) = (err.span().start(), err.span().end());
+    let line = match code.lines().nth(start.line.wrapping_sub(1)) {
+        Some(line) => line,
+        None => return err.to_string(),
+    };
+    let padding = line
+        .chars()
+        .take(start.column)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    let carets = if end.line == start.line && end.column > start.column {
+        end.column - start.column
+    } else {
+        1
+    };
+    let gutter = " ".repeat(start.line.to_string().len());
+    format!(
+        "{}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+        err,
+        gutter,
+        path,
+        start.line,
+        start.column + 1,
+        gutter,
+        start.line,
+        line,
+        gutter,
+        padding,
+        "^".repeat(carets),
+    )
 }
 
-impl<'opt> CodeEdit<'opt> {
-    pub(crate) fn new<'cm>(
-        cargo_equip_mod_name: &'opt Ident,
-        src_path: &Utf8Path,
-        err_context: impl FnOnce() -> (String, &'cm str),
-    ) -> anyhow::Result<Self> {
-        return (|| {
-            Self::from_code(cargo_equip_mod_name, &expand_mods(src_path, 0)?)
-                .map_err(anyhow::Error::from)
-        })()
-        .with_context(|| {
-            let (crate_name, package_id) = err_context();
-            format!("could not expand `{}` from `{}`", crate_name, package_id)
-        });
+pub(crate) fn region_begin(name: &str) -> String {
+    format!("// === cargo-equip: begin {} ===", name)
+}
 
-        fn expand_mods(src_path: &Utf8Path, depth: usize) -> anyhow::Result<String> {
-            let content = cargo_util::paths::read(src_path.as_ref())?;
+pub(crate) fn region_end(name: &str) -> String {
+    format!("// === cargo-equip: end {} ===", name)
+}
 
-            let syn::File { items, .. } = syn::parse_file(&content)
-                .map_err(|e| anyhow!("{:?}", e))
-                .with_context(|| format!("could not parse `{}`", src_path))?;
+/// Replaces the regions between `cargo-equip` markers in `old` with the ones in `new`.
+pub(crate) fn update_regions(old: &str, new: &str) -> anyhow::Result<String> {
+    let old_regions = find_regions(old)?;
+    let new_regions = find_regions(new)?;
 
-            let replacements = items
-                .into_iter()
-                .flat_map(|item| match item {
-                    Item::Mod(ItemMod {
-                        attrs,
-                        ident,
-                        content: None,
-                        semi,
-                        ..
-                    }) => Some((attrs, ident, semi)),
-                    _ => None,
-                })
-                .map(|(attrs, ident, semi)| {
-                    let paths = if let Some(path) = attrs
-                        .iter()
-                        .flat_map(Attribute::parse_meta)
-                        .flat_map(|meta| match meta {
-                            Meta::NameValue(name_value) => Some(name_value),
-                            _ => None,
-                        })
-                        .filter(|MetaNameValue { path, .. }| {
-                            matches!(path.get_ident(), Some(i) if i == "path")
-                        })
-                        .find_map(|MetaNameValue { lit, .. }| match lit {
-                            Lit::Str(s) => Some(s.value()),
-                            _ => None,
-                        }) {
-                        vec![src_path.with_file_name("").join(path)]
-                    } else if depth == 0 || src_path.file_name() == Some("mod.rs") {
-                        vec![
-                            src_path
-                                .with_file_name(&ident.to_string())
-                                .with_extension("rs"),
-                            src_path.with_file_name(&ident.to_string()).join("mod.rs"),
-                        ]
-                    } else {
-                        vec![
-                            src_path
-                                .with_extension("")
-                                .with_file_name(&ident.to_string())
-                                .with_extension("rs"),
+    if old_regions.is_empty() {
+        bail!("no `cargo-equip` markers found");
+    }
+    if names(&old_regions) != names(&new_regions) {
+        bail!(
+            "the bundled crates have changed ({} -> {}). bundle the code again without `--update`",
+            names(&old_regions).iter().format(", "),
+            names(&new_regions).iter().format(", "),
+        );
+    }
+
+    let mut updated = "".to_owned();
+    let mut pos = 0;
+    for ((_, old_range), (_, new_range)) in old_regions.into_iter().zip(new_regions) {
+        updated += &old[pos..old_range.start];
+        updated += &new[new_range];
+        pos = old_range.end;
+    }
+    updated += &old[pos..];
+    return Ok(updated);
+
+    fn names<'a>(regions: &[(&'a str, Range<usize>)]) -> Vec<&'a str> {
+        regions.iter().map(|(name, _)| *name).collect()
+    }
+
+    fn find_regions(code: &str) -> anyhow::Result<Vec<(&str, Range<usize>)>> {
+        const PREFIX: &str = "// === cargo-equip: ";
+        const SUFFIX: &str = " ===";
+
+        let mut regions = vec![];
+        let mut current = None;
+        let mut pos = 0;
+        for (i, line) in code.split_inclusive('\n').enumerate() {
+            let start = pos;
+            pos += line.len();
+            let marker = line
+                .trim()
+                .strip_prefix(PREFIX)
+                .and_then(|s| s.strip_suffix(SUFFIX));
+            match (marker, current) {
+                (Some(marker), None) => {
+                    if let Some(name) = marker.strip_prefix("begin ") {
+                        current = Some((name, start));
+                    } else {
+                        bail!("line {}: unexpected end marker", i + 1);
+                    }
+                }
+                (Some(marker), Some((name, region_start))) => {
+                    if marker.strip_prefix("end ") == Some(name) {
+                        regions.push((name, region_start..pos));
+                        current = None;
+                    } else {
+                        bail!("line {}: expected the end marker of `{}`", i + 1, name);
+                    }
+                }
+                (None, _) => {}
+            }
+        }
+        if let Some((name, _)) = current {
+            bail!("missing the end marker of `{}`", name);
+        }
+        Ok(regions)
+    }
+}
+
+pub(crate) fn parse_file(code: &str) -> anyhow::Result<syn::File> {
+    syn::parse_file(code)
+        .map_err(|e| anyhow!("{}", render_syn_error("<modified>", code, &e)))
+        .with_context(|| "broke the code during modification")
+}
+
+/// Modules in the code, with their own content and the other modules they refer to.
+#[allow(clippy::type_complexity)]
+pub(crate) fn module_deps(
+    code: &str,
+) -> anyhow::Result<Vec<(Vec<String>, String, BTreeSet<Vec<String>>)>> {
+    let modules = split_into_modules(code)?;
+    let paths = modules.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>();
+    return modules
+        .into_iter()
+        .map(|(path, content)| {
+            let deps = collect_paths(&content)?
+                .iter()
+                .flat_map(|segments| resolve(&paths, &path, segments))
+                .flat_map(|dep| longest_module_prefix(&paths, &dep))
+                .filter(|dep| *dep != path)
+                .collect();
+            Ok((path, content, deps))
+        })
+        .collect();
+
+    fn resolve(
+        paths: &[Vec<String>],
+        mod_path: &[String],
+        segments: &[String],
+    ) -> Option<Vec<String>> {
+        let (mut path, rest) = match segments {
+            [first, rest @ ..] if first == "crate" => (vec![], rest),
+            [first, rest @ ..] if first == "self" => (mod_path.to_owned(), rest),
+            [first, ..] if first == "super" => {
+                let supers = segments.iter().take_while(|s| *s == "super").count();
+                let path = mod_path.get(..mod_path.len().checked_sub(supers)?)?;
+                (path.to_owned(), &segments[supers..])
+            }
+            // A child module
+            [first, ..]
+                if paths
+                    .iter()
+                    .any(|p| p.split_last() == Some((first, mod_path))) =>
+            {
+                (mod_path.to_owned(), segments)
+            }
+            _ => return None,
+        };
+        path.extend(rest.iter().cloned());
+        Some(path)
+    }
+}
+
+/// Why `--only` keeps a module.
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) enum Kept {
+    /// Listed in `--only`, or a descendant of one.
+    Listed,
+    /// An ancestor of the module, which is kept with its own items.
+    Ancestor(Vec<String>),
+    /// Referred to from the module.
+    ReferredFrom(Vec<String>),
+}
+
+/// Finds the modules that `--only` keeps from the output of [`module_deps`].
+#[allow(clippy::type_complexity)]
+pub(crate) fn modules_to_keep(
+    modules: &[(Vec<String>, String, BTreeSet<Vec<String>>)],
+    only: &[Vec<String>],
+) -> BTreeMap<Vec<String>, Kept> {
+    let deps = modules
+        .iter()
+        .map(|(path, _, deps)| (path, deps))
+        .collect::<BTreeMap<_, _>>();
+    let mut keep = BTreeMap::new();
+    let mut queue = modules
+        .iter()
+        .map(|(path, ..)| path.clone())
+        .filter(|path| only.iter().any(|o| path.starts_with(o)))
+        .chain(only.iter().cloned())
+        .map(|path| (path, Kept::Listed))
+        .collect::<VecDeque<_>>();
+    while let Some((path, kept)) = queue.pop_front() {
+        for i in 0..=path.len() {
+            let prefix = path[..i].to_owned();
+            let kept = if i == path.len() {
+                kept.clone()
+            } else {
+                Kept::Ancestor(path.clone())
+            };
+            match keep.entry(prefix.clone()) {
+                btree_map::Entry::Vacant(entry) => {
+                    entry.insert(kept);
+                    queue.extend(
+                        deps.get(&prefix)
+                            .into_iter()
+                            .flat_map(|d| d.iter())
+                            .map(|d| (d.clone(), Kept::ReferredFrom(prefix.clone()))),
+                    );
+                }
+                btree_map::Entry::Occupied(mut entry) if kept == Kept::Listed => {
+                    entry.insert(kept);
+                }
+                btree_map::Entry::Occupied(_) => {}
+            }
+        }
+    }
+    keep
+}
+
+/// Splits a module path such as `::my-lib::a::b` into the crate name and the rest.
+///
+/// The leading `::` is optional, and hyphens in the crate name are replaced with underscores as in
+/// extern crate names.
+pub(crate) fn parse_module_path(path: &str) -> (String, Vec<String>) {
+    let mut segments = path.trim_start_matches("::").split("::");
+    let crate_name = segments.next().unwrap_or_default().replace('-', "_");
+    (crate_name, segments.map(ToOwned::to_owned).collect())
+}
+
+pub(crate) fn longest_module_prefix(
+    paths: &[Vec<String>],
+    segments: &[String],
+) -> Option<Vec<String>> {
+    (0..=segments.len())
+        .rev()
+        .map(|i| &segments[..i])
+        .find(|prefix| paths.iter().any(|p| p == prefix))
+        .map(ToOwned::to_owned)
+}
+
+/// Items with `#[deprecated]`, with their paths and the notes.
+pub(crate) fn deprecated_items(code: &str) -> anyhow::Result<Vec<(Vec<String>, Option<String>)>> {
+    let syn::File { items, .. } = parse_file(code)?;
+    let mut acc = vec![];
+    walk(&items, &mut vec![], &mut acc);
+    return Ok(acc);
+
+    fn walk(items: &[Item], path: &mut Vec<String>, acc: &mut Vec<(Vec<String>, Option<String>)>) {
+        for item in items {
+            let (attrs, ident) = match item {
+                Item::Const(ItemConst { attrs, ident, .. })
+                | Item::Enum(ItemEnum { attrs, ident, .. })
+                | Item::Mod(ItemMod { attrs, ident, .. })
+                | Item::Static(ItemStatic { attrs, ident, .. })
+                | Item::Struct(ItemStruct { attrs, ident, .. })
+                | Item::Trait(ItemTrait { attrs, ident, .. })
+                | Item::Type(ItemType { attrs, ident, .. })
+                | Item::Union(ItemUnion { attrs, ident, .. }) => (attrs, ident),
+                Item::Fn(ItemFn { attrs, sig, .. }) => (attrs, &sig.ident),
+                Item::Macro(ItemMacro {
+                    attrs,
+                    ident: Some(ident),
+                    ..
+                }) => (attrs, ident),
+                _ => continue,
+            };
+            path.push(ident.to_string());
+            for attr in attrs.iter().filter(|a| a.path.is_ident("deprecated")) {
+                let note = match attr.parse_meta() {
+                    Ok(Meta::NameValue(MetaNameValue {
+                        lit: Lit::Str(note),
+                        ..
+                    })) => Some(note.value()),
+                    Ok(Meta::List(MetaList { nested, .. })) => {
+                        nested.iter().find_map(|m| match m {
+                            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                                path,
+                                lit: Lit::Str(note),
+                                ..
+                            })) if path.is_ident("note") => Some(note.value()),
+                            _ => None,
+                        })
+                    }
+                    _ => None,
+                };
+                acc.push((path.clone(), note));
+            }
+            if let Item::Mod(ItemMod {
+                content: Some((_, items)),
+                ..
+            }) = item
+            {
+                walk(items, path, acc);
+            }
+            path.pop();
+        }
+    }
+}
+
+pub(crate) fn split_into_modules(code: &str) -> anyhow::Result<Vec<(Vec<String>, String)>> {
+    let syn::File { items, .. } = parse_file(code)?;
+
+    let line_starts =
+        itertools::chain!([0], code.match_indices('\n').map(|(i, _)| i + 1)).collect::<Vec<_>>();
+    let to_index = |LineColumn { line, column }| -> usize {
+        let start = line_starts[line - 1];
+        start
+            + code[start..]
+                .char_indices()
+                .nth(column)
+                .map_or(code.len() - start, |(i, _)| i)
+    };
+
+    let mut modules = vec![];
+    visit(
+        code,
+        &items,
+        0..code.len(),
+        &mut vec![],
+        &to_index,
+        &mut modules,
+    );
+    return Ok(modules);
+
+    fn visit(
+        code: &str,
+        items: &[Item],
+        range: Range<usize>,
+        path: &mut Vec<String>,
+        to_index: &impl Fn(LineColumn) -> usize,
+        modules: &mut Vec<(Vec<String>, String)>,
+    ) {
+        let inline_mods = items
+            .iter()
+            .flat_map(|item| match item {
+                Item::Mod(ItemMod {
+                    ident,
+                    content: Some((brace, items)),
+                    ..
+                }) => Some((item.span(), ident, brace.span, items)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut content = "".to_owned();
+        let mut pos = range.start;
+        for (span, ..) in &inline_mods {
+            content += &code[pos..to_index(span.start())];
+            pos = to_index(span.end());
+        }
+        content += &code[pos..range.end];
+
+        let indent = content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.len() - line.trim_start().len())
+            .min()
+            .unwrap_or(0);
+        let content = content
+            .lines()
+            .map(|line| line.get(indent..).unwrap_or("").trim_end())
+            .join("\n");
+        let content = content.trim_matches('\n');
+        if !content.is_empty() {
+            modules.push((path.clone(), format!("{}\n", content)));
+        }
+
+        for (_, ident, brace_span, items) in inline_mods {
+            let range = to_index(brace_span.start()) + 1..to_index(brace_span.end()) - 1;
+            path.push(ident.to_string());
+            visit(code, items, range, path, to_index, modules);
+            path.pop();
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn process_bin<'cm>(
+    cargo_equip_mod_name: &Ident,
+    src_path: &Utf8Path,
+    proc_macro_expander: Option<&mut ProcMacroExpander<'_>>,
+    mut translate_extern_crate_name: impl FnMut(&str) -> Option<String>,
+    mut is_lib_to_bundle: impl FnMut(&str) -> bool,
+    entry_point: Option<&str>,
+    preprocess: impl FnOnce(&mut CodeEdit<'_>) -> anyhow::Result<()>,
+    context: impl FnOnce() -> (String, &'cm str),
+) -> anyhow::Result<(String, BTreeMap<String, String>)> {
+    let mut edit = CodeEdit::new(cargo_equip_mod_name, src_path, context)?;
+    preprocess(&mut edit)?;
+    if let Some(proc_macro_expander) = proc_macro_expander {
+        edit.expand_proc_macros(proc_macro_expander)?;
+    }
+    if let Some(entry_point) = entry_point {
+        edit.rename_main(entry_point)?;
+    }
+    let extern_crate_renames = edit.extern_crate_renames_in_bin(&mut is_lib_to_bundle)?;
+    edit.translate_extern_crate_paths(|extern_crate_name| {
+        translate_extern_crate_name(
+            extern_crate_renames
+                .get(extern_crate_name)
+                .map_or(extern_crate_name, |s| s),
+        )
+    })?;
+    edit.process_extern_crate_in_bin(is_lib_to_bundle)?;
+    Ok((edit.finish()?, extern_crate_renames))
+}
+
+pub(crate) struct CodeEdit<'opt> {
+    cargo_equip_mod_name: &'opt Ident,
+    has_local_inner_macros_attr: bool,
+    string: String,
+    file: syn::File,
+    replacements: BTreeMap<(LineColumn, LineColumn), String>,
+}
+
+impl<'opt> CodeEdit<'opt> {
+    pub(crate) fn new<'cm>(
+        cargo_equip_mod_name: &'opt Ident,
+        src_path: &Utf8Path,
+        err_context: impl FnOnce() -> (String, &'cm str),
+    ) -> anyhow::Result<Self> {
+        return (|| {
+            Self::from_code(cargo_equip_mod_name, &expand_mods(src_path, 0)?)
+                .map_err(anyhow::Error::from)
+        })()
+        .with_context(|| {
+            let (crate_name, package_id) = err_context();
+            format!("could not expand `{}` from `{}`", crate_name, package_id)
+        });
+
+        fn expand_mods(src_path: &Utf8Path, depth: usize) -> anyhow::Result<String> {
+            let content = cargo_util::paths::read(src_path.as_ref())?;
+
+            let file = syn::parse_file(&content)
+                .map_err(|e| anyhow!("{}", render_syn_error(src_path.as_str(), &content, &e)))
+                .with_context(|| format!("could not parse `{}`", src_path))?;
+
+            // Paths for `include_str!` and `include_bytes!` are relative to the file, which is
+            // going to be inlined.
+            let mut replacements = BTreeMap::new();
+            if depth > 0 {
+                visit_asset_includes(&file, |_, path, _| {
+                    if Utf8Path::new(&path.value()).is_relative() {
+                        let abs_path = src_path.with_file_name("").join(path.value()).into_string();
+                        replacements.insert(
+                            (path.span().start(), path.span().end()),
+                            quote!(#abs_path).to_string(),
+                        );
+                    }
+                });
+            }
+
+            let syn::File { items, .. } = file;
+            let replacements = items
+                .into_iter()
+                .flat_map(|item| match item {
+                    Item::Mod(ItemMod {
+                        attrs,
+                        ident,
+                        content: None,
+                        semi,
+                        ..
+                    }) => Some((attrs, ident, semi)),
+                    _ => None,
+                })
+                .map(|(attrs, ident, semi)| {
+                    let paths = if let Some(path) = attrs
+                        .iter()
+                        .flat_map(Attribute::parse_meta)
+                        .flat_map(|meta| match meta {
+                            Meta::NameValue(name_value) => Some(name_value),
+                            _ => None,
+                        })
+                        .filter(|MetaNameValue { path, .. }| {
+                            matches!(path.get_ident(), Some(i) if i == "path")
+                        })
+                        .find_map(|MetaNameValue { lit, .. }| match lit {
+                            Lit::Str(s) => Some(s.value()),
+                            _ => None,
+                        }) {
+                        vec![src_path.with_file_name("").join(path)]
+                    } else if depth == 0 || src_path.file_name() == Some("mod.rs") {
+                        vec![
+                            src_path
+                                .with_file_name(&ident.to_string())
+                                .with_extension("rs"),
+                            src_path.with_file_name(&ident.to_string()).join("mod.rs"),
+                        ]
+                    } else {
+                        vec![
+                            src_path
+                                .with_extension("")
+                                .with_file_name(&ident.to_string())
+                                .with_extension("rs"),
                             src_path
                                 .with_extension("")
                                 .with_file_name(&ident.to_string())
@@ -394,13 +1430,14 @@ impl<'opt> CodeEdit<'opt> {
                         bail!("one of {:?} does not exist", paths);
                     }
                 })
+                .chain(replacements.into_iter().map(Ok))
                 .collect::<anyhow::Result<_>>()?;
 
             Ok(replace_ranges(&content, replacements))
         }
     }
 
-    fn from_code(cargo_equip_mod_name: &'opt Ident, string: &str) -> syn::Result<Self> {
+    pub(crate) fn from_code(cargo_equip_mod_name: &'opt Ident, string: &str) -> syn::Result<Self> {
         let file = syn::parse_file(string)?;
         return Ok(Self {
             cargo_equip_mod_name,
@@ -448,6 +1485,15 @@ impl<'opt> CodeEdit<'opt> {
         self.has_local_inner_macros_attr
     }
 
+    pub(crate) fn source_len(&self) -> usize {
+        self.string.len()
+    }
+
+    pub(crate) fn code(&mut self) -> anyhow::Result<&str> {
+        self.apply()?;
+        Ok(&self.string)
+    }
+
     pub(crate) fn finish(mut self) -> anyhow::Result<String> {
         self.apply()?;
         Ok(self.string)
@@ -462,11 +1508,64 @@ impl<'opt> CodeEdit<'opt> {
 
     fn force_apply(&mut self) -> anyhow::Result<()> {
         self.string = replace_ranges(&self.string, mem::take(&mut self.replacements));
-        self.file =
-            syn::parse_file(&self.string).with_context(|| "broke the code during modification")?;
+        self.file = syn::parse_file(&self.string)
+            .map_err(|e| anyhow!("{}", render_syn_error("<modified>", &self.string, &e)))
+            .with_context(|| "broke the code during modification")?;
         Ok(())
     }
 
+    fn extern_crate_renames_in_bin(
+        &mut self,
+        mut is_lib_to_bundle: impl FnMut(&str) -> bool,
+    ) -> anyhow::Result<BTreeMap<String, String>> {
+        self.apply()?;
+
+        // `extern crate $name as $rename;` at the crate root also adds `$rename` to the extern prelude.
+        Ok(self
+            .file
+            .items
+            .iter()
+            .flat_map(|item| match item {
+                Item::ExternCrate(ItemExternCrate {
+                    ident,
+                    rename: Some((_, rename)),
+                    ..
+                }) if rename != "_" && is_lib_to_bundle(&ident.to_string()) => {
+                    Some((rename.to_string(), ident.to_string()))
+                }
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn rename_main(&mut self, entry_point: &str) -> anyhow::Result<()> {
+        self.apply()?;
+
+        let ItemFn { vis, sig, .. } = self
+            .file
+            .items
+            .iter()
+            .find_map(|item| match item {
+                Item::Fn(item_fn) if item_fn.sig.ident == "main" => Some(item_fn),
+                _ => None,
+            })
+            .with_context(|| "could not find `fn main` at the crate root")?;
+
+        if matches!(vis, syn::Visibility::Inherited) {
+            let pos = sig.span().start();
+            self.replacements.insert((pos, pos), "pub ".to_owned());
+        }
+        self.replacements.insert(
+            (sig.ident.span().start(), sig.ident.span().end()),
+            entry_point.to_owned(),
+        );
+        let output = sig.output.to_token_stream();
+        self.force_apply()?;
+
+        self.string += &format!("\nfn main() {} {{\n    {}()\n}}\n", output, entry_point);
+        self.force_apply()
+    }
+
     fn process_extern_crate_in_bin(
         &mut self,
         is_lib_to_bundle: impl FnMut(&str) -> bool,
@@ -915,7 +2014,7 @@ impl<'opt> CodeEdit<'opt> {
                     ..
                 }) = expr
                 {
-                    if [parse_quote!(::core::concat), parse_quote!(::std::concat)].contains(path) {
+                    if is_builtin_macro(path, "concat") {
                         (|parse_stream: ParseStream<'_>| {
                             Punctuated::<Expr, Token![,]>::parse_separated_nonempty(parse_stream)
                         })
@@ -924,7 +2023,7 @@ impl<'opt> CodeEdit<'opt> {
                         .iter()
                         .map(|expr| self.resolve(expr))
                         .collect()
-                    } else if [parse_quote!(::core::env), parse_quote!(::std::env)].contains(path) {
+                    } else if is_builtin_macro(path, "env") {
                         let name = syn::parse2::<LitStr>(tokens.clone()).ok()?.value();
                         if name == "OUT_DIR" {
                             Some(self.out_dir.as_str().to_owned())
@@ -944,76 +2043,347 @@ impl<'opt> CodeEdit<'opt> {
                     None
                 }
             }
+
+            fn read_included_file(&self, mac: &Macro) -> Option<String> {
+                if !is_builtin_macro(&mac.path, "include") {
+                    return None;
+                }
+                let path = Utf8PathBuf::from(self.resolve(&syn::parse2(mac.tokens.clone()).ok()?)?);
+                if !path.is_absolute() {
+                    return None;
+                }
+                cargo_util::paths::read(path.as_ref()).ok()
+            }
         }
 
         impl Visit<'_> for Visitor<'_> {
             fn visit_item_macro(&mut self, i: &ItemMacro) {
-                if i.ident.is_none()
-                    && [parse_quote!(::core::include), parse_quote!(::std::include)]
-                        .contains(&i.mac.path)
-                {
-                    if let Ok(expr) = syn::parse2(i.mac.tokens.clone()) {
-                        if let Some(path) = self.resolve(&expr) {
-                            let path = Utf8PathBuf::from(path);
-                            if path.is_absolute() {
-                                if let Ok(content) = cargo_util::paths::read(path.as_ref()) {
-                                    self.replacements
-                                        .insert((i.span().start(), i.span().end()), content);
-                                }
-                            }
-                        }
+                if i.ident.is_none() {
+                    if let Some(content) = self.read_included_file(&i.mac) {
+                        self.replacements
+                            .insert((i.span().start(), i.span().end()), content);
                     }
                 }
             }
-        }
-    }
-
-    pub(crate) fn translate_extern_crate_paths(
-        &mut self,
-        translate_extern_crate_name: impl FnMut(&str) -> Option<String>,
-    ) -> anyhow::Result<()> {
-        self.apply()?;
-        Visitor {
-            replacements: &mut self.replacements,
-            cargo_equip_mod_name: self.cargo_equip_mod_name,
-            translate_extern_crate_name,
-        }
-        .visit_file(&self.file);
-        return Ok(());
-
-        struct Visitor<'a, F> {
-            replacements: &'a mut BTreeMap<(LineColumn, LineColumn), String>,
-            cargo_equip_mod_name: &'a Ident,
-            translate_extern_crate_name: F,
-        }
 
-        impl<F: FnMut(&str) -> Option<String>> Visitor<'_, F> {
-            fn attempt_translate(&mut self, leading_colon: Span, extern_crate_name: &Ident) {
-                if let Some(pseudo_extern_crate_name) =
-                    (self.translate_extern_crate_name)(&extern_crate_name.to_string())
-                {
+            fn visit_expr_macro(&mut self, i: &ExprMacro) {
+                if let Some(content) = self.read_included_file(&i.mac) {
                     self.replacements.insert(
-                        (leading_colon.start(), leading_colon.end()),
-                        format!("/*::*/crate::{}::crates::", self.cargo_equip_mod_name),
+                        (i.span().start(), i.span().end()),
+                        format!("({})", content.trim_end()),
                     );
-
-                    if extern_crate_name != &*pseudo_extern_crate_name {
-                        let span = extern_crate_name.span();
-                        self.replacements.insert(
-                            (span.start(), span.end()),
-                            format!("/*{}*/{}", extern_crate_name, pseudo_extern_crate_name),
-                        );
-                    }
                 }
             }
         }
+    }
 
-        impl<F: FnMut(&str) -> Option<String>> Visit<'_> for Visitor<'_, F> {
-            fn visit_item_use(&mut self, i: &'_ ItemUse) {
-                if let Some(leading_colon) = i.leading_colon {
-                    for extern_crate_name in extract_first_segments(&i.tree) {
-                        self.attempt_translate(leading_colon.span(), extern_crate_name);
-                    }
+    /// Replaces `include_str!`s and `include_bytes!`s with literals. Returns the paths and the sizes
+    /// of the files.
+    pub(crate) fn inline_assets(
+        &mut self,
+        src_path: &Utf8Path,
+    ) -> anyhow::Result<Vec<(Utf8PathBuf, usize)>> {
+        self.apply()?;
+        let replacements = &mut self.replacements;
+        let mut assets = vec![];
+        let mut result = Ok(());
+        visit_asset_includes(&self.file, |range, path, is_bytes| {
+            if result.is_err() {
+                return;
+            }
+            let path = src_path.with_file_name("").join(path.value());
+            let content = match cargo_util::paths::read_bytes(path.as_ref()) {
+                Ok(content) => content,
+                Err(err) => {
+                    result = Err(err);
+                    return;
+                }
+            };
+            let lit = if is_bytes {
+                LitByteStr::new(&content, Span::call_site()).to_token_stream()
+            } else {
+                match String::from_utf8(content.clone()) {
+                    Ok(content) => LitStr::new(&content, Span::call_site()).to_token_stream(),
+                    Err(_) => {
+                        result = Err(anyhow!("`{}` is not valid UTF-8", path));
+                        return;
+                    }
+                }
+            };
+            replacements.insert(
+                range,
+                format!(
+                    "/*{}!(..)*/{}",
+                    if is_bytes {
+                        "include_bytes"
+                    } else {
+                        "include_str"
+                    },
+                    lit
+                ),
+            );
+            assets.push((path, content.len()));
+        });
+        result?;
+        Ok(assets)
+    }
+
+    /// Rewrites `core::` and `alloc::` paths into `std::`, including ones in macros.
+    ///
+    /// Names that the file declares by itself, e.g. `mod core;`, are left as they are.
+    pub(crate) fn normalize_core_alloc(&mut self) -> anyhow::Result<()> {
+        self.apply()?;
+
+        let mut declared = BTreeSet::new();
+        visit_declared_idents(&self.file, &mut declared);
+        let names = ["core", "alloc"]
+            .iter()
+            .filter(|name| !declared.contains(**name))
+            .collect::<Vec<_>>();
+
+        for item in &self.file.items {
+            if let Item::ExternCrate(ItemExternCrate {
+                ident,
+                rename: None,
+                ..
+            }) = item
+            {
+                if names.iter().any(|name| ident == name) {
+                    self.replacements
+                        .insert((item.span().start(), item.span().end()), "".to_owned());
+                }
+            }
+        }
+        visit(self.file.to_token_stream(), &names, &mut self.replacements);
+        return Ok(());
+
+        fn visit_declared_idents(file: &syn::File, declared: &mut BTreeSet<String>) {
+            struct Visitor<'a>(&'a mut BTreeSet<String>);
+
+            impl Visit<'_> for Visitor<'_> {
+                fn visit_item_mod(&mut self, i: &ItemMod) {
+                    self.0.insert(i.ident.to_string());
+                    visit::visit_item_mod(self, i);
+                }
+
+                fn visit_item_extern_crate(&mut self, i: &ItemExternCrate) {
+                    if let Some((_, rename)) = &i.rename {
+                        self.0.insert(rename.to_string());
+                    }
+                }
+            }
+
+            Visitor(declared).visit_file(file);
+        }
+
+        fn visit(
+            tokens: TokenStream,
+            names: &[&&str],
+            replacements: &mut BTreeMap<(LineColumn, LineColumn), String>,
+        ) {
+            let tokens = tokens.into_iter().collect::<Vec<_>>();
+            for (i, tt) in tokens.iter().enumerate() {
+                match tt {
+                    TokenTree::Group(group) => visit(group.stream(), names, replacements),
+                    TokenTree::Ident(ident) if names.iter().any(|name| ident == name) => {
+                        // Skip `a::core::..` and `$crate::core::..`.
+                        let is_leading = !matches!(
+                            tokens.get(i.saturating_sub(3)..i),
+                            Some([prev, TokenTree::Punct(p1), TokenTree::Punct(p2)])
+                                if p1.as_char() == ':' && p2.as_char() == ':' && match prev {
+                                    TokenTree::Ident(prev) => {
+                                        ["crate", "self", "super", "Self"].iter().any(|s| prev == s)
+                                            || syn::parse2::<Ident>(prev.to_token_stream()).is_ok()
+                                    }
+                                    TokenTree::Punct(p) => p.as_char() == '>',
+                                    _ => false,
+                                }
+                        );
+                        if_chain! {
+                            if is_leading;
+                            if let Some([TokenTree::Punct(colon1), TokenTree::Punct(colon2)]) =
+                                tokens.get(i + 1..i + 3);
+                            if colon1.as_char() == ':' && colon1.spacing() == Spacing::Joint;
+                            if colon2.as_char() == ':';
+                            then {
+                                let span = ident.span();
+                                replacements.insert(
+                                    (span.start(), span.end()),
+                                    format!("/*{}*/std", ident),
+                                );
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Removes `eprint!`s, `eprintln!`s and `dbg!`s, and/or `debug_assert*!`s.
+    pub(crate) fn strip_debug(&mut self, prints: bool, asserts: bool) -> anyhow::Result<()> {
+        self.apply()?;
+        let mut names = vec![];
+        if prints {
+            names.extend(["eprint", "eprintln", "dbg"]);
+        }
+        if asserts {
+            names.extend(["debug_assert", "debug_assert_eq", "debug_assert_ne"]);
+        }
+        Visitor {
+            names: &names,
+            replacements: &mut self.replacements,
+        }
+        .visit_file(&self.file);
+        return Ok(());
+
+        struct Visitor<'a> {
+            names: &'a [&'a str],
+            replacements: &'a mut BTreeMap<(LineColumn, LineColumn), String>,
+        }
+
+        impl Visitor<'_> {
+            fn name(&self, mac: &Macro) -> Option<&str> {
+                self.names
+                    .iter()
+                    .copied()
+                    .find(|name| is_builtin_macro(&mac.path, name))
+            }
+
+            /// `dbg!(a)` → `(a)`, `dbg!(a, b)` → `(a, b)`
+            fn replace_dbg(&mut self, mac: &Macro) {
+                if let Ok(args) = (|parse_stream: ParseStream<'_>| {
+                    Punctuated::<Expr, Token![,]>::parse_terminated(parse_stream)
+                })
+                .parse2(mac.tokens.clone())
+                {
+                    let args = args.into_iter().collect::<Vec<_>>();
+                    let replacement = match &*args {
+                        [arg] => quote!((#arg)),
+                        args => quote!((#(#args),*)),
+                    };
+                    self.replacements.insert(
+                        (mac.span().start(), mac.span().end()),
+                        replacement.to_string(),
+                    );
+                }
+            }
+        }
+
+        impl Visit<'_> for Visitor<'_> {
+            fn visit_stmt(&mut self, i: &Stmt) {
+                let mac = match i {
+                    Stmt::Item(Item::Macro(ItemMacro {
+                        ident: None, mac, ..
+                    })) => mac,
+                    Stmt::Semi(Expr::Macro(ExprMacro { mac, .. }), _) => mac,
+                    _ => return visit::visit_stmt(self, i),
+                };
+                match self.name(mac) {
+                    // The arguments may have side effects.
+                    Some("dbg") => self.replace_dbg(mac),
+                    Some(_) => {
+                        self.replacements
+                            .insert((i.span().start(), i.span().end()), "".to_owned());
+                    }
+                    None => visit::visit_stmt(self, i),
+                }
+            }
+
+            fn visit_expr(&mut self, i: &Expr) {
+                if let Expr::Macro(ExprMacro { mac, .. }) = i {
+                    match self.name(mac) {
+                        Some("dbg") => self.replace_dbg(mac),
+                        Some(_) => {
+                            self.replacements
+                                .insert((i.span().start(), i.span().end()), "()".to_owned());
+                        }
+                        None => {}
+                    }
+                    return;
+                }
+                visit::visit_expr(self, i);
+            }
+        }
+    }
+
+    /// Replaces `env!("NAME")`s and `option_env!("NAME")`s with the given values.
+    pub(crate) fn define_env_vars(&mut self, defines: &[(String, String)]) -> anyhow::Result<()> {
+        self.apply()?;
+        let replacements = &mut self.replacements;
+        visit_builtin_macro_calls(&self.file, &["env", "option_env"], |range, name, tokens| {
+            let args = (|parse_stream: ParseStream<'_>| {
+                Punctuated::<LitStr, Token![,]>::parse_terminated(parse_stream)
+            })
+            .parse2(tokens.clone());
+            if_chain! {
+                if let Ok(args) = args;
+                if let Some(var) = args.first();
+                if let Some((_, value)) = defines.iter().rev().find(|(k, _)| *k == var.value());
+                then {
+                    let value = if name == "env" {
+                        quote!(#value)
+                    } else {
+                        quote!(::std::option::Option::Some(#value))
+                    };
+                    replacements.insert(range, format!("/*{}!(..)*/{}", name, value));
+                }
+            }
+        });
+        Ok(())
+    }
+
+    pub(crate) fn translate_extern_crate_paths(
+        &mut self,
+        translate_extern_crate_name: impl FnMut(&str) -> Option<String>,
+    ) -> anyhow::Result<()> {
+        self.apply()?;
+        Visitor {
+            replacements: &mut self.replacements,
+            cargo_equip_mod_name: self.cargo_equip_mod_name,
+            translate_extern_crate_name,
+        }
+        .visit_file(&self.file);
+        return Ok(());
+
+        struct Visitor<'a, F> {
+            replacements: &'a mut BTreeMap<(LineColumn, LineColumn), String>,
+            cargo_equip_mod_name: &'a Ident,
+            translate_extern_crate_name: F,
+        }
+
+        impl<F: FnMut(&str) -> Option<String>> Visitor<'_, F> {
+            fn attempt_translate(
+                &mut self,
+                leading_colon: (LineColumn, LineColumn),
+                extern_crate_name: &Ident,
+            ) {
+                if let Some(pseudo_extern_crate_name) =
+                    (self.translate_extern_crate_name)(&extern_crate_name.to_string())
+                {
+                    self.replacements.insert(
+                        leading_colon,
+                        format!("/*::*/crate::{}::crates::", self.cargo_equip_mod_name),
+                    );
+
+                    if extern_crate_name != &*pseudo_extern_crate_name {
+                        let span = extern_crate_name.span();
+                        self.replacements.insert(
+                            (span.start(), span.end()),
+                            format!("/*{}*/{}", extern_crate_name, pseudo_extern_crate_name),
+                        );
+                    }
+                }
+            }
+        }
+
+        impl<F: FnMut(&str) -> Option<String>> Visit<'_> for Visitor<'_, F> {
+            fn visit_item_use(&mut self, i: &'_ ItemUse) {
+                if let Some(leading_colon) = i.leading_colon {
+                    for extern_crate_name in extract_first_segments(&i.tree) {
+                        let span = leading_colon.span();
+                        self.attempt_translate((span.start(), span.end()), extern_crate_name);
+                    }
                 }
 
                 fn extract_first_segments(tree: &UseTree) -> Vec<&Ident> {
@@ -1035,9 +2405,45 @@ impl<'opt> CodeEdit<'opt> {
                 if let Some(leading_colon) = i.leading_colon {
                     let PathSegment { ident, .. } = i
                         .segments
-                        .last()
+                        .first()
                         .expect("`syn::Path::segments` is considered not to be empty");
-                    self.attempt_translate(leading_colon.span(), ident);
+                    let span = leading_colon.span();
+                    self.attempt_translate((span.start(), span.end()), ident);
+                }
+            }
+
+            // Paths in macro calls and `macro_rules!` are not parsed.
+            fn visit_macro(&mut self, i: &'_ Macro) {
+                self.visit_tokens(i.tokens.clone());
+            }
+        }
+
+        impl<F: FnMut(&str) -> Option<String>> Visitor<'_, F> {
+            fn visit_tokens(&mut self, tokens: TokenStream) {
+                let tokens = tokens.into_iter().collect::<Vec<_>>();
+                for (i, tt) in tokens.iter().enumerate() {
+                    if let TokenTree::Group(group) = tt {
+                        self.visit_tokens(group.stream());
+                    }
+                    let is_leading = match i.checked_sub(1).map(|i| &tokens[i]) {
+                        None => true,
+                        Some(TokenTree::Punct(punct)) => punct.as_char() != '>',
+                        Some(_) => false,
+                    };
+                    if_chain! {
+                        if is_leading;
+                        if let Some(
+                            [TokenTree::Punct(colon1), TokenTree::Punct(colon2), TokenTree::Ident(ident)]
+                        ) = tokens.get(i..i + 3);
+                        if colon1.as_char() == ':' && colon1.spacing() == Spacing::Joint;
+                        if colon2.as_char() == ':';
+                        then {
+                            self.attempt_translate(
+                                (colon1.span().start(), colon2.span().end()),
+                                ident,
+                            );
+                        }
+                    }
                 }
             }
         }
@@ -1082,6 +2488,7 @@ impl<'opt> CodeEdit<'opt> {
             }
 
             fn visit_item_use(&mut self, item_use: &'_ ItemUse) {
+                self.visit_visibility(&item_use.vis);
                 if item_use.leading_colon.is_none() {
                     self.visit_use_tree(&item_use.tree);
                 }
@@ -1117,6 +2524,39 @@ impl<'opt> CodeEdit<'opt> {
                     }
                 }
             }
+
+            // Paths in macro calls and `macro_rules!` are not parsed.
+            fn visit_macro(&mut self, i: &'_ Macro) {
+                self.visit_path(&i.path);
+                self.visit_tokens(i.tokens.clone());
+            }
+        }
+
+        impl Visitor<'_> {
+            fn visit_tokens(&mut self, tokens: TokenStream) {
+                let tokens = tokens.into_iter().collect::<Vec<_>>();
+                for (i, tt) in tokens.iter().enumerate() {
+                    if let TokenTree::Group(group) = tt {
+                        self.visit_tokens(group.stream());
+                    }
+                    let is_dollar_crate = matches!(
+                        i.checked_sub(1).map(|i| &tokens[i]),
+                        Some(TokenTree::Punct(punct)) if punct.as_char() == '$'
+                    );
+                    if_chain! {
+                        if !is_dollar_crate;
+                        if let Some(
+                            [TokenTree::Ident(ident), TokenTree::Punct(colon1), TokenTree::Punct(colon2)]
+                        ) = tokens.get(i..i + 3);
+                        if ident == "crate";
+                        if colon1.as_char() == ':' && colon1.spacing() == Spacing::Joint;
+                        if colon2.as_char() == ':';
+                        then {
+                            self.insert(ident);
+                        }
+                    }
+                }
+            }
         }
     }
 
@@ -1372,11 +2812,17 @@ impl<'opt> CodeEdit<'opt> {
         Ok(prelude)
     }
 
-    pub(crate) fn resolve_cfgs(&mut self, features: &[String]) -> anyhow::Result<()> {
+    /// Replaces `cfg!(..)`s with `true` or `false` if they can be evaluated as `#[cfg(..)]`s.
+    pub(crate) fn fold_cfg_macros(
+        &mut self,
+        features: &[String],
+        target: Option<&cfg_expr::targets::TargetInfo>,
+    ) -> anyhow::Result<()> {
         self.apply()?;
         Visitor {
             replacements: &mut self.replacements,
             features,
+            target,
         }
         .visit_file(&self.file);
         return Ok(());
@@ -1384,6 +2830,104 @@ impl<'opt> CodeEdit<'opt> {
         struct Visitor<'a> {
             replacements: &'a mut BTreeMap<(LineColumn, LineColumn), String>,
             features: &'a [String],
+            target: Option<&'a cfg_expr::targets::TargetInfo>,
+        }
+
+        impl Visit<'_> for Visitor<'_> {
+            fn visit_macro(&mut self, i: &Macro) {
+                if !is_builtin_macro(&i.path, "cfg") {
+                    return;
+                }
+                let value = cfg_expr::Expression::parse(&i.tokens.to_string())
+                    .ok()
+                    .and_then(|expr| eval_cfg(&expr, self.features, self.target));
+                if let Some(value) = value {
+                    self.replacements
+                        .insert((i.span().start(), i.span().end()), value.to_string());
+                }
+            }
+        }
+    }
+
+    /// Removes the derive macros from `#[derive(..)]`s.
+    pub(crate) fn strip_derives(&mut self, names: &[String]) -> anyhow::Result<()> {
+        self.apply()?;
+        Visitor {
+            replacements: &mut self.replacements,
+            names,
+        }
+        .visit_file(&self.file);
+        return Ok(());
+
+        struct Visitor<'a> {
+            replacements: &'a mut BTreeMap<(LineColumn, LineColumn), String>,
+            names: &'a [String],
+        }
+
+        impl Visit<'_> for Visitor<'_> {
+            fn visit_attribute(&mut self, i: &Attribute) {
+                if !i.path.is_ident("derive") {
+                    return;
+                }
+                let paths =
+                    match i.parse_args_with(Punctuated::<syn::Path, Token![,]>::parse_terminated) {
+                        Ok(paths) => paths,
+                        Err(_) => return,
+                    };
+                let (stripped, kept) = paths.iter().partition::<Vec<_>, _>(|path| {
+                    path.segments
+                        .last()
+                        .map_or(false, |s| self.names.contains(&s.ident.to_string()))
+                });
+                if stripped.is_empty() {
+                    return;
+                }
+                let replacement = if kept.is_empty() {
+                    "".to_owned()
+                } else {
+                    quote!(#[derive(#(#kept),*)]).to_string()
+                };
+                self.replacements
+                    .insert((i.span().start(), i.span().end()), replacement);
+            }
+        }
+    }
+
+    pub(crate) fn resolve_cfgs(
+        &mut self,
+        features: &[String],
+        target: Option<&cfg_expr::targets::TargetInfo>,
+    ) -> anyhow::Result<()> {
+        self.resolve_cfgs_with(&|expr| eval_cfg(expr, features, target))
+    }
+
+    /// Resolves only the `#[cfg(..)]`s that are decided by `feature = ".."`s.
+    pub(crate) fn resolve_feature_cfgs(&mut self, features: &[String]) -> anyhow::Result<()> {
+        self.resolve_cfgs_with(&|expr| {
+            expr.eval(|pred| match pred {
+                cfg_expr::Predicate::Feature(feature) => {
+                    Some(features.contains(&(*feature).to_owned()))
+                }
+                _ => None,
+            })
+        })
+    }
+
+    fn resolve_cfgs_with(
+        &mut self,
+        eval: &dyn Fn(&cfg_expr::Expression) -> Option<bool>,
+    ) -> anyhow::Result<()> {
+        self.apply()?;
+        Visitor {
+            replacements: &mut self.replacements,
+            eval,
+        }
+        .visit_file(&self.file);
+        return Ok(());
+
+        struct Visitor<'a> {
+            replacements: &'a mut BTreeMap<(LineColumn, LineColumn), String>,
+            eval: &'a dyn Fn(&cfg_expr::Expression) -> Option<bool>,
         }
 
         impl Visitor<'_> {
@@ -1407,19 +2951,7 @@ impl<'opt> CodeEdit<'opt> {
                                 .ok()?;
                         Some((span, expr))
                     })
-                    .map(|(span, expr)| {
-                        let sufficiency = expr.eval(|pred| match pred {
-                            cfg_expr::Predicate::Test | cfg_expr::Predicate::ProcMacro => {
-                                Some(false)
-                            }
-                            cfg_expr::Predicate::Flag("cargo_equip") => Some(true),
-                            cfg_expr::Predicate::Feature(feature) => {
-                                Some(self.features.contains(&(*feature).to_owned()))
-                            }
-                            _ => None,
-                        });
-                        (span, sufficiency)
-                    })
+                    .map(|(span, expr)| (span, (self.eval)(&expr)))
                     .collect::<Vec<_>>();
 
                 if sufficiencies.iter().any(|&(_, p)| p == Some(false)) {
@@ -1548,6 +3080,187 @@ impl<'opt> CodeEdit<'opt> {
         }
     }
 
+    /// Removes the modules other than `only`, their descendants, and the modules they refer to.
+    pub(crate) fn retain_modules(&mut self, only: &[Vec<String>]) -> anyhow::Result<()> {
+        self.apply()?;
+
+        let keep = modules_to_keep(&module_deps(&self.string)?, only);
+
+        let mut found = btreeset!(vec![]);
+        remove_mods(
+            &self.file.items,
+            &mut vec![],
+            only,
+            &keep,
+            &mut found,
+            &mut self.replacements,
+        );
+        if let Some(path) = only.iter().find(|p| !found.contains(*p)) {
+            bail!("no module named `{}`", path.join("::"));
+        }
+        return Ok(());
+
+        fn remove_mods(
+            items: &[Item],
+            path: &mut Vec<String>,
+            only: &[Vec<String>],
+            keep: &BTreeMap<Vec<String>, Kept>,
+            found: &mut BTreeSet<Vec<String>>,
+            replacements: &mut BTreeMap<(LineColumn, LineColumn), String>,
+        ) {
+            for item in items {
+                if let Item::Mod(ItemMod {
+                    ident,
+                    content: Some((_, items)),
+                    ..
+                }) = item
+                {
+                    path.push(ident.to_string());
+                    if keep.contains_key(path) || only.iter().any(|o| path.starts_with(o)) {
+                        found.insert(path.clone());
+                        remove_mods(items, path, only, keep, found, replacements);
+                    } else {
+                        replacements
+                            .insert((item.span().start(), item.span().end()), "".to_owned());
+                    }
+                    path.pop();
+                }
+            }
+        }
+    }
+
+    /// Removes `#[doc(hidden)]` modules which are referred to neither from the other modules nor
+    /// as `used`, and returns their paths.
+    pub(crate) fn remove_unreachable_hidden_modules(
+        &mut self,
+        used: &[Vec<String>],
+    ) -> anyhow::Result<Vec<Vec<String>>> {
+        self.apply()?;
+
+        let modules = module_deps(&self.string)?;
+
+        let mut hidden = btreeset!();
+        find_hidden(&self.file.items, &mut vec![], &mut hidden);
+        let is_hidden = |path: &[String]| (1..=path.len()).any(|i| hidden.contains(&path[..i]));
+
+        let deps = modules
+            .iter()
+            .map(|(path, _, deps)| (path, deps))
+            .collect::<BTreeMap<_, _>>();
+        let mut reachable = btreeset!();
+        let mut queue = modules
+            .iter()
+            .map(|(path, ..)| path.clone())
+            .filter(|path| !is_hidden(path))
+            .chain(used.iter().cloned())
+            .collect::<VecDeque<_>>();
+        while let Some(path) = queue.pop_front() {
+            for i in 0..=path.len() {
+                let prefix = path[..i].to_owned();
+                if reachable.insert(prefix.clone()) {
+                    queue.extend(
+                        deps.get(&prefix)
+                            .into_iter()
+                            .flat_map(|d| d.iter().cloned()),
+                    );
+                }
+            }
+        }
+
+        let mut removed = vec![];
+        remove_mods(
+            &self.file.items,
+            &mut vec![],
+            &hidden,
+            &reachable,
+            &mut removed,
+            &mut self.replacements,
+        );
+        return Ok(removed);
+
+        fn find_hidden(items: &[Item], path: &mut Vec<String>, acc: &mut BTreeSet<Vec<String>>) {
+            for item in items {
+                if let Item::Mod(ItemMod {
+                    attrs,
+                    ident,
+                    content: Some((_, items)),
+                    ..
+                }) = item
+                {
+                    path.push(ident.to_string());
+                    if attrs.iter().any(is_doc_hidden) {
+                        acc.insert(path.clone());
+                    }
+                    find_hidden(items, path, acc);
+                    path.pop();
+                }
+            }
+        }
+
+        fn is_doc_hidden(attr: &Attribute) -> bool {
+            matches!(
+                attr.parse_meta(),
+                Ok(Meta::List(MetaList { path, nested, .. }))
+                if path.is_ident("doc") && nested.iter().any(|m| {
+                    matches!(m, NestedMeta::Meta(Meta::Path(p)) if p.is_ident("hidden"))
+                })
+            )
+        }
+
+        fn remove_mods(
+            items: &[Item],
+            path: &mut Vec<String>,
+            hidden: &BTreeSet<Vec<String>>,
+            reachable: &BTreeSet<Vec<String>>,
+            removed: &mut Vec<Vec<String>>,
+            replacements: &mut BTreeMap<(LineColumn, LineColumn), String>,
+        ) {
+            for item in items {
+                if let Item::Mod(ItemMod {
+                    ident,
+                    content: Some((_, items)),
+                    ..
+                }) = item
+                {
+                    path.push(ident.to_string());
+                    if hidden.contains(path) && !reachable.contains(path) {
+                        replacements
+                            .insert((item.span().start(), item.span().end()), "".to_owned());
+                        removed.push(path.clone());
+                    } else {
+                        remove_mods(items, path, hidden, reachable, removed, replacements);
+                    }
+                    path.pop();
+                }
+            }
+        }
+    }
+
+    /// Comments out `#![feature(..)]`s in the root module and returns the feature names.
+    pub(crate) fn take_features(&mut self) -> anyhow::Result<Vec<String>> {
+        self.apply()?;
+
+        let mut features = vec![];
+        for attr in &self.file.attrs {
+            if let (AttrStyle::Inner(_), Ok(Meta::List(MetaList { path, nested, .. }))) =
+                (attr.style, attr.parse_meta())
+            {
+                if path.is_ident("feature") {
+                    for meta in nested {
+                        if let NestedMeta::Meta(Meta::Path(path)) = meta {
+                            features.push(path.to_token_stream().to_string());
+                        }
+                    }
+                    self.replacements.insert(
+                        (attr.span().start(), attr.span().end()),
+                        format!("/*{}*/", attr.to_token_stream()),
+                    );
+                }
+            }
+        }
+        Ok(features)
+    }
+
     pub(crate) fn allow_missing_docs(&mut self) {
         Visitor {
             replacements: &mut self.replacements,
@@ -1674,7 +3387,8 @@ impl<'opt> CodeEdit<'opt> {
 
 #[cfg(test)]
 mod tests {
-    use crate::rust::CodeEdit;
+    use crate::rust::{self, CodeEdit, Kept};
+    use maplit::btreemap;
     use pretty_assertions::assert_eq;
     use proc_macro2::Span;
     use syn::Ident;
@@ -1683,6 +3397,695 @@ mod tests {
         static DUMMY_MOD_NAME: Ident = Ident::new("__", Span::call_site());
     }
 
+    #[test]
+    fn renamed_extern_crates_in_bin() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "extern crate mylib as lib;\n\nuse ::lib::f;\n",
+            )?;
+            let renames = edit.extern_crate_renames_in_bin(|name| name == "mylib")?;
+            assert_eq!(btreemap!("lib".to_owned() => "mylib".to_owned()), renames);
+            edit.translate_extern_crate_paths(|name| {
+                let name = renames.get(name).map_or(name, |s| s);
+                (name == "mylib").then(|| name.to_owned())
+            })?;
+            edit.process_extern_crate_in_bin(|name| name == "mylib")?;
+            assert_eq!(
+                "/*extern crate mylib as lib;*/use crate::__::crates::mylib as lib;\n\n\
+                 use /*::*/crate::__::crates::/*lib*/mylib::f;\n",
+                edit.finish()?,
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn rename_main() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "fn main() -> Result<(), ()> {\n    Ok(())\n}\n",
+            )?;
+            edit.rename_main("solve")?;
+            assert_eq!(
+                "pub fn solve() -> Result<(), ()> {\n    Ok(())\n}\n\n\
+                 fn main() -> Result < () , () > {\n    solve()\n}\n",
+                edit.finish()?,
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn translate_crate_path() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "pub(crate) mod a {\n    pub(in crate::a) fn f() {}\n    pub(super) fn g() {}\n}\n\n\
+                 pub(crate) use crate::a::g;\n",
+            )?;
+            edit.translate_crate_path("mylib")?;
+            assert_eq!(
+                "pub(in crate::__::crates::mylib) mod a {\n    \
+                 pub(in crate::__::crates::mylib::a) fn f() {}\n    \
+                 pub(super) fn g() {}\n}\n\n\
+                 pub(in crate::__::crates::mylib) use crate::__::crates::mylib::a::g;\n",
+                edit.finish()?,
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn translate_extern_crate_paths() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "fn f() -> u32 {\n    ::dep::m::g() + ::dep::h()\n}\n",
+            )?;
+            edit.translate_extern_crate_paths(|name| {
+                (name == "dep").then(|| "__dep_0_1_0".to_owned())
+            })?;
+            assert_eq!(
+                "fn f() -> u32 {\n    \
+                 /*::*/crate::__::crates::/*dep*/__dep_0_1_0::m::g() + \
+                 /*::*/crate::__::crates::/*dep*/__dep_0_1_0::h()\n}\n",
+                edit.finish()?,
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn translate_extern_crate_paths_in_macros() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "macro_rules! m {\n    () => {\n        ::dep::f(::std::vec::Vec::<u8>::new())\n    };\n}\n",
+            )?;
+            edit.translate_extern_crate_paths(|name| (name == "dep").then(|| "__dep_0_1_0".to_owned()))?;
+            assert_eq!(
+                "macro_rules! m {\n    () => {\n        \
+                 /*::*/crate::__::crates::/*dep*/__dep_0_1_0::f(::std::vec::Vec::<u8>::new())\n    \
+                 };\n}\n",
+                edit.finish()?,
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn translate_crate_path_in_macros() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "macro_rules! m {\n    () => {\n        crate::internal::f($crate::g())\n    };\n}\n",
+            )?;
+            edit.translate_crate_path("lib")?;
+            assert_eq!(
+                "macro_rules! m {\n    () => {\n        \
+                 crate::__::crates::lib::internal::f($crate::g())\n    };\n}\n",
+                edit.finish()?,
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn features() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "#![feature(a, b)]\n#![allow(dead_code)]\n\nfn f() {}\n",
+            )?;
+            assert_eq!(["a", "b"][..], edit.take_features()?);
+            assert_eq!(
+                "/*# ! [feature (a , b)]*/\n#![allow(dead_code)]\n\nfn f() {}\n",
+                edit.finish()?,
+            );
+            Ok::<_, anyhow::Error>(())
+        })?;
+
+        assert_eq!(
+            "#![feature(a, c)]\n#![feature(b)]\nfn main() {}\n",
+            rust::insert_features(
+                "#![feature(b)]\nfn main() {}\n",
+                &["a", "b", "c"].iter().map(|&s| s.to_owned()).collect(),
+            )?,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn modules_to_keep() -> anyhow::Result<()> {
+        let modules = rust::module_deps(
+            "pub mod a {\n    pub mod b {\n        pub fn h() {}\n    }\n    pub fn f() { crate::c::d::g(); }\n}\n\
+             pub mod c {\n    pub mod d {\n        pub fn g() {}\n    }\n}\n\
+             pub mod e {}\n",
+        )?;
+        let path = |s: &str| s.split("::").map(ToOwned::to_owned).collect::<Vec<_>>();
+        assert_eq!(
+            btreemap!(
+                vec![] => Kept::Ancestor(path("a")),
+                path("a") => Kept::Listed,
+                path("a::b") => Kept::Listed,
+                path("c") => Kept::Ancestor(path("c::d")),
+                path("c::d") => Kept::ReferredFrom(path("a")),
+            ),
+            rust::modules_to_keep(&modules, &[path("a")]),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_module_path() {
+        let expected = ("my_lib".to_owned(), vec!["a".to_owned(), "b".to_owned()]);
+        assert_eq!(expected, rust::parse_module_path("::my_lib::a::b"));
+        assert_eq!(expected, rust::parse_module_path("my-lib::a::b"));
+        assert_eq!(
+            ("my_lib".to_owned(), vec![]),
+            rust::parse_module_path("::my-lib"),
+        );
+    }
+
+    #[test]
+    fn retain_modules() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "pub mod a {\n    pub fn f() { crate::b::g(); }\n    pub mod c {}\n}\n\
+                 pub mod b {\n    pub fn g() {}\n}\n\
+                 pub mod d {\n    pub mod e {}\n}\n",
+            )?;
+            edit.retain_modules(&[vec!["a".to_owned()]])?;
+            assert_eq!(
+                "pub mod a {\n    pub fn f() { crate::b::g(); }\n    pub mod c {}\n}\n\
+                 pub mod b {\n    pub fn g() {}\n}\n\n\n\n",
+                edit.finish()?,
+            );
+            Ok::<_, anyhow::Error>(())
+        })
+    }
+
+    #[test]
+    fn remove_unreachable_hidden_modules() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "pub fn f() { helper::g(); }\n\
+                 #[doc(hidden)]\npub mod helper {\n    pub fn g() {}\n}\n\
+                 #[doc(hidden)]\npub mod internal {\n    pub fn h() {}\n}\n\
+                 #[doc(hidden)]\npub mod used {\n    pub fn i() {}\n}\n",
+            )?;
+            let removed = edit.remove_unreachable_hidden_modules(&[vec!["used".to_owned()]])?;
+            assert_eq!([vec!["internal".to_owned()]], *removed);
+            assert_eq!(
+                "pub fn f() { helper::g(); }\n\
+                 #[doc(hidden)]\npub mod helper {\n    pub fn g() {}\n}\n\n\n\n\n\
+                 #[doc(hidden)]\npub mod used {\n    pub fn i() {}\n}\n",
+                edit.finish()?,
+            );
+            Ok::<_, anyhow::Error>(())
+        })
+    }
+
+    #[test]
+    fn normalize_core_alloc() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "extern crate alloc;\n\
+                 use alloc::vec::Vec;\n\
+                 use ::core::cmp;\n\
+                 fn f(_: core::num::Wrapping<u32>) { a::core::g(); }\n\
+                 macro_rules! m { () => { $crate::core::h(); ::core::panic!(); }; }\n",
+            )?;
+            edit.normalize_core_alloc()?;
+            assert_eq!(
+                "\n\
+                 use /*alloc*/std::vec::Vec;\n\
+                 use ::/*core*/std::cmp;\n\
+                 fn f(_: /*core*/std::num::Wrapping<u32>) { a::core::g(); }\n\
+                 macro_rules! m { () => { $crate::core::h(); ::/*core*/std::panic!(); }; }\n",
+                edit.finish()?,
+            );
+
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "mod core { pub fn f() {} }\nfn g() { core::f(); alloc::h(); }\n",
+            )?;
+            edit.normalize_core_alloc()?;
+            assert_eq!(
+                "mod core { pub fn f() {} }\nfn g() { core::f(); /*alloc*/std::h(); }\n",
+                edit.finish()?,
+            );
+            Ok::<_, anyhow::Error>(())
+        })
+    }
+
+    #[test]
+    fn min_rustc_probe() {
+        let probe = |v| super::min_rustc_probe(&semver::Version::parse(v).unwrap());
+        assert_eq!(None, probe("1.35.0"));
+        assert_eq!((1, 70), probe("1.70.0").unwrap().0);
+        assert_eq!((1, 70), probe("1.72.1").unwrap().0);
+        assert_eq!(
+            "/// Fails to compile with rustc older than 1.53.\n\
+             #[allow(dead_code)]\n\
+             fn __cargo_equip_requires_rustc_1_53() {\n    let _ = u32::BITS;\n}\n",
+            probe("1.53.0").unwrap().1,
+        );
+    }
+
+    #[test]
+    fn publish_main() -> anyhow::Result<()> {
+        assert_eq!(
+            ("#[inline]\npub(crate) fn main() {}\n".to_owned(), false),
+            super::publish_main("#[inline]\nfn main() {}\n")?,
+        );
+        assert_eq!(
+            (
+                "pub fn main() -> Result<(), ()> {\n    Ok(())\n}\n".to_owned(),
+                true
+            ),
+            super::publish_main("pub fn main() -> Result<(), ()> {\n    Ok(())\n}\n")?,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn insert_after_inner_attrs() -> anyhow::Result<()> {
+        assert_eq!(
+            "fn f() {}\n\nfn main() {}\n",
+            super::insert_after_inner_attrs("fn main() {}\n", "fn f() {}\n")?,
+        );
+        assert_eq!(
+            "#![allow(dead_code)]\n#![cfg_attr(a, b)]\nfn f() {}\n\nfn main() {}\n",
+            super::insert_after_inner_attrs(
+                "#![allow(dead_code)]\n#![cfg_attr(a, b)]\nfn main() {}\n",
+                "fn f() {}\n",
+            )?,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn find_extern_crate_refs() -> syn::Result<()> {
+        let found = super::find_extern_crate_refs(
+            "extern crate a;\nuse ::b::{x, y as _};\nfn main() { ::c::f(); ::std::process::exit(0); }\n",
+        )?
+        .into_iter()
+        .map(|(pos, name)| (pos.line, pos.column, name))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            [
+                (1, 0, "a".to_owned()),
+                (2, 0, "b".to_owned()),
+                (3, 12, "c".to_owned()),
+                (3, 22, "std".to_owned()),
+            ],
+            *found,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn find_macro_calls() -> anyhow::Result<()> {
+        let found = super::find_macro_calls(
+            "fn main() {\n    println!(\"{}\", include!(\"a\"));\n    let include = 1;\n}\n",
+            &["include".to_owned(), "asm".to_owned()],
+        )?;
+        assert_eq!(
+            [(
+                proc_macro2::LineColumn {
+                    line: 2,
+                    column: 19
+                },
+                "include".to_owned()
+            )],
+            *found,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn deprecated_items() -> anyhow::Result<()> {
+        let items = super::deprecated_items(
+            "#[deprecated]\npub fn f() {}\n\
+             pub mod a {\n    #[deprecated(since = \"0.2.0\", note = \"use `h`\")]\n    pub fn g() {}\n}\n\
+             #[deprecated = \"gone\"]\npub struct S;\n",
+        )?;
+        assert_eq!(
+            [
+                (vec!["f".to_owned()], None),
+                (
+                    vec!["a".to_owned(), "g".to_owned()],
+                    Some("use `h`".to_owned())
+                ),
+                (vec!["S".to_owned()], Some("gone".to_owned())),
+            ],
+            *items,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn retain_modules_with_cycle() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "pub mod a {\n    pub fn f() { crate::b::g(); }\n}\n\
+                 pub mod b {\n    pub fn g() { crate::a::f(); }\n}\n\
+                 pub mod c {}\n",
+            )?;
+            edit.retain_modules(&[vec!["a".to_owned()]])?;
+            assert_eq!(
+                "pub mod a {\n    pub fn f() { crate::b::g(); }\n}\n\
+                 pub mod b {\n    pub fn g() { crate::a::f(); }\n}\n\n",
+                edit.finish()?,
+            );
+            Ok::<_, anyhow::Error>(())
+        })
+    }
+
+    #[test]
+    fn resolve_cfgs_with_target() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let code = "#[cfg(target_pointer_width = \"64\")]\nfn a() {}\n\
+                        #[cfg(windows)]\nfn b() {}\n";
+
+            let mut edit = CodeEdit::from_code(dummy_mod_name, code)?;
+            edit.resolve_cfgs(&[], None)?;
+            assert_eq!(code, edit.finish()?);
+
+            let target =
+                cfg_expr::targets::get_builtin_target_by_triple("x86_64-unknown-linux-gnu");
+            let mut edit = CodeEdit::from_code(dummy_mod_name, code)?;
+            edit.resolve_cfgs(&[], target)?;
+            assert_eq!("\nfn a() {}\n\n\n", edit.finish()?);
+            Ok::<_, anyhow::Error>(())
+        })
+    }
+
+    #[test]
+    fn fold_cfg_macros() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "fn f() -> (bool, bool, bool) {\n    \
+                 (cfg!(feature = \"a\"), cfg!(all(feature = \"a\", feature = \"b\")), cfg!(debug_assertions))\n}\n",
+            )?;
+            edit.fold_cfg_macros(&["a".to_owned()], None)?;
+            assert_eq!(
+                "fn f() -> (bool, bool, bool) {\n    (true, false, cfg!(debug_assertions))\n}\n",
+                edit.finish()?,
+            );
+            Ok::<_, anyhow::Error>(())
+        })
+    }
+
+    #[test]
+    fn strip_derives() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "#[derive(Clone, Copy, Debug)]\nstruct A;\n\
+                 #[derive(std::fmt::Debug)]\nenum B {}\n",
+            )?;
+            edit.strip_derives(&["Debug".to_owned()])?;
+            assert_eq!(
+                "# [derive (Clone , Copy)]\nstruct A;\n\nenum B {}\n",
+                edit.finish()?,
+            );
+            Ok::<_, anyhow::Error>(())
+        })
+    }
+
+    #[test]
+    fn facade_target() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let prelude = format!(
+                "pub(in crate::{0}) use crate::{0}::crates::__inner_0_1_0 as inner;\n",
+                dummy_mod_name,
+            );
+            let crate_mod = format!(
+                "use crate::{}::preludes::facade::*;\npub use inner::*;\n",
+                dummy_mod_name,
+            );
+            assert_eq!(
+                Some("__inner_0_1_0".to_owned()),
+                rust::facade_target(dummy_mod_name, &crate_mod, "", &prelude)?,
+            );
+            assert_eq!(
+                None,
+                rust::facade_target(
+                    dummy_mod_name,
+                    &format!("{}pub fn f() {{}}\n", crate_mod),
+                    "",
+                    &prelude,
+                )?,
+            );
+            assert_eq!(
+                None,
+                rust::facade_target(dummy_mod_name, "pub use std::*;\n", "", &prelude)?,
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn extract_embedded_manifest() {
+        assert_eq!(
+            Some((
+                "[dependencies]\nmylib = { path = \"../mylib\" }\n".to_owned(),
+                "#!/usr/bin/env cargo\nfn main() {}\n".to_owned(),
+            )),
+            rust::extract_embedded_manifest(
+                "#!/usr/bin/env cargo\n---cargo\n[dependencies]\nmylib = { path = \"../mylib\" }\n---\n\
+                 fn main() {}\n",
+            ),
+        );
+        assert_eq!(
+            Some((
+                "[dependencies]\nmylib = \"0.1\"\n".to_owned(),
+                "//! ```cargo\n//! [dependencies]\n//! mylib = \"0.1\"\n//! ```\n\nfn main() {}\n"
+                    .to_owned(),
+            )),
+            rust::extract_embedded_manifest(
+                "//! ```cargo\n//! [dependencies]\n//! mylib = \"0.1\"\n//! ```\n\nfn main() {}\n",
+            ),
+        );
+        assert_eq!(None, rust::extract_embedded_manifest("fn main() {}\n"));
+    }
+
+    #[test]
+    fn insert_prelude_for_main_crate() -> syn::Result<()> {
+        let code = "use a::b;\nmod m {\n    fn f() {}\n}\n";
+        let mod_name = &syn::parse_str::<syn::Ident>("__cargo_equip")?;
+        assert_eq!(
+            "pub use __cargo_equip::prelude::*;\n\nuse a::b;\nmod m {\n    pub use crate::__cargo_equip::prelude::*;\n\nfn f() {}\n}\n",
+            rust::insert_prelude_for_main_crate(code, mod_name, true)?,
+        );
+        assert_eq!(
+            "use a::b;\nmod m {\n    pub use crate::__cargo_equip::prelude::*;\n\nfn f() {}\n}\n",
+            rust::insert_prelude_for_main_crate(code, mod_name, false)?,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dedup_uses() -> syn::Result<()> {
+        assert_eq!(
+            "use a::{b, c};\n\npub use a::b;\nmod m {\n    use a::*;\n    \n}\n",
+            rust::dedup_uses(
+                "use a::{b, c};\nuse a::c;\npub use a::b;\nmod m {\n    use a::*;\n    use a::*;\n}\n",
+            )?,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn find_io_apis() -> syn::Result<()> {
+        let found = rust::find_io_apis(
+            "use std::{fs::File, io};\nmod m {\n    fn f() {\n        ::std::env::args();\n    }\n}\n",
+        )?
+        .into_iter()
+        .map(|(pos, mod_path, api)| (pos.line, mod_path, api))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            [
+                (1, "crate".to_owned(), "std::fs".to_owned()),
+                (4, "crate::m".to_owned(), "std::env".to_owned()),
+            ][..],
+            found,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn render_syn_error() {
+        let code = "fn main() {\n    let x = 1 2;\n}\n";
+        let err = syn::parse_file(code).unwrap_err();
+        assert_eq!(
+            "expected `;`\n --> src/main.rs:2:15\n  |\n2 |     let x = 1 2;\n  |               ^",
+            rust::render_syn_error("src/main.rs", code, &err),
+        );
+    }
+
+    #[test]
+    fn update_regions() -> anyhow::Result<()> {
+        let old = "fn main() {\n    edited();\n}\n\
+                   mod m {\n    // === cargo-equip: begin a ===\n    pub mod a {}\n    \
+                   // === cargo-equip: end a ===\n}\n";
+        let new = "fn main() {}\n\
+                   mod m {\n    // === cargo-equip: begin a ===\n    pub mod a { fn f() {} }\n    \
+                   // === cargo-equip: end a ===\n}\n";
+        assert_eq!(
+            "fn main() {\n    edited();\n}\n\
+             mod m {\n    // === cargo-equip: begin a ===\n    pub mod a { fn f() {} }\n    \
+             // === cargo-equip: end a ===\n}\n",
+            rust::update_regions(old, new)?,
+        );
+        assert!(rust::update_regions(old, "fn main() {}\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn expand_includes() -> anyhow::Result<()> {
+        let out_dir = tempfile::Builder::new()
+            .prefix("cargo-equip-test-")
+            .tempdir()?;
+        let out_dir = camino::Utf8Path::from_path(out_dir.path()).unwrap();
+        cargo_util::paths::write(out_dir.join("items.rs"), "pub struct A;\n")?;
+        cargo_util::paths::write(out_dir.join("table.rs"), "[1, 2, 3]\n")?;
+
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "include!(concat!(env!(\"OUT_DIR\"), \"/items.rs\"));\n\n\
+                 static TABLE: [u32; 3] = ::std::include!(concat!(env!(\"OUT_DIR\"), \"/table.rs\"));\n",
+            )?;
+            edit.expand_includes(out_dir)?;
+            assert_eq!(
+                "pub struct A;\n\n\nstatic TABLE: [u32; 3] = ([1, 2, 3]);\n",
+                edit.finish()?,
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn inline_assets() -> anyhow::Result<()> {
+        let src_dir = tempfile::Builder::new()
+            .prefix("cargo-equip-test-")
+            .tempdir()?;
+        let src_dir = camino::Utf8Path::from_path(src_dir.path()).unwrap();
+        let lib_rs = &src_dir.join("lib.rs");
+        cargo_util::paths::write(
+            lib_rs,
+            "mod a;\n\npub static B: &[u8] = include_bytes!(\"b.bin\");\n",
+        )?;
+        cargo_util::paths::create_dir_all(src_dir.join("a"))?;
+        cargo_util::paths::write(
+            src_dir.join("a").join("mod.rs"),
+            "pub fn s() -> String {\n    format!(\"{}\", include_str!(\"s.txt\"))\n}\n",
+        )?;
+        cargo_util::paths::write(src_dir.join("a").join("s.txt"), "\"s\"\n")?;
+        cargo_util::paths::write(src_dir.join("b.bin"), [0, 255])?;
+
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::new(dummy_mod_name, lib_rs, || unreachable!())?;
+            let assets = edit.inline_assets(lib_rs)?;
+            assert_eq!(
+                [(src_dir.join("a").join("s.txt"), 4), (src_dir.join("b.bin"), 2)],
+                *assets,
+            );
+            assert_eq!(
+                "mod a {\n    pub fn s() -> String {\n        format!(\"{}\", /*include_str!(..)*/\"\\\"s\\\"\\n\")\n    }\n    }\n\n\
+                 pub static B: &[u8] = /*include_bytes!(..)*/b\"\\0\\xFF\";\n",
+                edit.finish()?,
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn strip_debug() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "fn f(x: u32) -> u32 {\n    eprintln!(\"{}\", x);\n    debug_assert!(x > 0);\n    \
+                 let y = dbg!(x + 1);\n    dbg!(y);\n    match x { 0 => eprint!(\"0\"), _ => {} }\n    y\n}\n",
+            )?;
+            edit.strip_debug(true, false)?;
+            assert_eq!(
+                "fn f(x: u32) -> u32 {\n    \n    debug_assert!(x > 0);\n    \
+                 let y = (x + 1);\n    (y);\n    match x { 0 => (), _ => {} }\n    y\n}\n",
+                edit.finish()?,
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn define_env_vars() -> anyhow::Result<()> {
+        DUMMY_MOD_NAME.with(|dummy_mod_name| {
+            let mut edit = CodeEdit::from_code(
+                dummy_mod_name,
+                "const A: &str = env!(\"A\");\n\
+                 const B: Option<&str> = option_env!(\"B\");\n\
+                 fn f() { println!(\"{}\", env!(\"A\", \"msg\")); }\n\
+                 const C: &str = env!(\"C\");\n",
+            )?;
+            edit.define_env_vars(&[
+                ("A".to_owned(), "a".to_owned()),
+                ("B".to_owned(), "b".to_owned()),
+            ])?;
+            assert_eq!(
+                "const A: &str = /*env!(..)*/\"a\";\n\
+                 const B: Option<&str> = /*option_env!(..)*/:: std :: option :: Option :: Some (\"b\");\n\
+                 fn f() { println!(\"{}\", /*env!(..)*/\"a\"); }\n\
+                 const C: &str = env!(\"C\");\n",
+                edit.finish()?,
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn split_into_modules() -> anyhow::Result<()> {
+        let modules = rust::split_into_modules(
+            r#"pub mod a {
+    pub mod b {
+        pub fn f() {}
+    }
+
+    pub fn g() {
+        b::f();
+    }
+}
+
+pub fn h() {}
+"#,
+        )?;
+        assert_eq!(
+            vec![
+                (vec![], "pub fn h() {}\n".to_owned()),
+                (
+                    vec!["a".to_owned()],
+                    "pub fn g() {\n    b::f();\n}\n".to_owned()
+                ),
+                (
+                    vec!["a".to_owned(), "b".to_owned()],
+                    "pub fn f() {}\n".to_owned(),
+                ),
+            ],
+            modules,
+        );
+        Ok(())
+    }
+
     #[test]
     fn erase_docs() -> anyhow::Result<()> {
         fn test(input: &str, expected: &str) -> anyhow::Result<()> {
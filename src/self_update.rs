@@ -0,0 +1,396 @@
+use crate::{process::ProcessBuilderExt as _, shell::Shell};
+use anyhow::{anyhow, bail, Context as _};
+use cargo_util::ProcessBuilder;
+use itertools::Itertools as _;
+use serde::Deserialize;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+pub(crate) fn self_update(
+    current_exe: &Path,
+    dry_run: bool,
+    cwd: &Path,
+    shell: &mut Shell,
+) -> anyhow::Result<()> {
+    let current = semver::Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
+
+    let latest_release = env!("CARGO_PKG_REPOSITORY")
+        .replace("https://github.com/", "https://api.github.com/repos/")
+        + "/releases/latest";
+    shell.status("Fetching", &latest_release)?;
+    let release = curl(&[&latest_release], cwd)?;
+    let release = serde_json::from_str::<Release>(&release)
+        .with_context(|| format!("could not parse the output from {}", latest_release))?;
+    let tag_name = &release.tag_name;
+    let latest = semver::Version::parse(tag_name.trim_start_matches('v'))
+        .with_context(|| format!("could not parse the tag name `{}`", tag_name))?;
+
+    if latest <= current {
+        shell.status("Fresh", format!("cargo-equip v{} is the latest", current))?;
+        return Ok(());
+    }
+
+    let triple =
+        host_triple(env::consts::ARCH, env::consts::OS, TARGET_ENV).with_context(|| {
+            format!(
+                "no prebuilt binary is provided for {}-{}. run `cargo install cargo-equip` instead",
+                env::consts::ARCH,
+                env::consts::OS,
+            )
+        })?;
+    let (asset, checksum) = select_asset(&release, &triple)?;
+
+    if dry_run {
+        shell.status(
+            "Would update",
+            format!(
+                "`{}` from v{} to v{}",
+                current_exe.display(),
+                current,
+                latest
+            ),
+        )?;
+        shell.status("Would download", &asset.browser_download_url)?;
+        return Ok(());
+    }
+
+    let tempdir = tempfile::Builder::new()
+        .prefix("cargo-equip-self-update-")
+        .tempdir()?;
+    let archive = tempdir.path().join(&asset.name);
+
+    shell.status("Downloading", &asset.browser_download_url)?;
+    curl(
+        &[
+            &asset.browser_download_url,
+            "-o",
+            &archive.to_string_lossy(),
+        ],
+        cwd,
+    )?;
+
+    let expected = match checksum {
+        Checksum::Digest(digest) => digest.to_owned(),
+        Checksum::File(url) => curl(&[url], cwd)?,
+    };
+    verify_checksum(&asset.name, &expected, &sha256(&archive, tempdir.path())?)?;
+    shell.status("Verified", format!("SHA-256 of `{}`", asset.name))?;
+
+    ProcessBuilder::new(which::which("tar").map_err(|_| anyhow!("command not found: tar"))?)
+        .arg("-xf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(tempdir.path())
+        .cwd(tempdir.path())
+        .exec()?;
+    let new_exe = tempdir
+        .path()
+        .join(asset_stem(tag_name, &triple))
+        .join("cargo-equip")
+        .with_extension(env::consts::EXE_EXTENSION);
+    if !new_exe.exists() {
+        bail!("`{}` does not contain `{}`", asset.name, new_exe.display());
+    }
+
+    replace_exe(current_exe, &new_exe)?;
+    shell.status(
+        "Updated",
+        format!(
+            "`{}` from v{} to v{}",
+            current_exe.display(),
+            current,
+            latest
+        ),
+    )?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+    /// `sha256:{hex}`, which GitHub computes for recently uploaded assets.
+    #[serde(default)]
+    digest: Option<String>,
+}
+
+/// Where the expected SHA-256 of an asset comes from.
+#[derive(Debug, PartialEq)]
+enum Checksum<'a> {
+    /// URL of `{asset}.sha256` attached to the release.
+    File(&'a str),
+    Digest(&'a str),
+}
+
+#[cfg(target_env = "musl")]
+const TARGET_ENV: &str = "musl";
+#[cfg(target_env = "gnu")]
+const TARGET_ENV: &str = "gnu";
+#[cfg(target_env = "msvc")]
+const TARGET_ENV: &str = "msvc";
+#[cfg(not(any(target_env = "musl", target_env = "gnu", target_env = "msvc")))]
+const TARGET_ENV: &str = "";
+
+/// The target triple of the prebuilt binary that runs on the host.
+fn host_triple(arch: &str, os: &str, target_env: &str) -> Option<String> {
+    let arch = match arch {
+        "x86_64" | "aarch64" | "x86" | "arm" | "riscv64" => arch,
+        _ => return None,
+    };
+    let rest = match (os, target_env) {
+        ("linux", "musl") => "unknown-linux-musl",
+        ("linux", _) => "unknown-linux-gnu",
+        ("macos", _) => "apple-darwin",
+        ("windows", "gnu") => "pc-windows-gnu",
+        ("windows", _) => "pc-windows-msvc",
+        ("freebsd", _) => "unknown-freebsd",
+        _ => return None,
+    };
+    Some(format!("{}-{}", arch, rest))
+}
+
+fn asset_stem(tag_name: &str, triple: &str) -> String {
+    format!("cargo-equip-{}-{}", tag_name, triple)
+}
+
+/// Finds the archive for `triple` in the release and how to verify it.
+///
+/// Fails if the release provides neither `{asset}.sha256` nor a digest for it.
+fn select_asset<'a>(
+    release: &'a Release,
+    triple: &str,
+) -> anyhow::Result<(&'a Asset, Checksum<'a>)> {
+    let Release { tag_name, assets } = release;
+
+    let ext = if triple.contains("-windows-") {
+        "zip"
+    } else {
+        "tar.gz"
+    };
+    let name = format!("{}.{}", asset_stem(tag_name, triple), ext);
+    let asset = assets.iter().find(|a| a.name == name).with_context(|| {
+        let prefix = asset_stem(tag_name, "");
+        format!(
+            "`{}` has no prebuilt binary for `{}`. available: {}. run `cargo install \
+             cargo-equip` instead",
+            tag_name,
+            triple,
+            assets
+                .iter()
+                .filter(|a| !a.name.ends_with(".sha256"))
+                .flat_map(|a| a.name.strip_prefix(&prefix))
+                .map(|s| s.trim_end_matches(".tar.gz").trim_end_matches(".zip"))
+                .map(|s| format!("`{}`", s))
+                .format(", "),
+        )
+    })?;
+
+    let checksum_name = format!("{}.sha256", name);
+    if let Some(file) = assets.iter().find(|a| a.name == checksum_name) {
+        Ok((asset, Checksum::File(&file.browser_download_url)))
+    } else if let Some(digest) = asset
+        .digest
+        .as_deref()
+        .and_then(|d| d.strip_prefix("sha256:"))
+    {
+        Ok((asset, Checksum::Digest(digest)))
+    } else {
+        bail!(
+            "`{}` provides no checksum for `{}`, so it cannot be verified. run `cargo install \
+             cargo-equip` instead",
+            tag_name,
+            name,
+        );
+    }
+}
+
+/// `expected` is either a bare hex digest or the content of a `.sha256` file, which is the output
+/// of `sha256sum`.
+fn verify_checksum(name: &str, expected: &str, actual: &str) -> anyhow::Result<()> {
+    let expected = expected
+        .split_whitespace()
+        .next()
+        .filter(|s| s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit()))
+        .with_context(|| format!("invalid checksum for `{}`: {:?}", name, expected))?;
+    if !expected.eq_ignore_ascii_case(actual) {
+        bail!(
+            "checksum mismatch for `{}`: expected {}, got {}",
+            name,
+            expected,
+            actual,
+        );
+    }
+    Ok(())
+}
+
+fn curl(args: &[&str], cwd: &Path) -> anyhow::Result<String> {
+    let curl_exe = which::which("curl").map_err(|_| anyhow!("command not found: curl"))?;
+    ProcessBuilder::new(curl_exe)
+        .args(args)
+        .args(&["-L", "--fail", "--silent", "--show-error"])
+        .cwd(cwd)
+        .read_stdout()
+}
+
+fn sha256(path: &Path, cwd: &Path) -> anyhow::Result<String> {
+    let mut cmd = if let Ok(sha256sum) = which::which("sha256sum") {
+        ProcessBuilder::new(sha256sum)
+    } else if let Ok(shasum) = which::which("shasum") {
+        let mut cmd = ProcessBuilder::new(shasum);
+        cmd.args(&["-a", "256"]);
+        cmd
+    } else {
+        bail!("command not found: sha256sum or shasum");
+    };
+    let output = cmd.arg(path).cwd(cwd).read_stdout::<String>()?;
+    output
+        .split_whitespace()
+        .next()
+        .map(ToOwned::to_owned)
+        .with_context(|| format!("could not compute the checksum of `{}`", path.display()))
+}
+
+/// Replaces the executable, keeping the old one in place until the new one has been moved.
+fn replace_exe(current_exe: &Path, new_exe: &Path) -> anyhow::Result<()> {
+    let with_suffix = |suffix: &str| {
+        let mut path = current_exe.as_os_str().to_owned();
+        path.push(suffix);
+        PathBuf::from(path)
+    };
+    let staged = with_suffix(".new");
+    let old = with_suffix(".old");
+
+    fs::copy(new_exe, &staged)
+        .with_context(|| format!("could not copy the new binary to `{}`", staged.display()))?;
+    fs::rename(current_exe, &old)
+        .with_context(|| format!("could not move `{}`", current_exe.display()))?;
+    if let Err(err) = fs::rename(&staged, current_exe) {
+        let _ = fs::rename(&old, current_exe);
+        return Err(err).with_context(|| format!("could not replace `{}`", current_exe.display()));
+    }
+    // This fails on Windows while the old binary is running.
+    let _ = fs::remove_file(&old);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Asset, Checksum, Release};
+    use pretty_assertions::assert_eq;
+
+    fn release(assets: &[(&str, Option<&str>)]) -> Release {
+        Release {
+            tag_name: "v0.20.0".to_owned(),
+            assets: assets
+                .iter()
+                .map(|(name, digest)| Asset {
+                    name: (*name).to_owned(),
+                    browser_download_url: format!("https://example.com/{}", name),
+                    digest: digest.map(ToOwned::to_owned),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn host_triple() {
+        use super::host_triple;
+
+        assert_eq!(
+            Some("x86_64-unknown-linux-gnu"),
+            host_triple("x86_64", "linux", "gnu").as_deref(),
+        );
+        assert_eq!(
+            Some("aarch64-unknown-linux-musl"),
+            host_triple("aarch64", "linux", "musl").as_deref(),
+        );
+        assert_eq!(
+            Some("aarch64-apple-darwin"),
+            host_triple("aarch64", "macos", "").as_deref(),
+        );
+        assert_eq!(
+            Some("x86_64-pc-windows-msvc"),
+            host_triple("x86_64", "windows", "msvc").as_deref(),
+        );
+        assert_eq!(None, host_triple("mips", "linux", "gnu"));
+        assert_eq!(None, host_triple("x86_64", "solaris", ""));
+    }
+
+    #[test]
+    fn select_asset() {
+        let release = release(&[
+            ("cargo-equip-v0.20.0-x86_64-unknown-linux-gnu.tar.gz", None),
+            (
+                "cargo-equip-v0.20.0-x86_64-unknown-linux-gnu.tar.gz.sha256",
+                None,
+            ),
+            (
+                "cargo-equip-v0.20.0-x86_64-pc-windows-msvc.zip",
+                Some("sha256:0123"),
+            ),
+            ("cargo-equip-v0.20.0-x86_64-apple-darwin.tar.gz", None),
+        ]);
+
+        let (asset, checksum) = super::select_asset(&release, "x86_64-unknown-linux-gnu").unwrap();
+        assert_eq!(
+            "cargo-equip-v0.20.0-x86_64-unknown-linux-gnu.tar.gz",
+            asset.name,
+        );
+        assert_eq!(
+            Checksum::File(
+                "https://example.com/cargo-equip-v0.20.0-x86_64-unknown-linux-gnu.tar.gz.sha256",
+            ),
+            checksum,
+        );
+
+        let (asset, checksum) = super::select_asset(&release, "x86_64-pc-windows-msvc").unwrap();
+        assert_eq!("cargo-equip-v0.20.0-x86_64-pc-windows-msvc.zip", asset.name);
+        assert_eq!(Checksum::Digest("0123"), checksum);
+
+        let err = super::select_asset(&release, "x86_64-apple-darwin")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("provides no checksum"));
+
+        let err = super::select_asset(&release, "aarch64-unknown-linux-gnu")
+            .err()
+            .unwrap();
+        assert_eq!(
+            "`v0.20.0` has no prebuilt binary for `aarch64-unknown-linux-gnu`. available: \
+             `x86_64-unknown-linux-gnu`, `x86_64-pc-windows-msvc`, `x86_64-apple-darwin`. run \
+             `cargo install cargo-equip` instead",
+            err.to_string(),
+        );
+    }
+
+    #[test]
+    fn verify_checksum() {
+        const SHA256: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+        assert!(super::verify_checksum("a", SHA256, SHA256).is_ok());
+        assert!(super::verify_checksum("a", &SHA256.to_uppercase(), SHA256).is_ok());
+        assert!(super::verify_checksum("a", &format!("{}  ./a\n", SHA256), SHA256).is_ok());
+        assert!(super::verify_checksum("a", &SHA256.replace('9', "0"), SHA256).is_err());
+        assert!(super::verify_checksum("a", "", SHA256).is_err());
+        assert!(super::verify_checksum("a", "0123", "0123").is_err());
+    }
+
+    #[test]
+    fn sha256() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = &dir.path().join("test");
+        std::fs::write(path, "test").unwrap();
+        assert_eq!(
+            "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08",
+            super::sha256(path, dir.path()).unwrap(),
+        );
+    }
+}
@@ -1,38 +1,61 @@
 use std::{
     fmt,
-    io::{self, Sink, Write},
+    io::{self, Write},
 };
 use termcolor::{Color, ColorSpec, NoColor, StandardStream, WriteColor};
 
 pub struct Shell {
-    output: ShellOut,
+    stdout: Box<dyn Write>,
+    stderr: Box<dyn WriteColor>,
+    quiet: bool,
+    hide_statuses: bool,
 }
 
 impl Shell {
     pub fn new() -> Self {
-        Self {
-            output: ShellOut::stream(),
-        }
+        Self::from_writers(
+            Box::new(StandardStream::stdout(color_choice(atty::Stream::Stdout))),
+            Box::new(StandardStream::stderr(color_choice(atty::Stream::Stderr))),
+        )
+    }
+
+    /// Creates a `Shell` which writes the output to `stdout` and discards the messages.
+    pub fn from_stdout(stdout: Box<dyn Write>) -> Self {
+        Self::from_writers(stdout, Box::new(NoColor::new(io::sink())))
     }
 
-    pub fn from_stdout(stdout: Box<dyn Write>) -> Shell {
+    /// Creates a `Shell` which writes the output to `stdout` and the messages to `stderr`.
+    ///
+    /// Wrap `stderr` with [`NoColor`] to capture the messages as plain text.
+    pub fn from_writers(stdout: Box<dyn Write>, stderr: Box<dyn WriteColor>) -> Self {
         Self {
-            output: ShellOut::write(stdout),
+            stdout,
+            stderr,
+            quiet: false,
+            hide_statuses: false,
         }
     }
 
+    /// Suppresses statuses and warnings. Errors are still printed.
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    pub(crate) fn is_quiet(&self) -> bool {
+        self.quiet
+    }
+
+    /// Suppresses statuses only.
+    pub(crate) fn set_hide_statuses(&mut self, hide_statuses: bool) {
+        self.hide_statuses = hide_statuses;
+    }
+
     pub(crate) fn out(&mut self) -> &mut dyn Write {
-        match &mut self.output {
-            ShellOut::Stream { stdout, .. } => stdout,
-            ShellOut::Write { stdout, .. } => stdout,
-        }
+        &mut self.stdout
     }
 
     pub fn err(&mut self) -> &mut dyn Write {
-        match &mut self.output {
-            ShellOut::Stream { stderr, .. } => stderr,
-            ShellOut::Write { stderr, .. } => stderr,
-        }
+        &mut self.stderr
     }
 
     pub(crate) fn status(
@@ -40,10 +63,16 @@ impl Shell {
         status: impl fmt::Display,
         message: impl fmt::Display,
     ) -> io::Result<()> {
+        if self.quiet || self.hide_statuses {
+            return Ok(());
+        }
         self.print(status, message, Color::Green, true)
     }
 
-    pub(crate) fn warn(&mut self, message: impl fmt::Display) -> io::Result<()> {
+    pub fn warn(&mut self, message: impl fmt::Display) -> io::Result<()> {
+        if self.quiet {
+            return Ok(());
+        }
         self.print("warning", message, Color::Yellow, false)
     }
 
@@ -58,31 +87,17 @@ impl Shell {
         color: Color,
         justified: bool,
     ) -> io::Result<()> {
-        return match &mut self.output {
-            ShellOut::Stream { stderr, .. } => print(stderr, status, message, color, justified),
-            ShellOut::Write { .. } => {
-                print(NoColor::new(io::sink()), status, message, color, justified)
-            }
-        };
-
-        fn print(
-            mut stderr: impl WriteColor,
-            status: impl fmt::Display,
-            message: impl fmt::Display,
-            color: Color,
-            justified: bool,
-        ) -> io::Result<()> {
-            stderr.set_color(ColorSpec::new().set_bold(true).set_fg(Some(color)))?;
-            if justified {
-                write!(stderr, "{:>12}", status)?;
-            } else {
-                write!(stderr, "{}", status)?;
-                stderr.set_color(ColorSpec::new().set_bold(true))?;
-                write!(stderr, ":")?;
-            }
-            stderr.reset()?;
-            writeln!(stderr, " {}", message)
+        let stderr = &mut self.stderr;
+        stderr.set_color(ColorSpec::new().set_bold(true).set_fg(Some(color)))?;
+        if justified {
+            write!(stderr, "{:>12}", status)?;
+        } else {
+            write!(stderr, "{}", status)?;
+            stderr.set_color(ColorSpec::new().set_bold(true))?;
+            write!(stderr, ":")?;
         }
+        stderr.reset()?;
+        writeln!(stderr, " {}", message)
     }
 }
 
@@ -92,37 +107,10 @@ impl Default for Shell {
     }
 }
 
-enum ShellOut {
-    Stream {
-        stdout: StandardStream,
-        stderr: StandardStream,
-    },
-    Write {
-        stdout: Box<dyn Write>,
-        stderr: Sink,
-    },
-}
-
-impl ShellOut {
-    fn stream() -> Self {
-        Self::Stream {
-            stdout: StandardStream::stdout(if atty::is(atty::Stream::Stdout) {
-                termcolor::ColorChoice::Auto
-            } else {
-                termcolor::ColorChoice::Never
-            }),
-            stderr: StandardStream::stderr(if atty::is(atty::Stream::Stderr) {
-                termcolor::ColorChoice::Auto
-            } else {
-                termcolor::ColorChoice::Never
-            }),
-        }
-    }
-
-    fn write(stdout: Box<dyn Write>) -> Self {
-        Self::Write {
-            stdout,
-            stderr: io::sink(),
-        }
+fn color_choice(stream: atty::Stream) -> termcolor::ColorChoice {
+    if atty::is(stream) {
+        termcolor::ColorChoice::Auto
+    } else {
+        termcolor::ColorChoice::Never
     }
 }
@@ -0,0 +1,214 @@
+use crate::{
+    rust::{self, CodeEdit},
+    shell::Shell,
+    workspace::{MetadataExt as _, TargetExt as _},
+};
+use anyhow::{bail, Context as _};
+use cargo_metadata as cm;
+use itertools::Itertools as _;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A module of a library in the workspace.
+pub(crate) struct Module {
+    pub(crate) path: Vec<String>,
+    pub(crate) lines: usize,
+    pub(crate) items: usize,
+    /// Modules in the same library which this module refers to.
+    pub(crate) deps: BTreeSet<Vec<String>>,
+}
+
+pub(crate) struct Lib<'cm> {
+    pub(crate) package: &'cm cm::Package,
+    pub(crate) target: &'cm cm::Target,
+    pub(crate) modules: Vec<Module>,
+    /// Bin-like targets in the workspace and modules of this library they depend on, directly or
+    /// indirectly.
+    pub(crate) users: BTreeMap<String, BTreeSet<Vec<String>>>,
+}
+
+pub(crate) fn read_libs<'cm>(
+    metadata: &'cm cm::Metadata,
+    cargo_equip_mod_name: &syn::Ident,
+    shell: &mut Shell,
+) -> anyhow::Result<Vec<Lib<'cm>>> {
+    let members = metadata
+        .packages
+        .iter()
+        .filter(|p| metadata.workspace_members.contains(&p.id))
+        .collect::<Vec<_>>();
+
+    let mut libs = vec![];
+    for package in &members {
+        for target in package.targets.iter().filter(|t| t.is_lib()) {
+            shell.status(
+                "Reading",
+                format!("`{}` from `{}`", target.crate_name(), package.id),
+            )?;
+            let code = CodeEdit::new(cargo_equip_mod_name, &target.src_path, || {
+                (target.crate_name(), &package.id.repr)
+            })?
+            .finish()?;
+
+            let modules = rust::module_deps(&code)?
+                .into_iter()
+                .map(|(path, content, deps)| {
+                    Ok(Module {
+                        lines: content.lines().filter(|l| !l.trim().is_empty()).count(),
+                        items: rust::parse_file(&content)?.items.len(),
+                        path,
+                        deps,
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            libs.push(Lib {
+                package,
+                target,
+                modules,
+                users: BTreeMap::new(),
+            });
+        }
+    }
+
+    for package in &members {
+        for target in package
+            .targets
+            .iter()
+            .filter(|t| t.is_bin() || t.is_example())
+        {
+            let code = CodeEdit::new(cargo_equip_mod_name, &target.src_path, || {
+                (target.crate_name(), &package.id.repr)
+            })?
+            .finish()?;
+
+            for segments in rust::collect_paths(&code)? {
+                let (extern_crate_name, segments) = match &*segments {
+                    [first, rest @ ..] => (first, rest),
+                    [] => continue,
+                };
+                let lib_package = if libs.iter().any(|l| {
+                    l.package.id == package.id && l.target.crate_name() == *extern_crate_name
+                }) {
+                    Some(*package)
+                } else {
+                    metadata.dep_lib_by_extern_crate_name(&package.id, extern_crate_name)
+                };
+                let lib = lib_package.and_then(|lib_package| {
+                    libs.iter_mut().find(|l| l.package.id == lib_package.id)
+                });
+                if let Some(lib) = lib {
+                    let paths = lib
+                        .modules
+                        .iter()
+                        .map(|m| m.path.clone())
+                        .collect::<Vec<_>>();
+                    if let Some(path) = rust::longest_module_prefix(&paths, segments) {
+                        lib.users
+                            .entry(target.display_name())
+                            .or_default()
+                            .insert(path);
+                    }
+                }
+            }
+        }
+    }
+
+    for lib in &mut libs {
+        let deps = lib
+            .modules
+            .iter()
+            .map(|m| (&m.path, &m.deps))
+            .collect::<BTreeMap<_, _>>();
+        for used in lib.users.values_mut() {
+            let mut queue = used.iter().cloned().collect::<Vec<_>>();
+            while let Some(path) = queue.pop() {
+                for dep in deps.get(&path).into_iter().flat_map(|d| d.iter()) {
+                    if used.insert(dep.clone()) {
+                        queue.push(dep.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    libs.sort_by_key(|l| l.target.crate_name());
+    Ok(libs)
+}
+
+pub(crate) fn stats(
+    metadata: &cm::Metadata,
+    cargo_equip_mod_name: &syn::Ident,
+    shell: &mut Shell,
+) -> anyhow::Result<String> {
+    let libs = read_libs(metadata, cargo_equip_mod_name, shell)?;
+
+    let mut out = "".to_owned();
+    for lib in &libs {
+        if !out.is_empty() {
+            out += "\n";
+        }
+        let crate_name = lib.target.crate_name();
+        out += &format!("# {}\n\n", crate_name);
+
+        let rows = lib
+            .modules
+            .iter()
+            .map(|module| {
+                let name = itertools::chain!([&crate_name], &module.path).join("::");
+                let users = lib
+                    .users
+                    .iter()
+                    .filter(|(_, used)| used.contains(&module.path))
+                    .map(|(target, _)| target)
+                    .join(", ");
+                (name, module.lines, module.items, users)
+            })
+            .collect::<Vec<_>>();
+
+        let width = rows
+            .iter()
+            .map(|(name, ..)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max("module".len());
+        out += &format!(
+            "{:<width$} {:>6} {:>6} used by\n",
+            "module", "lines", "items",
+        );
+        for (name, lines, items, users) in rows {
+            out += &format!(
+                "{:<width$} {:>6} {:>6} {}\n",
+                name,
+                lines,
+                items,
+                if users.is_empty() { "-" } else { &users },
+            );
+        }
+    }
+    Ok(out)
+}
+
+/// Lists the targets that use the module or its descendants, directly or indirectly.
+pub(crate) fn rdeps(
+    metadata: &cm::Metadata,
+    module: &str,
+    cargo_equip_mod_name: &syn::Ident,
+    shell: &mut Shell,
+) -> anyhow::Result<Vec<String>> {
+    let (crate_name, path) = rust::parse_module_path(module);
+
+    let libs = read_libs(metadata, cargo_equip_mod_name, shell)?;
+    let lib = libs
+        .iter()
+        .find(|l| l.target.crate_name() == crate_name)
+        .with_context(|| format!("no library named `{}` in the workspace", crate_name))?;
+    if !lib.modules.iter().any(|m| m.path.starts_with(&path)) {
+        bail!("no module named `{}`", module);
+    }
+
+    Ok(lib
+        .users
+        .iter()
+        .filter(|(_, used)| used.iter().any(|p| p.starts_with(&path)))
+        .map(|(target, _)| target.clone())
+        .collect())
+}
@@ -0,0 +1,142 @@
+use crate::rust::CodeEdit;
+use std::{collections::BTreeMap, sync::Arc};
+
+#[cfg(feature = "plugins")]
+use {once_cell::sync::Lazy, std::sync::Mutex};
+
+/// A library being bundled.
+#[cfg_attr(not(feature = "plugins"), allow(dead_code))]
+pub struct Crate<'a> {
+    /// Name of the module for the crate in `crate::{cargo_equip_mod_name}::crates`.
+    pub name: &'a str,
+    /// e.g. `__cargo_equip`
+    pub cargo_equip_mod_name: &'a syn::Ident,
+    pub code: &'a str,
+    pub file: &'a syn::File,
+    /// Names of the modules for the bundled dependencies, keyed by their extern crate names.
+    pub renames: &'a BTreeMap<String, String>,
+    pub features: &'a [String],
+    pub target: Option<&'a cfg_expr::targets::TargetInfo>,
+}
+
+/// A pass over each library, run after the paths in it are translated.
+pub trait Transform: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Returns the new code, or `None` to leave the code as it is.
+    fn transform(&self, krate: &Crate<'_>) -> anyhow::Result<Option<String>>;
+}
+
+#[cfg(feature = "plugins")]
+static REGISTERED: Lazy<Mutex<Vec<Arc<dyn Transform>>>> = Lazy::new(Mutex::default);
+
+/// Adds a pass to run after the built-in ones.
+#[cfg(feature = "plugins")]
+pub fn register_transform(transform: impl Transform + 'static) {
+    REGISTERED.lock().unwrap().push(Arc::new(transform));
+}
+
+/// The built-in passes enabled by the options, followed by the registered ones.
+pub(crate) fn transforms(
+    resolve_cfgs: bool,
+    fold_cfg_macros: bool,
+    strip_derives: &[String],
+    remove_docs: bool,
+    remove_comments: bool,
+) -> Vec<Arc<dyn Transform>> {
+    let mut transforms = vec![];
+    if resolve_cfgs {
+        transforms.push(Arc::new(ResolveCfgs) as Arc<dyn Transform>);
+    }
+    if fold_cfg_macros {
+        transforms.push(Arc::new(FoldCfgMacros));
+    }
+    if !strip_derives.is_empty() {
+        transforms.push(Arc::new(StripDerives(strip_derives.to_owned())));
+    }
+    if remove_docs {
+        transforms.push(Arc::new(RemoveDocs));
+    }
+    if remove_comments {
+        transforms.push(Arc::new(RemoveComments));
+    }
+    #[cfg(feature = "plugins")]
+    transforms.extend(REGISTERED.lock().unwrap().iter().cloned());
+    transforms
+}
+
+fn edit(
+    krate: &Crate<'_>,
+    f: impl FnOnce(&mut CodeEdit<'_>) -> anyhow::Result<()>,
+) -> anyhow::Result<Option<String>> {
+    let mut edit = CodeEdit::from_code(krate.cargo_equip_mod_name, krate.code)?;
+    f(&mut edit)?;
+    edit.finish().map(Some)
+}
+
+struct ResolveCfgs;
+
+impl Transform for ResolveCfgs {
+    fn name(&self) -> &str {
+        "resolve-cfgs"
+    }
+
+    fn transform(&self, krate: &Crate<'_>) -> anyhow::Result<Option<String>> {
+        edit(krate, |edit| {
+            edit.resolve_cfgs(krate.features, krate.target)
+        })
+    }
+}
+
+struct FoldCfgMacros;
+
+impl Transform for FoldCfgMacros {
+    fn name(&self) -> &str {
+        "fold-cfg-macros"
+    }
+
+    fn transform(&self, krate: &Crate<'_>) -> anyhow::Result<Option<String>> {
+        edit(krate, |edit| {
+            edit.fold_cfg_macros(krate.features, krate.target)
+        })
+    }
+}
+
+struct StripDerives(Vec<String>);
+
+impl Transform for StripDerives {
+    fn name(&self) -> &str {
+        "strip-derives"
+    }
+
+    fn transform(&self, krate: &Crate<'_>) -> anyhow::Result<Option<String>> {
+        edit(krate, |edit| edit.strip_derives(&self.0))
+    }
+}
+
+struct RemoveDocs;
+
+impl Transform for RemoveDocs {
+    fn name(&self) -> &str {
+        "remove-docs"
+    }
+
+    fn transform(&self, krate: &Crate<'_>) -> anyhow::Result<Option<String>> {
+        edit(krate, |edit| {
+            edit.allow_missing_docs();
+            edit.erase_docs()
+        })
+    }
+}
+
+struct RemoveComments;
+
+impl Transform for RemoveComments {
+    fn name(&self) -> &str {
+        "remove-comments"
+    }
+
+    fn transform(&self, krate: &Crate<'_>) -> anyhow::Result<Option<String>> {
+        edit(krate, |edit| edit.erase_comments())
+    }
+}
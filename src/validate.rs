@@ -0,0 +1,47 @@
+use crate::{config::Validate, rust, shell::Shell};
+
+/// Checks the output against the limits of the judge, and returns the number of the findings.
+pub(crate) fn validate(code: &str, rules: &Validate, shell: &mut Shell) -> anyhow::Result<usize> {
+    let mut findings = vec![];
+
+    let lines_with = |p: &dyn Fn(&str) -> bool| {
+        code.split('\n')
+            .enumerate()
+            .filter(|(_, line)| p(line))
+            .map(|(i, _)| i + 1)
+            .collect::<Vec<_>>()
+    };
+    let mut report_lines = |what: &str, lines: Vec<usize>| {
+        if let Some(first) = lines.first() {
+            findings.push(format!(
+                "{} on {} line(s), first at line {}",
+                what,
+                lines.len(),
+                first,
+            ));
+        }
+    };
+
+    if rules.ascii_only {
+        report_lines("non-ASCII characters", lines_with(&|l| !l.is_ascii()));
+    }
+    if rules.forbid_tabs {
+        report_lines("tabs", lines_with(&|l| l.contains('\t')));
+    }
+    if rules.forbid_crlf {
+        report_lines("CRLF", lines_with(&|l| l.ends_with('\r')));
+    }
+    if rules.forbid_bom && code.starts_with('\u{feff}') {
+        findings.push("a BOM at the beginning".to_owned());
+    }
+    if !rules.forbidden_macros.is_empty() {
+        for (pos, name) in rust::find_macro_calls(code, &rules.forbidden_macros)? {
+            findings.push(format!("`{}!` at {}:{}", name, pos.line, pos.column + 1,));
+        }
+    }
+
+    for finding in &findings {
+        shell.warn(format!("the output contains {}", finding))?;
+    }
+    Ok(findings.len())
+}
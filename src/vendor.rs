@@ -0,0 +1,216 @@
+use crate::{
+    rust::CodeEdit,
+    shell::Shell,
+    workspace::{PackageExt as _, SourceExt as _, TargetExt as _},
+};
+use anyhow::{bail, Context as _};
+use cargo_metadata as cm;
+use indoc::indoc;
+use std::{collections::BTreeMap, path::Path};
+
+pub(crate) fn vendor(
+    metadata: &cm::Metadata,
+    root_package: &cm::Package,
+    root: &cm::Target,
+    libs_to_bundle: &BTreeMap<&cm::PackageId, (&cm::Target, String)>,
+    cargo_equip_mod_name: &syn::Ident,
+    dir: &Path,
+    shell: &mut Shell,
+) -> anyhow::Result<()> {
+    if let Some(package_id) = libs_to_bundle
+        .keys()
+        .find(|p| metadata[p].has_custom_build())
+    {
+        bail!(
+            "`--vendor` does not support packages with build scripts: `{}`",
+            package_id
+        );
+    }
+
+    if dir.exists() && dir.read_dir()?.next().is_some() {
+        bail!("`{}` is not empty", dir.display());
+    }
+
+    let nodes = metadata
+        .resolve
+        .as_ref()
+        .map(|cm::Resolve { nodes, .. }| &nodes[..])
+        .unwrap_or(&[])
+        .iter()
+        .map(|node| (&node.id, node))
+        .collect::<BTreeMap<_, _>>();
+
+    let expand = |package: &cm::Package, target: &cm::Target| -> _ {
+        CodeEdit::new(cargo_equip_mod_name, &target.src_path, || {
+            (target.crate_name(), &package.id.repr)
+        })?
+        .finish()
+    };
+
+    let manifest = |package_name: &str,
+                    package: &cm::Package,
+                    target: &cm::Target,
+                    crates_dir: &str|
+     -> anyhow::Result<toml_edit::Document> {
+        let mut manifest = indoc! {r#"
+            [package]
+            name = ""
+            version = "0.0.0"
+            edition = ""
+        "#}
+        .parse::<toml_edit::Document>()
+        .unwrap();
+
+        manifest["package"]["name"] = toml_edit::value(package_name);
+        manifest["package"]["version"] = toml_edit::value(package.version.to_string());
+        manifest["package"]["edition"] = toml_edit::value(&*package.edition);
+
+        let mut tbl = toml_edit::Table::new();
+        tbl["name"] = toml_edit::value(target.crate_name());
+        if target.is_lib() {
+            tbl["path"] = toml_edit::value("lib.rs");
+            if package.has_proc_macro() {
+                tbl["proc-macro"] = toml_edit::value(true);
+            }
+            manifest["lib"] = toml_edit::Item::Table(tbl);
+        } else {
+            tbl["path"] = toml_edit::value("main.rs");
+            let mut arr = toml_edit::ArrayOfTables::new();
+            arr.push(tbl);
+            manifest["bin"] = toml_edit::Item::ArrayOfTables(arr);
+        }
+
+        let node = nodes
+            .get(&package.id)
+            .with_context(|| format!("`{}` is not in the resolve graph", package.id))?;
+
+        let mut dependencies = toml_edit::Table::new();
+        let mut features = node
+            .features
+            .iter()
+            .filter(|f| *f != "default")
+            .map(|f| (&**f, false))
+            .collect::<BTreeMap<_, _>>();
+
+        for cm::NodeDep {
+            name,
+            pkg,
+            dep_kinds,
+            ..
+        } in &node.deps
+        {
+            if !dep_kinds.iter().any(|cm::DepKindInfo { kind, .. }| {
+                *kind == cm::DependencyKind::Normal
+                    || *kind == cm::DependencyKind::Development && target.is_example()
+            }) {
+                continue;
+            }
+
+            let mut dep = toml_edit::InlineTable::new();
+            if let Some((_, pseudo_extern_crate_name)) = libs_to_bundle.get(pkg) {
+                dep.get_or_insert("package", &**pseudo_extern_crate_name);
+                dep.get_or_insert(
+                    "path",
+                    format!("{}/{}", crates_dir, pseudo_extern_crate_name),
+                );
+            } else {
+                let dep_package = &metadata[pkg];
+                dep.get_or_insert("package", &*dep_package.name);
+                match &dep_package.source {
+                    None => {
+                        dep.get_or_insert("path", dep_package.manifest_dir().as_str());
+                    }
+                    Some(source) => {
+                        if let Some((repository, rev)) = source.rev_git() {
+                            dep.get_or_insert("git", repository);
+                            dep.get_or_insert("rev", rev);
+                        } else {
+                            dep.get_or_insert("version", format!("={}", dep_package.version));
+                        }
+                    }
+                }
+                let dep_features = &nodes[pkg].features;
+                if !dep_features.iter().any(|f| f == "default") {
+                    dep.get_or_insert("default-features", false);
+                }
+                let dep_features = dep_features
+                    .iter()
+                    .filter(|f| *f != "default")
+                    .map(|f| &**f)
+                    .collect::<toml_edit::Value>();
+                if dep_features.as_array().map_or(false, |a| !a.is_empty()) {
+                    dep.get_or_insert("features", dep_features);
+                }
+            }
+            // An enabled feature named after a dependency is an implicit feature of an optional
+            // one.
+            if let Some(is_dep) = features.get_mut(&**name) {
+                *is_dep = true;
+                dep.get_or_insert("optional", true);
+            }
+            dependencies[name] = toml_edit::value(dep);
+        }
+        manifest["dependencies"] = toml_edit::Item::Table(dependencies);
+
+        let mut features_tbl = toml_edit::Table::new();
+        features_tbl["default"] =
+            toml_edit::value(features.keys().copied().collect::<toml_edit::Value>());
+        for (feature, is_dep) in features {
+            if !is_dep {
+                features_tbl[feature] = toml_edit::value(toml_edit::Array::new());
+            }
+        }
+        manifest["features"] = toml_edit::Item::Table(features_tbl);
+
+        Ok(manifest)
+    };
+
+    let write = |path: &Path, content: &str| -> anyhow::Result<()> {
+        cargo_util::paths::create_dir_all(path.parent().expect("should not be root"))?;
+        cargo_util::paths::write(path, content)
+    };
+
+    for (package_id, (target, pseudo_extern_crate_name)) in libs_to_bundle {
+        if *package_id == &root_package.id && root.is_lib() {
+            continue;
+        }
+        let package = &metadata[package_id];
+        shell.status("Vendoring", package_id)?;
+        let crate_dir = &dir.join("crates").join(pseudo_extern_crate_name);
+        let manifest = manifest(pseudo_extern_crate_name, package, target, "..")?;
+        write(&crate_dir.join("Cargo.toml"), &manifest.to_string())?;
+        write(&crate_dir.join("lib.rs"), &expand(package, target)?)?;
+    }
+
+    // A bin-like target refers to the library target of its own package, which is not in the
+    // resolve graph. The package is renamed since it cannot share its name with the library.
+    let own_lib = libs_to_bundle
+        .get(&root_package.id)
+        .filter(|_| !root.is_lib());
+    let package_name = if own_lib.is_some() {
+        format!("{}-{}", root_package.name, root.name)
+    } else {
+        root_package.name.clone()
+    };
+    let mut manifest = manifest(&package_name, root_package, root, "crates")?;
+    if let Some((_, pseudo_extern_crate_name)) = own_lib {
+        let mut dep = toml_edit::InlineTable::new();
+        dep.get_or_insert("package", &**pseudo_extern_crate_name);
+        dep.get_or_insert("path", format!("crates/{}", pseudo_extern_crate_name));
+        manifest["dependencies"][&**pseudo_extern_crate_name] = toml_edit::value(dep);
+    }
+    manifest["workspace"] = toml_edit::table();
+    write(&dir.join("Cargo.toml"), &manifest.to_string())?;
+    write(
+        &dir.join(if root.is_lib() { "lib.rs" } else { "main.rs" }),
+        &expand(root_package, root)?,
+    )?;
+
+    let lockfile = metadata.workspace_root.join("Cargo.lock");
+    if lockfile.exists() {
+        cargo_util::paths::copy(lockfile, dir.join("Cargo.lock"))?;
+    }
+
+    shell.status("Wrote", dir.display())?;
+    Ok(())
+}
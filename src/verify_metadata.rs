@@ -0,0 +1,110 @@
+use crate::{config::Config, shell::Shell, workspace};
+use anyhow::bail;
+use cargo_metadata as cm;
+use itertools::Itertools as _;
+use std::path::Path;
+
+/// Checks `workspace.metadata.cargo-equip` and `package.metadata.cargo-equip` of the workspace
+/// members beyond what is needed for parsing.
+pub(crate) fn verify_metadata(
+    manifest_path: &Path,
+    cwd: &Path,
+    shell: &mut Shell,
+) -> anyhow::Result<()> {
+    let metadata = workspace::cargo_metadata(manifest_path, cwd, &[], false)?;
+
+    let sources = itertools::chain!(
+        [(
+            "workspace.metadata.cargo-equip".to_owned(),
+            "workspace.metadata.cargo-equip",
+            &metadata.workspace_metadata,
+            metadata.workspace_root.join("Cargo.toml"),
+        )],
+        metadata
+            .packages
+            .iter()
+            .filter(|p| metadata.workspace_members.contains(&p.id))
+            .map(|p| {
+                (
+                    format!("package.metadata.cargo-equip` of `{}", p.name),
+                    "package.metadata.cargo-equip",
+                    &p.metadata,
+                    p.manifest_path.clone(),
+                )
+            }),
+    );
+
+    let mut problems = 0;
+    for (key, section, value, manifest_path) in sources {
+        let value = match value.get("cargo-equip") {
+            Some(value) => value,
+            None => continue,
+        };
+        let found = match Config::parse(value, manifest_path.as_ref(), section) {
+            Ok(config) => verify(&metadata, &config),
+            Err(err) => vec![format!("{:#}", err)],
+        };
+        if found.is_empty() {
+            shell.status("Ok", format!("`{}`", key))?;
+        }
+        for problem in found {
+            problems += 1;
+            shell.warn(format!("`{}`: {}", key, problem))?;
+        }
+    }
+
+    if problems > 0 {
+        bail!("found {} problem(s)", problems);
+    }
+    Ok(())
+}
+
+fn verify(metadata: &cm::Metadata, config: &Config) -> Vec<String> {
+    let mut problems = vec![];
+
+    for (name, profile) in &config.profiles {
+        if let Some(io_template) = &profile.io_template {
+            let path = metadata.workspace_root.as_std_path().join(io_template);
+            if !path.exists() {
+                problems.push(format!(
+                    "`profiles.{}.io-template`: `{}` does not exist",
+                    name,
+                    path.display(),
+                ));
+            }
+        }
+        if let Some(triple) = &profile.target {
+            if cfg_expr::targets::get_builtin_target_by_triple(triple).is_none() {
+                problems.push(format!(
+                    "`profiles.{}.target`: unknown target triple `{}`",
+                    name, triple,
+                ));
+            }
+        }
+    }
+
+    for (package_name, rename) in &config.rename {
+        if !metadata.packages.iter().any(|p| p.name == *package_name) {
+            problems.push(format!(
+                "`rename.{}`: no package named `{}` in the dependency graph",
+                package_name, package_name,
+            ));
+        }
+        if syn::parse_str::<syn::Ident>(rename).is_err() {
+            problems.push(format!(
+                "`rename.{}`: `{}` is not a valid identifier",
+                package_name, rename,
+            ));
+        }
+    }
+    for (rename, n) in config.rename.values().counts() {
+        if n > 1 {
+            problems.push(format!(
+                "`rename`: {} packages are renamed to `{}`",
+                n, rename
+            ));
+        }
+    }
+
+    problems
+}
@@ -1,6 +1,6 @@
 mod license;
 
-use crate::{process::ProcessBuilderExt as _, shell::Shell, toolchain, User};
+use crate::{process::ProcessBuilderExt as _, shell::Shell, toolchain, CheckMode, User};
 use anyhow::{bail, Context as _};
 use camino::{Utf8Path, Utf8PathBuf};
 use cargo_metadata as cm;
@@ -9,29 +9,81 @@ use if_chain::if_chain;
 use indoc::indoc;
 use itertools::Itertools as _;
 use krates::PkgSpec;
-use rand::Rng as _;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{BTreeMap, HashMap, HashSet},
     env,
-    io::Cursor,
+    io::{Cursor, Write as _},
     path::{Path, PathBuf},
     str,
 };
 
+/// Finds the nearest `Cargo.toml` that has `[package]` or `[workspace]`.
+///
+/// Which workspace the package belongs to is left to Cargo.
 pub(crate) fn locate_project(cwd: &Path) -> anyhow::Result<PathBuf> {
-    cwd.ancestors()
-        .map(|p| p.join("Cargo.toml"))
-        .find(|p| p.exists())
-        .with_context(|| {
-            format!(
-                "could not find `Cargo.toml` in `{}` or any parent directory",
-                cwd.display(),
-            )
-        })
+    for dir in cwd.ancestors() {
+        let path = dir.join("Cargo.toml");
+        if !path.exists() {
+            continue;
+        }
+        let manifest = cargo_util::paths::read(&path)?
+            .parse::<toml_edit::Document>()
+            .with_context(|| format!("could not parse `{}`", path.display()))?;
+        if !manifest["package"].is_none() || !manifest["workspace"].is_none() {
+            return Ok(path);
+        }
+    }
+    bail!(
+        "could not find `Cargo.toml` in `{}` or any parent directory",
+        cwd.display(),
+    );
+}
+
+/// Returns `Cargo.toml` in `dir`, which must have `[workspace]`.
+pub(crate) fn workspace_root_manifest(dir: &Path) -> anyhow::Result<PathBuf> {
+    let path = dir.join("Cargo.toml");
+    let manifest = cargo_util::paths::read(&path)?
+        .parse::<toml_edit::Document>()
+        .with_context(|| format!("could not parse `{}`", path.display()))?;
+    if manifest["workspace"].is_none() {
+        bail!("`{}` does not have `[workspace]`", path.display());
+    }
+    Ok(path)
+}
+
+pub(crate) fn cargo_metadata(
+    manifest_path: &Path,
+    cwd: &Path,
+    features: &[String],
+    offline: bool,
+) -> cm::Result<cm::Metadata> {
+    let mut cmd = cm::MetadataCommand::new();
+    if let Ok(cargo_exe) = crate::process::cargo_exe() {
+        cmd.cargo_path(cargo_exe);
+    }
+    if !features.is_empty() {
+        cmd.features(cm::CargoOpt::SomeFeatures(features.to_owned()));
+    }
+    if offline {
+        cmd.other_options(vec!["--offline".to_owned()]);
+    }
+    cmd.manifest_path(manifest_path).current_dir(cwd).exec()
 }
 
-pub(crate) fn cargo_metadata(manifest_path: &Path, cwd: &Path) -> cm::Result<cm::Metadata> {
-    cm::MetadataCommand::new()
+pub(crate) fn cargo_metadata_no_deps(
+    manifest_path: &Path,
+    cwd: &Path,
+    offline: bool,
+) -> cm::Result<cm::Metadata> {
+    let mut cmd = cm::MetadataCommand::new();
+    if let Ok(cargo_exe) = crate::process::cargo_exe() {
+        cmd.cargo_path(cargo_exe);
+    }
+    if offline {
+        cmd.other_options(vec!["--offline".to_owned()]);
+    }
+    cmd.no_deps()
         .manifest_path(manifest_path)
         .current_dir(cwd)
         .exec()
@@ -42,6 +94,8 @@ pub(crate) fn cargo_check_message_format_json(
     metadata: &cm::Metadata,
     package: &cm::Package,
     krate: &cm::Target,
+    features: &[String],
+    offline: bool,
     shell: &mut Shell,
 ) -> anyhow::Result<Vec<cm::Message>> {
     let messages = ProcessBuilder::new(toolchain::rustup_exe(package.manifest_dir())?)
@@ -54,6 +108,13 @@ pub(crate) fn cargo_check_message_format_json(
         .arg("-p")
         .arg(format!("{}:{}", package.name, package.version))
         .args(&krate.target_option())
+        .args(&features_option(features))
+        .args(&if offline { vec!["--offline"] } else { vec![] })
+        .args(&if shell.is_quiet() {
+            vec!["--quiet"]
+        } else {
+            vec![]
+        })
         .cwd(&metadata.workspace_root)
         .try_inspect(|this| shell.status("Running", this))?
         .read_stdout::<Vec<u8>>()?;
@@ -65,6 +126,14 @@ pub(crate) fn cargo_check_message_format_json(
         .map_err(Into::into)
 }
 
+pub(crate) fn features_option(features: &[String]) -> Vec<String> {
+    if features.is_empty() {
+        vec![]
+    } else {
+        vec!["--features".to_owned(), features.join(",")]
+    }
+}
+
 pub(crate) fn list_out_dirs<'cm>(
     metadata: &'cm cm::Metadata,
     messages: &[cm::Message],
@@ -82,35 +151,44 @@ pub(crate) fn list_out_dirs<'cm>(
         .collect()
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn cargo_check_using_current_lockfile_and_cache(
     metadata: &cm::Metadata,
     package: &cm::Package,
     target: &cm::Target,
-    exclude: &[PkgSpec],
+    is_excluded: impl Fn(&cm::PackageId) -> bool,
     code: &str,
+    cwd: &Path,
+    toolchain: Option<&str>,
+    rustflags: Option<&str>,
+    envs: &[(String, String)],
+    no_rustc_wrapper: bool,
+    triple: Option<&str>,
+    mode: CheckMode,
+    force: bool,
+    shell: &mut Shell,
 ) -> anyhow::Result<()> {
-    let package_name = {
-        let mut rng = rand::thread_rng();
-        let suf = (0..16)
-            .map(|_| match rng.gen_range(0..=35) {
-                n @ 0..=25 => b'a' + n,
-                n @ 26..=35 => b'0' + n - 26,
-                _ => unreachable!(),
-            })
-            .collect::<Vec<_>>();
-        let suf = str::from_utf8(&suf).expect("should be valid ASCII");
-        format!("cargo-equip-check-output-{}", suf)
-    };
+    let package_name = "cargo-equip-check-output";
     let crate_name = &*if target.is_lib() {
         package_name.replace('-', "_")
     } else {
         package_name.to_owned()
     };
 
-    let temp_pkg = tempfile::Builder::new()
-        .prefix(&package_name)
-        .rand_bytes(0)
-        .tempdir()?;
+    // The package is kept across invocations so that Cargo can reuse the previous build. Each
+    // target has its own one so that checks of different targets, e.g. from `cargo equip daemon`
+    // or multiple `--bin`s, do not overwrite each other's files.
+    let check_pkg = &metadata
+        .target_directory
+        .join("cargo-equip")
+        .join("check-pkg")
+        .join(format!(
+            "{}-{}-{}",
+            package.name,
+            target.kind.join(","),
+            target.name,
+        ));
+    cargo_util::paths::create_dir_all(check_pkg)?;
 
     let orig_manifest =
         cargo_util::paths::read(package.manifest_path.as_ref())?.parse::<toml_edit::Document>()?;
@@ -143,13 +221,17 @@ pub(crate) fn cargo_check_using_current_lockfile_and_cache(
         });
     }
     temp_manifest["dependencies"] = orig_manifest["dependencies"].clone();
-    temp_manifest["dev-dependencies"] = orig_manifest["dev-dependencies"].clone();
+    // Dev-dependencies are only available to examples.
+    if target.is_example() {
+        temp_manifest["dev-dependencies"] = orig_manifest["dev-dependencies"].clone();
+    }
 
     let renames = package
         .dependencies
         .iter()
         .filter(|cm::Dependency { kind, .. }| {
-            [cm::DependencyKind::Normal, cm::DependencyKind::Development].contains(kind)
+            *kind == cm::DependencyKind::Normal
+                || target.is_example() && *kind == cm::DependencyKind::Development
         })
         .flat_map(|cm::Dependency { rename, .. }| rename)
         .collect::<HashSet<_>>();
@@ -165,7 +247,7 @@ pub(crate) fn cargo_check_using_current_lockfile_and_cache(
             .expect("should contain")
             .deps
             .iter()
-            .filter(|cm::NodeDep { pkg, .. }| !exclude.iter().any(|s| s.matches(&metadata[pkg])))
+            .filter(|cm::NodeDep { pkg, .. }| !is_excluded(pkg))
             .map(|cm::NodeDep { name, pkg, .. }| {
                 if renames.contains(&name) {
                     name
@@ -177,19 +259,7 @@ pub(crate) fn cargo_check_using_current_lockfile_and_cache(
             table.remove(name_in_toml);
         }
 
-        for (_, value) in table.iter_mut() {
-            if !value["path"].is_none() {
-                if let toml_edit::Item::Value(value) = &mut value["path"] {
-                    if let Some(possibly_rel_path) = value.as_str() {
-                        *value = package
-                            .manifest_dir()
-                            .join(possibly_rel_path)
-                            .into_string()
-                            .into();
-                    }
-                }
-            }
-        }
+        absolutize_paths(table, package.manifest_dir());
     };
 
     if let toml_edit::Item::Table(table) = &mut temp_manifest["dependencies"] {
@@ -199,22 +269,111 @@ pub(crate) fn cargo_check_using_current_lockfile_and_cache(
         modify_dependencies(table);
     }
 
-    cargo_util::paths::write(
-        temp_pkg.path().join("Cargo.toml"),
-        temp_manifest.to_string(),
+    // `[patch]`, `[replace]`, and `[profile]` are only read from the workspace root.
+    let ws_manifest = cargo_util::paths::read(metadata.workspace_root.join("Cargo.toml").as_ref())?
+        .parse::<toml_edit::Document>()?;
+    if let toml_edit::Item::Table(patch) = &ws_manifest["patch"] {
+        let mut patch = patch.clone();
+        for (_, table) in patch.iter_mut() {
+            if let toml_edit::Item::Table(table) = table {
+                absolutize_paths(table, &metadata.workspace_root);
+            }
+        }
+        temp_manifest["patch"] = toml_edit::Item::Table(patch);
+    }
+    if let toml_edit::Item::Table(replace) = &ws_manifest["replace"] {
+        let mut replace = replace.clone();
+        absolutize_paths(&mut replace, &metadata.workspace_root);
+        temp_manifest["replace"] = toml_edit::Item::Table(replace);
+    }
+    // e.g. `overflow-checks`
+    if let toml_edit::Item::Table(profile) = &ws_manifest["profile"] {
+        temp_manifest["profile"] = toml_edit::Item::Table(profile.clone());
+    }
+    // The package is under the target directory, which may be inside the workspace.
+    temp_manifest["workspace"] = toml_edit::table();
+
+    write_if_changed(
+        &check_pkg.join("Cargo.toml"),
+        temp_manifest.to_string().as_ref(),
     )?;
-    cargo_util::paths::copy(
-        metadata.workspace_root.join("Cargo.lock"),
-        temp_pkg.path().join("Cargo.lock"),
+    write_if_changed(
+        &check_pkg.join("Cargo.lock"),
+        &cargo_util::paths::read_bytes(metadata.workspace_root.join("Cargo.lock").as_ref())?,
     )?;
-    cargo_util::paths::write(temp_pkg.path().join(format!("{}.rs", crate_name)), code)?;
+    write_if_changed(&check_pkg.join(format!("{}.rs", crate_name)), code.as_ref())?;
+
+    let state_path = &metadata
+        .target_directory
+        .join("cargo-equip")
+        .join("state.json");
+    let hash = format!(
+        "{:x}",
+        md5::compute(
+            [
+                target.kind.join(",").as_bytes(),
+                temp_manifest.to_string().as_ref(),
+                &cargo_util::paths::read_bytes(check_pkg.join("Cargo.lock").as_ref())?,
+                code.as_ref(),
+                toolchain.unwrap_or_default().as_ref(),
+                rustflags.unwrap_or_default().as_ref(),
+                format!("{:?}", (envs, no_rustc_wrapper)).as_ref(),
+                triple.unwrap_or_default().as_ref(),
+                format!("{:?}", mode).as_ref(),
+            ]
+            .join(&b'\0'),
+        ),
+    );
+    let state_key = check_pkg.file_name().expect("should have a file name");
+    if !force
+        && CheckState::read(state_path.as_ref())?
+            .last_checked
+            .get(state_key)
+            == Some(&hash)
+    {
+        shell.status("Fresh", "unchanged, skipping check")?;
+        return Ok(());
+    }
 
-    ProcessBuilder::new(crate::process::cargo_exe()?)
-        .arg("check")
+    let mut cargo_check = if let Some(toolchain) = toolchain {
+        let mut cargo_check = ProcessBuilder::new(toolchain::rustup_exe(package.manifest_dir())?);
+        cargo_check.arg("run").arg(toolchain).arg("cargo");
+        cargo_check
+    } else {
+        ProcessBuilder::new(crate::process::cargo_exe()?)
+    };
+    if let Some(rustflags) = rustflags {
+        // `$RUSTFLAGS` takes precedence over `build.rustflags`, and later flags win.
+        let rustflags = match env::var("RUSTFLAGS") {
+            Ok(orig) if !orig.trim().is_empty() => format!("{} {}", orig, rustflags),
+            _ => rustflags.to_owned(),
+        };
+        cargo_check.env("RUSTFLAGS", rustflags);
+    }
+    if no_rustc_wrapper {
+        // Empty values override `build.rustc-wrapper` and `build.rustc-workspace-wrapper`.
+        cargo_check
+            .env("RUSTC_WRAPPER", "")
+            .env("RUSTC_WORKSPACE_WRAPPER", "");
+    }
+    for (key, value) in envs {
+        cargo_check.env(key, value);
+    }
+    cargo_check
+        .arg(if mode == CheckMode::Check {
+            "check"
+        } else {
+            "build"
+        })
+        .args(&if mode == CheckMode::BuildRelease {
+            vec!["--release"]
+        } else {
+            vec![]
+        })
         .arg("--target-dir")
         .arg(&metadata.target_directory)
         .arg("--manifest-path")
-        .arg(temp_pkg.path().join("Cargo.toml"))
+        .arg(check_pkg.join("Cargo.toml"))
         .args(&if target.is_bin() {
             vec!["--bin", crate_name]
         } else if target.is_example() {
@@ -222,15 +381,305 @@ pub(crate) fn cargo_check_using_current_lockfile_and_cache(
         } else {
             vec!["--lib"]
         })
+        .args(&triple.map(|t| vec!["--target", t]).unwrap_or_default())
+        .args(&if shell.is_quiet() {
+            vec!["--quiet"]
+        } else {
+            vec![]
+        })
         .arg("--offline")
-        .cwd(&metadata.workspace_root)
-        .exec()?;
+        // `.cargo/config.toml`s are searched from the current directory, as `cargo` does.
+        .cwd(cwd)
+        .exec()
+        .with_context(|| {
+            format!(
+                "could not check the output. the package is left at `{}`",
+                check_pkg,
+            )
+        })?;
+
+    // Read again since another invocation may have updated it meanwhile.
+    let mut state = CheckState::read(state_path.as_ref())?;
+    state.last_checked.insert(state_key.to_owned(), hash);
+    state.write(state_path.as_ref())
+}
+
+/// `target/cargo-equip/state.json`
+#[derive(Deserialize, Serialize, Default, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+struct CheckState {
+    /// Hashes of the last successful checks, keyed by the names of the packages for checking.
+    last_checked: BTreeMap<String, String>,
+}
+
+impl CheckState {
+    /// Returns the default if the file does not exist or is in an old format.
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&cargo_util::paths::read(path)?).unwrap_or_default())
+    }
+
+    /// Replaces the file by renaming a temporary file so that it is never left half-written.
+    fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let dir = path.parent().expect("should not be root");
+        let mut file = tempfile::Builder::new()
+            .prefix(".state-")
+            .suffix(".json")
+            .tempfile_in(dir)?;
+        file.write_all(serde_json::to_string(self)?.as_ref())?;
+        file.persist(path)
+            .with_context(|| format!("could not write `{}`", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Writes a package for a single `.rs` file with an embedded manifest, and returns the paths of
+/// its `Cargo.toml` and `src/main.rs`.
+///
+/// The package is kept in the cache directory so that the lockfile and the build are reused.
+pub(crate) fn script_package(
+    src_path: &Path,
+    manifest: &str,
+    code: &str,
+    cache_dir: &Path,
+) -> anyhow::Result<(PathBuf, PathBuf)> {
+    let src_path = Utf8Path::from_path(src_path)
+        .with_context(|| format!("`{}` is not valid UTF-8", src_path.display()))?;
+    let script_dir = src_path.parent().expect("should not be root");
+
+    let name = src_path.file_stem().unwrap_or_default().replace(
+        |c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+        "-",
+    );
+    let name = match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => name,
+        _ => format!("script-{}", name),
+    };
 
-    temp_pkg.close()?;
-    Ok(())
+    let mut manifest = manifest
+        .parse::<toml_edit::Document>()
+        .with_context(|| format!("could not parse the embedded manifest in `{}`", src_path))?;
+    if manifest["package"].is_none() {
+        manifest["package"] = toml_edit::table();
+    }
+    for (key, default) in [("name", &*name), ("version", "0.0.0"), ("edition", "2021")] {
+        if manifest["package"][key].is_none() {
+            manifest["package"][key] = toml_edit::value(default);
+        }
+    }
+    for key in ["dependencies", "dev-dependencies"] {
+        if let toml_edit::Item::Table(table) = &mut manifest[key] {
+            absolutize_paths(table, script_dir);
+        }
+    }
+    manifest["workspace"] = toml_edit::table();
+
+    let package_dir = Utf8Path::from_path(cache_dir)
+        .with_context(|| format!("`{}` is not valid UTF-8", cache_dir.display()))?
+        .join("scripts")
+        .join(format!(
+            "{}-{}",
+            name,
+            &format!("{:x}", md5::compute(src_path.as_str()))[..16],
+        ));
+    let manifest_path = package_dir.join("Cargo.toml");
+    let main_path = package_dir.join("src").join("main.rs");
+    cargo_util::paths::create_dir_all(package_dir.join("src"))?;
+    write_if_changed(&manifest_path, manifest.to_string().as_ref())?;
+    write_if_changed(&main_path, code.as_ref())?;
+    Ok((manifest_path.into(), main_path.into()))
+}
+
+/// Writes a package that has the target and depends on the packages in the registry in addition
+/// to the original dependencies, and returns the path of its `Cargo.toml`.
+///
+/// The target keeps its original `path` so that `mod`s are resolved as usual.
+pub(crate) fn registry_package(
+    metadata: &cm::Metadata,
+    package: &cm::Package,
+    target: &cm::Target,
+    registry_deps: &BTreeMap<String, PathBuf>,
+    cache_dir: &Path,
+) -> anyhow::Result<PathBuf> {
+    let mut manifest = standalone_manifest(metadata, package)?;
+    // The original package is a dependency.
+    manifest["package"]["name"] =
+        toml_edit::value(format!("cargo-equip-registry-{}", package.name));
+    if manifest["dependencies"].is_none() {
+        manifest["dependencies"] = toml_edit::table();
+    }
+    // The target can use the lib of its own package.
+    if package.targets.iter().any(TargetExt::is_lib) {
+        let mut dep = toml_edit::InlineTable::default();
+        dep.get_or_insert("path", package.manifest_dir().as_str());
+        manifest["dependencies"][&*package.name] = toml_edit::value(dep);
+    }
+    for (name, path) in registry_deps {
+        let path = Utf8Path::from_path(path)
+            .with_context(|| format!("`{}` is not valid UTF-8", path.display()))?;
+        let package_name = cargo_util::paths::read(path.join("Cargo.toml").as_ref())?
+            .parse::<toml_edit::Document>()?["package"]["name"]
+            .as_str()
+            .with_context(|| format!("`{}` has no `package.name`", path.join("Cargo.toml")))?
+            .to_owned();
+        let mut dep = toml_edit::InlineTable::default();
+        dep.get_or_insert("path", path.as_str());
+        dep.get_or_insert("package", package_name);
+        manifest["dependencies"][name] = toml_edit::value(dep);
+    }
+    push_target(&mut manifest, target);
+
+    let package_dir = cache_subdir(cache_dir, "registry", package, target.src_path.as_str())?;
+    let manifest_path = package_dir.join("Cargo.toml");
+    cargo_util::paths::create_dir_all(&package_dir)?;
+    write_if_changed(&manifest_path, manifest.to_string().as_ref())?;
+    Ok(manifest_path.into())
+}
+
+/// Writes a copy of the package outside of the workspace, and returns the path of its
+/// `Cargo.toml`.
+///
+/// This is for when `cargo metadata` fails for the workspace because of other members. The
+/// lockfile of the workspace is copied so that the versions stay the same.
+pub(crate) fn standalone_package(
+    metadata: &cm::Metadata,
+    package: &cm::Package,
+    cache_dir: &Path,
+) -> anyhow::Result<PathBuf> {
+    let mut manifest = standalone_manifest(metadata, package)?;
+    for target in &package.targets {
+        if target.is_lib_like() {
+            let mut tbl = toml_edit::Table::new();
+            tbl["name"] = toml_edit::value(target.crate_name());
+            tbl["path"] = toml_edit::value(target.src_path.as_str());
+            if target.is_proc_macro() {
+                tbl["proc-macro"] = toml_edit::value(true);
+            }
+            manifest["lib"] = toml_edit::Item::Table(tbl);
+        } else if target.is_custom_build() {
+            manifest["package"]["build"] = toml_edit::value(target.src_path.as_str());
+        } else if target.is_bin() || target.is_example() {
+            push_target(&mut manifest, target);
+        }
+    }
+
+    let package_dir = cache_subdir(
+        cache_dir,
+        "standalone",
+        package,
+        package.manifest_path.as_str(),
+    )?;
+    let manifest_path = package_dir.join("Cargo.toml");
+    let lock_path = package_dir.join("Cargo.lock");
+    cargo_util::paths::create_dir_all(&package_dir)?;
+    write_if_changed(&manifest_path, manifest.to_string().as_ref())?;
+    let ws_lock_path = metadata.workspace_root.join("Cargo.lock");
+    if !lock_path.exists() && ws_lock_path.exists() {
+        cargo_util::paths::copy(ws_lock_path, &lock_path)?;
+    }
+    Ok(manifest_path.into())
+}
+
+/// `[package]`, the dependencies, and `[features]` of the package with absolute paths, in a
+/// workspace of its own with the `metadata` and `[patch]` of the original workspace.
+fn standalone_manifest(
+    metadata: &cm::Metadata,
+    package: &cm::Package,
+) -> anyhow::Result<toml_edit::Document> {
+    let orig_manifest =
+        cargo_util::paths::read(package.manifest_path.as_ref())?.parse::<toml_edit::Document>()?;
+    let ws_manifest = cargo_util::paths::read(metadata.workspace_root.join("Cargo.toml").as_ref())?
+        .parse::<toml_edit::Document>()?;
+
+    let mut manifest = toml_edit::Document::new();
+    manifest["package"] = toml_edit::table();
+    manifest["package"]["name"] = toml_edit::value(&*package.name);
+    manifest["package"]["version"] = toml_edit::value(package.version.to_string());
+    manifest["package"]["edition"] = toml_edit::value(&*package.edition);
+    if let Some(license) = &package.license {
+        manifest["package"]["license"] = toml_edit::value(&**license);
+    }
+    if let Some(repository) = &package.repository {
+        manifest["package"]["repository"] = toml_edit::value(&**repository);
+    }
+    manifest["package"]["metadata"] = orig_manifest["package"]["metadata"].clone();
+    for key in ["dependencies", "dev-dependencies", "features"] {
+        manifest[key] = orig_manifest[key].clone();
+        if let toml_edit::Item::Table(table) = &mut manifest[key] {
+            absolutize_paths(table, package.manifest_dir());
+        }
+    }
+
+    manifest["workspace"] = toml_edit::table();
+    manifest["workspace"]["metadata"] = ws_manifest["workspace"]["metadata"].clone();
+    if let toml_edit::Item::Table(patch) = &ws_manifest["patch"] {
+        let mut patch = patch.clone();
+        for (_, table) in patch.iter_mut() {
+            if let toml_edit::Item::Table(table) = table {
+                absolutize_paths(table, &metadata.workspace_root);
+            }
+        }
+        manifest["patch"] = toml_edit::Item::Table(patch);
+    }
+    Ok(manifest)
+}
+
+fn push_target(manifest: &mut toml_edit::Document, target: &cm::Target) {
+    let key = if target.is_example() {
+        "example"
+    } else {
+        "bin"
+    };
+    if manifest[key].is_none() {
+        manifest[key] = toml_edit::Item::ArrayOfTables(toml_edit::ArrayOfTables::new());
+    }
+    let mut tbl = toml_edit::Table::new();
+    tbl["name"] = toml_edit::value(&*target.name);
+    tbl["path"] = toml_edit::value(target.src_path.as_str());
+    if let toml_edit::Item::ArrayOfTables(arr) = &mut manifest[key] {
+        arr.push(tbl);
+    }
+}
+
+fn cache_subdir(
+    cache_dir: &Path,
+    kind: &str,
+    package: &cm::Package,
+    key: &str,
+) -> anyhow::Result<Utf8PathBuf> {
+    Ok(Utf8Path::from_path(cache_dir)
+        .with_context(|| format!("`{}` is not valid UTF-8", cache_dir.display()))?
+        .join(kind)
+        .join(format!(
+            "{}-{}",
+            package.name,
+            &format!("{:x}", md5::compute(key))[..16],
+        )))
+}
+
+fn absolutize_paths(table: &mut toml_edit::Table, base_dir: &Utf8Path) {
+    for (_, value) in table.iter_mut() {
+        if !value["path"].is_none() {
+            if let toml_edit::Item::Value(value) = &mut value["path"] {
+                if let Some(possibly_rel_path) = value.as_str() {
+                    *value = base_dir.join(possibly_rel_path).into_string().into();
+                }
+            }
+        }
+    }
+}
+
+fn write_if_changed(path: &Utf8Path, contents: &[u8]) -> anyhow::Result<()> {
+    if path.exists() && cargo_util::paths::read_bytes(path.as_ref())? == contents {
+        return Ok(());
+    }
+    cargo_util::paths::write(path, contents)
 }
 
 pub(crate) trait MetadataExt {
+    fn root_targets(&self) -> Vec<(&cm::Target, &cm::Package)>;
     fn exactly_one_target(&self) -> anyhow::Result<(&cm::Target, &cm::Package)>;
     fn lib_target(&self) -> anyhow::Result<(&cm::Target, &cm::Package)>;
     fn bin_target_by_name<'a>(
@@ -251,6 +700,7 @@ pub(crate) trait MetadataExt {
         need_dev_deps: bool,
         cargo_udeps_outcome: &HashSet<String>,
         exclude: &[PkgSpec],
+        shell: &mut Shell,
     ) -> anyhow::Result<BTreeMap<&'a cm::PackageId, (&'a cm::Target, String)>>;
     fn dep_lib_by_extern_crate_name<'a>(
         &'a self,
@@ -265,17 +715,19 @@ pub(crate) trait MetadataExt {
 }
 
 impl MetadataExt for cm::Metadata {
+    fn root_targets(&self) -> Vec<(&cm::Target, &cm::Package)> {
+        let root_package = self.root_package();
+        targets_in_ws(self)
+            .filter(|(t, p)| {
+                (t.is_lib() || t.is_bin() || t.is_example())
+                    && root_package.map_or(true, |r| r.id == p.id)
+            })
+            .collect()
+    }
+
     fn exactly_one_target(&self) -> anyhow::Result<(&cm::Target, &cm::Package)> {
         let root_package = self.root_package();
-        match (
-            &*targets_in_ws(self)
-                .filter(|(t, p)| {
-                    (t.is_lib() || t.is_bin() || t.is_example())
-                        && root_package.map_or(true, |r| r.id == p.id)
-                })
-                .collect::<Vec<_>>(),
-            root_package,
-        ) {
+        match (&*self.root_targets(), root_package) {
             ([], Some(root_package)) => {
                 bail!("no lib/bin/example target in `{}`", root_package.name)
             }
@@ -287,19 +739,7 @@ impl MetadataExt for cm::Metadata {
                  available targets: {}\n\
                  note: currently `cargo-equip` does not support the `default-run` manifest key.",
                 ts.iter()
-                    .map(|(target, _)| format!(
-                        "{}{}",
-                        &target.name,
-                        if target.is_lib() {
-                            " (lib)"
-                        } else if target.is_bin() {
-                            " (bin)"
-                        } else if target.is_example() {
-                            " (example)"
-                        } else {
-                            unreachable!()
-                        }
-                    ))
+                    .map(|(target, _)| target.display_name())
                     .format(", "),
             ),
         }
@@ -343,10 +783,21 @@ impl MetadataExt for cm::Metadata {
         &'a self,
         src_path: &Path,
     ) -> anyhow::Result<(&'a cm::Target, &'a cm::Package)> {
-        match *targets_in_ws(self)
-            .filter(|(t, _)| t.src_path == src_path)
-            .collect::<Vec<_>>()
-        {
+        let canonicalize = |p: &Path| p.canonicalize().unwrap_or_else(|_| p.to_owned());
+        let src_path_canonicalized = &canonicalize(src_path);
+
+        let mut targets = targets_in_ws(self)
+            .filter(|(t, _)| canonicalize(t.src_path.as_ref()) == *src_path_canonicalized)
+            .collect::<Vec<_>>();
+        if targets.is_empty() && !src_path.exists() {
+            if let Some(file_name) = src_path.file_name() {
+                targets = targets_in_ws(self)
+                    .filter(|(t, _)| t.src_path.file_name() == file_name.to_str())
+                    .collect();
+            }
+        }
+
+        match *targets {
             [] => bail!(
                 "`{}` is not the main source file of any bin targets in this workspace ",
                 src_path.display(),
@@ -365,6 +816,7 @@ impl MetadataExt for cm::Metadata {
         need_dev_deps: bool,
         cargo_udeps_outcome: &HashSet<String>,
         exclude: &[PkgSpec],
+        shell: &mut Shell,
     ) -> anyhow::Result<BTreeMap<&'a cm::PackageId, (&'a cm::Target, String)>> {
         let package = &self[package_id];
 
@@ -372,7 +824,8 @@ impl MetadataExt for cm::Metadata {
             .dependencies
             .iter()
             .filter(|cm::Dependency { kind, .. }| {
-                [cm::DependencyKind::Normal, cm::DependencyKind::Development].contains(kind)
+                *kind == cm::DependencyKind::Normal
+                    || need_dev_deps && *kind == cm::DependencyKind::Development
             })
             .flat_map(|cm::Dependency { rename, .. }| rename)
             .collect::<HashSet<_>>();
@@ -439,16 +892,19 @@ impl MetadataExt for cm::Metadata {
             bail!("this tool requires Rust 1.41+ for calculating dependencies");
         }
 
+        // Packages whose library targets are only `cdylib`s and/or `staticlib`s.
+        let mut skipped = vec![];
+
         let mut deps = nodes[package_id]
             .deps
             .iter()
             .filter(|node_dep| satisfies(node_dep, need_dev_deps))
             .flat_map(|node_dep| {
                 let lib_package = &self[&node_dep.pkg];
-                let lib_target =
-                    lib_package.targets.iter().find(|cm::Target { kind, .. }| {
-                        *kind == ["lib".to_owned()] || *kind == ["proc-macro".to_owned()]
-                    })?;
+                let lib_target = lib_package.lib_like_target().or_else(|| {
+                    skipped.push(lib_package);
+                    None
+                })?;
                 let (lib_extern_crate_name, lib_name_in_toml) = if renames.contains(&node_dep.name)
                 {
                     (node_dep.name.clone(), &node_dep.name)
@@ -476,7 +932,7 @@ impl MetadataExt for cm::Metadata {
         while {
             let next = deps
                 .iter()
-                .filter(|(_, (cm::Target { kind, .. }, _))| *kind == ["lib".to_owned()])
+                .filter(|(_, (target, _))| target.is_lib())
                 .map(|(package_id, _)| nodes[package_id])
                 .flat_map(|cm::Node { deps, .. }| deps)
                 .filter(|node_dep| {
@@ -484,8 +940,9 @@ impl MetadataExt for cm::Metadata {
                 })
                 .flat_map(|cm::NodeDep { pkg, .. }| {
                     let package = &self[pkg];
-                    let target = package.targets.iter().find(|cm::Target { kind, .. }| {
-                        *kind == ["lib".to_owned()] || *kind == ["proc-macro".to_owned()]
+                    let target = package.lib_like_target().or_else(|| {
+                        skipped.push(package);
+                        None
                     })?;
                     let mut extern_crate_name = format!(
                         "__{}_{}",
@@ -506,6 +963,12 @@ impl MetadataExt for cm::Metadata {
             !next_is_empty
         } {}
 
+        for package in skipped {
+            shell.warn(format!(
+                "`{}` has no library target that can be linked from Rust. skipping",
+                package.id,
+            ))?;
+        }
         Ok(deps)
     }
 
@@ -545,10 +1008,7 @@ impl MetadataExt for cm::Metadata {
                 .iter()
                 .map(|dep_id| &self[dep_id])
                 .flat_map(|p| p.targets.iter().map(move |t| (t, p)))
-                .find(|(t, _)| {
-                    t.crate_name() == extern_crate_name
-                        && (*t.kind == ["lib".to_owned()] || *t.kind == ["proc-macro".to_owned()])
-                })
+                .find(|(t, _)| t.crate_name() == extern_crate_name && t.is_lib_like())
                 .map(|(_, p)| p)
                 .or_else(|| {
                     matches!(package.lib_like_target(), Some(t) if t.crate_name() == extern_crate_name)
@@ -567,6 +1027,7 @@ impl MetadataExt for cm::Metadata {
         let renames = package
             .dependencies
             .iter()
+            .filter(|cm::Dependency { kind, .. }| *kind == cm::DependencyKind::Normal)
             .flat_map(|cm::Dependency { rename, .. }| rename)
             .collect::<HashSet<_>>();
 
@@ -581,25 +1042,16 @@ impl MetadataExt for cm::Metadata {
         Ok(deps
             .iter()
             .filter(|cm::NodeDep { pkg, dep_kinds, .. }| {
-                matches!(
-                    &**dep_kinds,
-                    [cm::DepKindInfo {
-                        kind: cm::DependencyKind::Normal,
-                        ..
-                    }]
-                ) && only.contains(pkg)
+                dep_kinds
+                    .iter()
+                    .any(|cm::DepKindInfo { kind, .. }| *kind == cm::DependencyKind::Normal)
+                    && only.contains(pkg)
             })
             .flat_map(|cm::NodeDep { name, pkg, .. }| {
                 let extern_crate_name = if renames.contains(name) {
                     name.clone()
                 } else {
-                    self[pkg]
-                        .targets
-                        .iter()
-                        .find(|cm::Target { kind, .. }| {
-                            *kind == ["lib".to_owned()] || *kind == ["proc-macro".to_owned()]
-                        })?
-                        .crate_name()
+                    self[pkg].lib_like_target()?.crate_name()
                 };
                 Some((pkg, extern_crate_name))
             })
@@ -612,18 +1064,49 @@ fn target_by_kind_and_name<'a>(
     kind: &str,
     name: &str,
 ) -> anyhow::Result<(&'a cm::Target, &'a cm::Package)> {
-    match *targets_in_ws(metadata)
-        .filter(|(t, _)| t.name == name && t.kind == [kind.to_owned()])
+    let targets = targets_in_ws(metadata)
+        .filter(|(t, _)| t.has_kind(kind))
+        .collect::<Vec<_>>();
+
+    match *targets
+        .iter()
+        .filter(|(t, _)| t.name == name)
         .collect::<Vec<_>>()
     {
-        [] => bail!("no {} target named `{}`", kind, name),
-        [target] => Ok(target),
+        [] => {}
+        [target] => return Ok(*target),
         [..] => bail!(
             "multiple {} targets named `{}` in this workspace",
             kind,
             name,
         ),
     }
+
+    // Falls back to suffix matches, then substring matches (e.g. `a` for `abc300_a`).
+    let matches = [
+        |t: &cm::Target, name: &str| t.name.ends_with(name),
+        |t: &cm::Target, name: &str| t.name.contains(name),
+    ]
+    .iter()
+    .map(|p| {
+        targets
+            .iter()
+            .filter(|(t, _)| !name.is_empty() && p(t, name))
+            .collect::<Vec<_>>()
+    })
+    .find(|matches| !matches.is_empty())
+    .unwrap_or_default();
+
+    match *matches {
+        [] => bail!("no {} target named `{}`", kind, name),
+        [target] => Ok(*target),
+        [..] => bail!(
+            "{} target name `{}` is ambiguous. candidates: {}",
+            kind,
+            name,
+            matches.iter().map(|(t, _)| &t.name).format(", "),
+        ),
+    }
 }
 
 fn targets_in_ws(metadata: &cm::Metadata) -> impl Iterator<Item = (&cm::Target, &cm::Package)> {
@@ -640,7 +1123,12 @@ pub(crate) trait PackageExt {
     fn has_proc_macro(&self) -> bool;
     fn lib_like_target(&self) -> Option<&cm::Target>;
     fn manifest_dir(&self) -> &Utf8Path;
-    fn read_license_text(&self, mine: &[User], cache_dir: &Path) -> anyhow::Result<Option<String>>;
+    fn read_license_text(
+        &self,
+        mine: &[User],
+        cache_dir: &Path,
+        offline: bool,
+    ) -> anyhow::Result<Option<String>>;
 }
 
 impl PackageExt for cm::Package {
@@ -657,17 +1145,20 @@ impl PackageExt for cm::Package {
     }
 
     fn lib_like_target(&self) -> Option<&cm::Target> {
-        self.targets.iter().find(|cm::Target { kind, .. }| {
-            [&["lib".to_owned()][..], &["proc-macro".to_owned()][..]].contains(&&**kind)
-        })
+        self.targets.iter().find(|t| t.is_lib_like())
     }
 
     fn manifest_dir(&self) -> &Utf8Path {
         self.manifest_path.parent().expect("should not be empty")
     }
 
-    fn read_license_text(&self, mine: &[User], cache_dir: &Path) -> anyhow::Result<Option<String>> {
-        license::read_non_unlicense_license_file(self, mine, cache_dir)
+    fn read_license_text(
+        &self,
+        mine: &[User],
+        cache_dir: &Path,
+        offline: bool,
+    ) -> anyhow::Result<Option<String>> {
+        license::read_non_unlicense_license_file(self, mine, cache_dir, offline)
     }
 }
 
@@ -694,40 +1185,69 @@ impl PackageIdExt for cm::PackageId {
 }
 
 pub(crate) trait TargetExt {
+    fn has_kind(&self, kind: &str) -> bool;
     fn is_bin(&self) -> bool;
     fn is_example(&self) -> bool;
     fn is_custom_build(&self) -> bool;
     fn is_lib(&self) -> bool;
     fn is_proc_macro(&self) -> bool;
+    fn is_lib_like(&self) -> bool;
     fn crate_name(&self) -> String;
+    fn display_name(&self) -> String;
     fn target_option(&self) -> Vec<&str>;
 }
 
 impl TargetExt for cm::Target {
+    /// Whether `kind` is one of the kinds (the crate types for libraries) of this target.
+    fn has_kind(&self, kind: &str) -> bool {
+        self.kind.iter().any(|k| k == kind)
+    }
+
     fn is_bin(&self) -> bool {
-        self.kind == ["bin".to_owned()]
+        self.has_kind("bin")
     }
 
     fn is_example(&self) -> bool {
-        self.kind == ["example".to_owned()]
+        self.has_kind("example")
     }
 
     fn is_custom_build(&self) -> bool {
-        self.kind == ["custom-build".to_owned()]
+        self.has_kind("custom-build")
     }
 
+    /// `lib`, `rlib`, or `dylib`, possibly with other crate types such as `cdylib`.
     fn is_lib(&self) -> bool {
-        self.kind == ["lib".to_owned()]
+        ["lib", "rlib", "dylib"].iter().any(|k| self.has_kind(k))
     }
 
     fn is_proc_macro(&self) -> bool {
-        self.kind == ["proc-macro".to_owned()]
+        self.has_kind("proc-macro")
+    }
+
+    fn is_lib_like(&self) -> bool {
+        self.is_lib() || self.is_proc_macro()
     }
 
     fn crate_name(&self) -> String {
         self.name.replace('-', "_")
     }
 
+    fn display_name(&self) -> String {
+        format!(
+            "{}{}",
+            &self.name,
+            if self.is_lib() {
+                " (lib)"
+            } else if self.is_bin() {
+                " (bin)"
+            } else if self.is_example() {
+                " (example)"
+            } else {
+                ""
+            }
+        )
+    }
+
     fn target_option(&self) -> Vec<&str> {
         if self.is_lib() {
             vec!["--lib"]
@@ -739,7 +1259,7 @@ impl TargetExt for cm::Target {
     }
 }
 
-trait SourceExt {
+pub(crate) trait SourceExt {
     fn rev_git(&self) -> Option<(&str, &str)>;
 }
 
@@ -752,3 +1272,136 @@ impl SourceExt for cm::Source {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use std::{fs, path::Path};
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    const PACKAGE: &str = "[package]\nname = \"a\"\nversion = \"0.0.0\"\n";
+
+    #[test]
+    fn locate_project_nested_workspace() {
+        let root = tempfile::tempdir().unwrap();
+        let root = &root.path().canonicalize().unwrap();
+        write(
+            root,
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"crates/*\"]\nexclude = [\"contest\"]\n",
+        );
+        write(root, "crates/lib/Cargo.toml", PACKAGE);
+        write(
+            root,
+            "contest/Cargo.toml",
+            "[workspace]\nmembers = [\"abc*\"]\n",
+        );
+        write(root, "contest/abc001/Cargo.toml", PACKAGE);
+        write(root, "contest/abc001/src/Cargo.toml", "");
+
+        assert_eq!(
+            root.join("crates/lib/Cargo.toml"),
+            super::locate_project(&root.join("crates/lib")).unwrap(),
+        );
+        assert_eq!(
+            root.join("contest/Cargo.toml"),
+            super::locate_project(&root.join("contest")).unwrap(),
+        );
+        assert_eq!(
+            root.join("contest/abc001/Cargo.toml"),
+            super::locate_project(&root.join("contest/abc001/src")).unwrap(),
+        );
+        assert_eq!(
+            root.join("contest/Cargo.toml"),
+            super::workspace_root_manifest(&root.join("contest")).unwrap(),
+        );
+        assert!(super::workspace_root_manifest(&root.join("crates/lib")).is_err());
+    }
+
+    #[test]
+    fn locate_project_not_member() {
+        let root = tempfile::tempdir().unwrap();
+        let root = &root.path().canonicalize().unwrap();
+        write(
+            root,
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"crates/*\"]\n",
+        );
+        write(root, "other/Cargo.toml", PACKAGE);
+
+        // Left to Cargo, which fails with its own message.
+        assert_eq!(
+            root.join("other/Cargo.toml"),
+            super::locate_project(&root.join("other")).unwrap(),
+        );
+    }
+
+    #[test]
+    fn locate_project_path_dependency_member() {
+        let root = tempfile::tempdir().unwrap();
+        let root = &root.path().canonicalize().unwrap();
+        write(
+            root,
+            "Cargo.toml",
+            indoc! {r#"
+                [package]
+                name = "bin"
+                version = "0.0.0"
+
+                [dependencies]
+                lib = { path = "lib" }
+
+                [workspace]
+            "#},
+        );
+        write(root, "src/main.rs", "fn main() {}\n");
+        write(
+            root,
+            "lib/Cargo.toml",
+            "[package]\nname = \"lib\"\nversion = \"0.0.0\"\n",
+        );
+        write(root, "lib/src/lib.rs", "");
+
+        let manifest_path = super::locate_project(&root.join("lib/src")).unwrap();
+        assert_eq!(root.join("lib/Cargo.toml"), manifest_path);
+        let metadata = super::cargo_metadata(&manifest_path, root, &[], true).unwrap();
+        assert_eq!(root.to_str(), Some(metadata.workspace_root.as_str()));
+    }
+
+    #[test]
+    fn check_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = &dir.path().join("state.json");
+        assert_eq!(
+            super::CheckState::default(),
+            super::CheckState::read(path).unwrap()
+        );
+
+        fs::write(path, r#"{"last-checked":"0123"}"#).unwrap();
+        assert_eq!(
+            super::CheckState::default(),
+            super::CheckState::read(path).unwrap()
+        );
+
+        for (key, hash) in [("a-bin-a", "0123"), ("a-bin-b", "4567")] {
+            let mut state = super::CheckState::read(path).unwrap();
+            state.last_checked.insert(key.to_owned(), hash.to_owned());
+            state.write(path).unwrap();
+        }
+        let state = super::CheckState::read(path).unwrap();
+        assert_eq!(
+            Some("0123"),
+            state.last_checked.get("a-bin-a").map(|s| &**s)
+        );
+        assert_eq!(
+            Some("4567"),
+            state.last_checked.get("a-bin-b").map(|s| &**s)
+        );
+        assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+    }
+}
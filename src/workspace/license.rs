@@ -3,7 +3,7 @@ use crate::{
     workspace::{PackageExt as _, SourceExt as _},
     User,
 };
-use anyhow::{anyhow, Context as _};
+use anyhow::{anyhow, bail, Context as _};
 use cargo_metadata as cm;
 use cargo_util::ProcessBuilder;
 use maplit::btreeset;
@@ -18,9 +18,10 @@ pub(super) fn read_non_unlicense_license_file(
     package: &cm::Package,
     mine: &[User],
     cache_dir: &Path,
+    offline: bool,
 ) -> anyhow::Result<Option<String>> {
     if !mine.is_empty() {
-        let users = users(package, cache_dir)?;
+        let users = users(package, cache_dir, offline)?;
         if mine.iter().any(|u| users.contains(u)) {
             return Ok(None);
         }
@@ -55,7 +56,7 @@ pub(super) fn read_non_unlicense_license_file(
     }
 }
 
-fn users(package: &cm::Package, cache_dir: &Path) -> anyhow::Result<BTreeSet<User>> {
+fn users(package: &cm::Package, cache_dir: &Path, offline: bool) -> anyhow::Result<BTreeSet<User>> {
     let path = &cache_dir.join("owners.json");
     let cur_cache = if path.exists() {
         serde_json::from_str(&cargo_util::paths::read(path)?)?
@@ -72,6 +73,12 @@ fn users(package: &cm::Package, cache_dir: &Path) -> anyhow::Result<BTreeSet<Use
                 .or_default()
                 .entry(package.version.clone())
             {
+                btree_map::Entry::Vacant(_) if offline => {
+                    bail!(
+                        "the owners of `{}` are not cached. run once without `--offline`",
+                        package.id,
+                    );
+                }
                 btree_map::Entry::Vacant(entry) => {
                     let owners = retrieve_owner_urls(&package.name, cache_dir)?
                         .flat_map(|url| {
@@ -0,0 +1,30 @@
+use std::{env, path::PathBuf, str};
+
+#[test]
+fn with_cargo() -> anyhow::Result<()> {
+    let assert = assert_cmd::Command::cargo_bin("cargo-equip")?
+        .args([
+            "equip",
+            "--toolchain",
+            &env::var("CARGO_EQUIP_TEST_NIGHTLY_TOOLCHAIN")
+                .unwrap_or_else(|_| "nightly".to_owned()),
+            "--cargo",
+            "cargo",
+            "--bin",
+            "a",
+            "--no-check",
+            "--offline",
+            "bench-size",
+        ])
+        .current_dir(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("tests")
+                .join("multi-bin"),
+        )
+        .assert()
+        .success();
+    let stdout = str::from_utf8(&assert.get_output().stdout)?;
+    assert!(stdout.starts_with("strategy"));
+    assert!(stdout.lines().count() > 2);
+    Ok(())
+}
@@ -0,0 +1,194 @@
+#![cfg(unix)]
+
+use serde_json::json;
+use std::{
+    env,
+    io::{BufRead as _, BufReader, Write as _},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn bundle() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let daemon = Daemon::spawn(&dir.path().join("daemon.sock"))?;
+    let mut conn = daemon.connect()?;
+
+    let response = conn.request(&json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "bundle",
+        "params": { "args": ["--bin", "b"] },
+    }))?;
+    assert_eq!(json!("2.0"), response["jsonrpc"]);
+    assert_eq!(json!(1), response["id"]);
+    let code = response["result"]["code"].as_str().unwrap();
+    assert!(code.contains("add(3, 4)"));
+    assert!(code.contains("pub mod multi_bin {"));
+
+    let response = conn.request(&json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "bundle",
+        "params": {
+            "src": "src/bin/a.rs",
+            "code": "use multi_bin::add;\n\nfn main() {\n    println!(\"{}\", add(5, 6));\n}\n",
+        },
+    }))?;
+    let code = response["result"]["code"].as_str().unwrap();
+    assert!(code.contains("add(5, 6)"));
+    assert!(!code.contains("add(1, 2)"));
+
+    daemon.shutdown(conn)
+}
+
+#[test]
+fn malformed_request() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let daemon = Daemon::spawn(&dir.path().join("daemon.sock"))?;
+    let mut conn = daemon.connect()?;
+
+    let response = conn.request_line("{\"jsonrpc\":")?;
+    assert_eq!(json!(null), response["id"]);
+    assert_eq!(json!(-32700), response["error"]["code"]);
+
+    let response = conn.request(&json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "bundle",
+        "params": { "unknown": true },
+    }))?;
+    assert_eq!(json!(1), response["id"]);
+    assert_eq!(json!(-32602), response["error"]["code"]);
+
+    let response = conn.request(&json!({ "jsonrpc": "2.0", "id": 2, "method": "build" }))?;
+    assert_eq!(json!(-32601), response["error"]["code"]);
+
+    daemon.shutdown(conn)
+}
+
+#[test]
+fn process_global_options() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let daemon = Daemon::spawn(&dir.path().join("daemon.sock"))?;
+    let mut conn = daemon.connect()?;
+
+    for (id, params) in vec![
+        json!({ "args": ["--bin", "a", "--cargo", "cargo"] }),
+        json!({ "args": ["--bin", "a", "--cargo=cargo"] }),
+        json!({ "args": ["--src", "-"] }),
+        json!({ "args": ["--src=-"] }),
+        json!({ "src": "-" }),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let response = conn.request(&json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "bundle",
+            "params": params,
+        }))?;
+        assert_eq!(json!(-32602), response["error"]["code"], "{}", params);
+    }
+
+    let response = conn.request(&json!({
+        "jsonrpc": "2.0",
+        "id": 5,
+        "method": "bundle",
+        "params": { "args": ["--bin", "a"] },
+    }))?;
+    assert!(response["result"]["code"]
+        .as_str()
+        .unwrap()
+        .contains("add(1, 2)"));
+
+    daemon.shutdown(conn)
+}
+
+struct Daemon {
+    child: Child,
+    socket: PathBuf,
+}
+
+impl Daemon {
+    fn spawn(socket: &Path) -> anyhow::Result<Self> {
+        let child = Command::new(assert_cmd::cargo::cargo_bin("cargo-equip"))
+            .args(["equip", "--toolchain", &nightly(), "--no-check", "daemon"])
+            .arg("--socket")
+            .arg(socket)
+            .current_dir(multi_bin())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let daemon = Self {
+            child,
+            socket: socket.to_owned(),
+        };
+
+        let started = Instant::now();
+        while !daemon.socket.exists() {
+            if started.elapsed() > Duration::from_secs(60) {
+                anyhow::bail!("the daemon did not start listening");
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        Ok(daemon)
+    }
+
+    fn connect(&self) -> anyhow::Result<Connection> {
+        let stream = UnixStream::connect(&self.socket)?;
+        stream.set_read_timeout(Some(Duration::from_secs(120)))?;
+        Ok(Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            stream,
+        })
+    }
+
+    fn shutdown(mut self, mut conn: Connection) -> anyhow::Result<()> {
+        let response = conn.request(&json!({ "jsonrpc": "2.0", "id": 0, "method": "shutdown" }))?;
+        assert_eq!(json!(null), response["result"]);
+        assert!(self.child.wait()?.success());
+        assert!(!self.socket.exists());
+        Ok(())
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+struct Connection {
+    stream: UnixStream,
+    reader: BufReader<UnixStream>,
+}
+
+impl Connection {
+    fn request(&mut self, request: &serde_json::Value) -> anyhow::Result<serde_json::Value> {
+        self.request_line(&request.to_string())
+    }
+
+    fn request_line(&mut self, line: &str) -> anyhow::Result<serde_json::Value> {
+        writeln!(self.stream, "{}", line)?;
+        let mut response = "".to_owned();
+        self.reader.read_line(&mut response)?;
+        Ok(serde_json::from_str(&response)?)
+    }
+}
+
+fn nightly() -> String {
+    env::var("CARGO_EQUIP_TEST_NIGHTLY_TOOLCHAIN").unwrap_or_else(|_| "nightly".to_owned())
+}
+
+fn multi_bin() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("multi-bin")
+}
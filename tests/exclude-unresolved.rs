@@ -0,0 +1,66 @@
+use std::{env, fs};
+
+#[test]
+fn unresolved_dependency_is_kept_in_check_manifest() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let root = &dir.path().canonicalize()?;
+    let write = |path: &str, content: &str| -> anyhow::Result<()> {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, content)?;
+        Ok(())
+    };
+    write(
+        "Cargo.toml",
+        "[package]\nname = \"a\"\nversion = \"0.0.0\"\nedition = \"2018\"\n\n\
+         [dependencies]\nbundled = { path = \"bundled\" }\nunresolved = { path = \"unresolved\" }\n\n\
+         [workspace]\n",
+    )?;
+    write(
+        "src/main.rs",
+        "fn main() {\n    bundled::f();\n    unresolved::g();\n}\n",
+    )?;
+    write(
+        "bundled/Cargo.toml",
+        "[package]\nname = \"bundled\"\nversion = \"0.0.0\"\nedition = \"2018\"\n",
+    )?;
+    write("bundled/src/lib.rs", "pub fn f() {}\n")?;
+    write(
+        "unresolved/Cargo.toml",
+        "[package]\nname = \"unresolved\"\nversion = \"0.0.0\"\nedition = \"2018\"\n\n\
+         [lib]\npath = \"missing.rs\"\n",
+    )?;
+
+    // The check fails since the source of `unresolved` is missing, but the manifest is written
+    // before it.
+    let assert = assert_cmd::Command::cargo_bin("cargo-equip")?
+        .args([
+            "equip",
+            "--toolchain",
+            &env::var("CARGO_EQUIP_TEST_NIGHTLY_TOOLCHAIN")
+                .unwrap_or_else(|_| "nightly".to_owned()),
+            "--bin",
+            "a",
+            "--exclude-unresolved",
+            "--offline",
+            "--output",
+        ])
+        .arg(root.join("out.rs"))
+        .current_dir(root)
+        .assert();
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).into_owned();
+    assert!(stderr.contains("leaving it unbundled"), "{}", stderr);
+
+    let manifest = fs::read_to_string(
+        root.join("target")
+            .join("cargo-equip")
+            .join("check-pkg")
+            .join("a-bin-a")
+            .join("Cargo.toml"),
+    )?;
+    let manifest = manifest.parse::<toml_edit::Document>()?;
+    let dependencies = manifest["dependencies"].as_table().unwrap();
+    assert!(dependencies.contains_key("unresolved"), "{}", manifest);
+    assert!(!dependencies.contains_key("bundled"), "{}", manifest);
+    Ok(())
+}
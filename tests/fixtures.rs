@@ -0,0 +1,112 @@
+use cargo_equip::shell::Shell;
+use insta::assert_snapshot;
+use once_cell::sync::Lazy;
+use ra_ap_paths::AbsPathBuf;
+use std::{
+    cell::RefCell,
+    env,
+    io::{self, Write},
+    path::Path,
+    rc::Rc,
+    sync::{Mutex, MutexGuard},
+};
+use structopt::StructOpt as _;
+
+macro_rules! fixture_tests {
+    ($($name:ident;)*) => {
+        $(
+            #[test]
+            fn $name() -> anyhow::Result<()> {
+                fixture_test(stringify!($name), LOCK.lock().unwrap())
+            }
+        )*
+    };
+}
+
+static LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+fixture_tests! {
+    cfgs;
+    crate_paths;
+    facades;
+    macros;
+}
+
+/// Bundles the bin of `tests/fixtures/{name}` and takes snapshots of the output and the code of
+/// each crate after each transformation.
+fn fixture_test(name: &str, _: MutexGuard<'_, ()>) -> anyhow::Result<()> {
+    let workspace_root = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name);
+    let snapshot_dir = tempfile::Builder::new()
+        .prefix("cargo-equip-test-")
+        .tempdir()?;
+    let stdout = Rc::new(RefCell::default());
+
+    cargo_equip::run(
+        cargo_equip::Opt::from_iter_safe(&[
+            "",
+            "equip",
+            "--toolchain",
+            &env::var("CARGO_EQUIP_TEST_NIGHTLY_TOOLCHAIN")
+                .unwrap_or_else(|_| "nightly".to_owned()),
+            "--bin",
+            name,
+            "--snapshot-dir",
+            snapshot_dir.path().to_str().unwrap(),
+        ])?,
+        cargo_equip::Context {
+            cwd: workspace_root.clone(),
+            cargo_equip_exe: AbsPathBuf::assert(env!("CARGO_BIN_EXE_cargo-equip").into()),
+            cache_dir: Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests")
+                .join("cache"),
+            shell: &mut Shell::from_stdout(Box::new(Writer(stdout.clone()))),
+        },
+    )?;
+
+    // Package IDs contain the absolute path of the workspace.
+    let normalize = |code: String| code.replace(workspace_root.to_str().unwrap(), "$DIR");
+
+    let output = String::from_utf8(Rc::try_unwrap(stdout).unwrap().into_inner())?;
+    assert_snapshot!(format!("{}__output", name), normalize(output));
+
+    let mut stages = vec![];
+    let mut dirs = vec![snapshot_dir.path().to_owned()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.file_name() != Some("output.rs".as_ref()) {
+                stages.push(path);
+            }
+        }
+    }
+    stages.sort();
+    for stage in stages {
+        let code = cargo_util::paths::read(&stage)?;
+        let stage = stage
+            .strip_prefix(snapshot_dir.path())?
+            .with_extension("")
+            .iter()
+            .map(|s| s.to_str().unwrap())
+            .collect::<Vec<_>>()
+            .join("__");
+        assert_snapshot!(format!("{}__{}", name, stage), normalize(code));
+    }
+    return Ok(());
+
+    struct Writer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Writer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.borrow_mut().flush()
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! A library with `#[cfg]`s.
+
+/// Enabled by default.
+#[cfg(feature = "a")]
+pub fn a() -> u32 {
+    1
+}
+
+/// Disabled by default.
+#[cfg(feature = "b")]
+pub fn b() -> u32 {
+    2
+}
+
+#[cfg(not(feature = "b"))]
+pub fn b() -> u32 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn it_works() {
+        assert_eq!(1, super::a());
+    }
+}
@@ -0,0 +1,5 @@
+use cfgs::{a, b};
+
+fn main() {
+    println!("{}", a() + b());
+}
@@ -0,0 +1,20 @@
+mod internal_math {
+    pub(crate) fn safe_mod(x: i64, m: i64) -> i64 {
+        x.rem_euclid(m)
+    }
+}
+
+macro_rules! impl_modint {
+    ($name:ident, $modulus:expr) => {
+        #[derive(Clone, Copy, Debug)]
+        pub struct $name(pub i64);
+
+        impl $name {
+            pub fn new(x: i64) -> Self {
+                Self(crate::internal_math::safe_mod(x, $modulus))
+            }
+        }
+    };
+}
+
+impl_modint!(ModInt998244353, 998_244_353);
@@ -0,0 +1,5 @@
+use crate_paths::ModInt998244353 as Mint;
+
+fn main() {
+    println!("{}", Mint::new(-1).0);
+}
@@ -0,0 +1,9 @@
+pub fn f() -> u32 {
+    1
+}
+
+pub mod m {
+    pub fn g() -> u32 {
+        2
+    }
+}
@@ -0,0 +1,5 @@
+use facade::{f, m::g};
+
+fn main() {
+    println!("{}", f() + g());
+}
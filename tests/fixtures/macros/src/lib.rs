@@ -0,0 +1,10 @@
+pub fn f() -> u32 {
+    1
+}
+
+#[macro_export]
+macro_rules! twice {
+    ($e:expr) => {
+        $crate::f() + $e + $e
+    };
+}
@@ -0,0 +1,5 @@
+use macros::twice;
+
+fn main() {
+    println!("{}", twice!(::macros::f()));
+}
@@ -3,14 +3,25 @@ use std::{process::Output, str};
 
 #[test]
 fn helps() -> anyhow::Result<()> {
-    assert_snapshot!("short", run("-h")?);
-    assert_snapshot!("long", run("--help")?);
+    assert_snapshot!("short", run(&["-h"])?);
+    assert_snapshot!("long", run(&["--help"])?);
     Ok(())
 }
 
-fn run(flag: &str) -> anyhow::Result<String> {
+#[test]
+fn completions() -> anyhow::Result<()> {
+    let completions = run(&["completions", "bash"])?;
+    assert!(
+        completions.ends_with("complete -F _cargo-equip -o bashdefault -o default cargo-equip\n")
+    );
+    assert_snapshot!("completions-bash", completions);
+    Ok(())
+}
+
+fn run(args: &[&str]) -> anyhow::Result<String> {
     let assert = assert_cmd::Command::cargo_bin("cargo-equip")?
-        .args(["equip", flag])
+        .arg("equip")
+        .args(args)
         .assert()
         .success();
     let Output { stdout, .. } = assert.get_output();
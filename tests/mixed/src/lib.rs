@@ -0,0 +1,5 @@
+pub mod a {
+    pub fn f() -> u32 {
+        1
+    }
+}
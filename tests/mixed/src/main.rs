@@ -0,0 +1,5 @@
+use mixed::a::f;
+
+fn main() {
+    println!("{}", f());
+}
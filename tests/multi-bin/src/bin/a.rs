@@ -0,0 +1,5 @@
+use multi_bin::add;
+
+fn main() {
+    println!("{}", add(1, 2));
+}
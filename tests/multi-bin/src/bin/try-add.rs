@@ -0,0 +1,6 @@
+use multi_bin::add;
+
+fn main() -> Result<(), std::num::ParseIntError> {
+    println!("{}", add("5".parse()?, 6));
+    Ok(())
+}
@@ -0,0 +1,38 @@
+#![cfg(unix)]
+
+use std::{env, fs, os::unix::fs::symlink, path::PathBuf};
+
+#[test]
+fn symlink_is_kept() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let target = &dir.path().join("main.rs");
+    let link = &dir.path().join("submission.rs");
+    fs::write(target, "")?;
+    symlink(target, link)?;
+
+    assert_cmd::Command::cargo_bin("cargo-equip")?
+        .args([
+            "equip",
+            "--toolchain",
+            &env::var("CARGO_EQUIP_TEST_NIGHTLY_TOOLCHAIN")
+                .unwrap_or_else(|_| "nightly".to_owned()),
+            "--bin",
+            "a",
+            "--no-check",
+            "--backup",
+            "--output",
+        ])
+        .arg(link)
+        .current_dir(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("tests")
+                .join("multi-bin"),
+        )
+        .assert()
+        .success();
+
+    assert!(fs::symlink_metadata(link)?.file_type().is_symlink());
+    assert!(fs::read_to_string(target)?.contains("add(1, 2)"));
+    assert_eq!("", fs::read_to_string(dir.path().join("main.rs.bak"))?);
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use std::{env, fs, path::PathBuf, process::Command, str};
+
+#[test]
+fn libraries_are_expanded_once() -> anyhow::Result<()> {
+    let assert = assert_cmd::Command::cargo_bin("cargo-equip")?
+        .args([
+            "equip",
+            "--toolchain",
+            &env::var("CARGO_EQUIP_TEST_NIGHTLY_TOOLCHAIN")
+                .unwrap_or_else(|_| "nightly".to_owned()),
+            "--bin",
+            "a",
+            "--bin",
+            "b",
+            "--section-header",
+            "// ===== {bin} =====",
+        ])
+        .current_dir(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("tests")
+                .join("multi-bin"),
+        )
+        .assert()
+        .success();
+    let stdout = str::from_utf8(&assert.get_output().stdout)?;
+
+    let a = stdout.find("// ===== a =====\n").unwrap();
+    let b = stdout.find("// ===== b =====\n").unwrap();
+    let libs = stdout.find("mod __cargo_equip {").unwrap();
+    assert!(a < b && b < libs);
+    assert!(stdout[a..b].contains("add(1, 2)"));
+    assert!(stdout[b..libs].contains("add(3, 4)"));
+    assert_eq!(1, stdout.matches("mod __cargo_equip {").count());
+    assert_eq!(1, stdout.matches("pub mod multi_bin {\n").count());
+    Ok(())
+}
+
+#[test]
+fn packet_compiles() -> anyhow::Result<()> {
+    let assert = assert_cmd::Command::cargo_bin("cargo-equip")?
+        .args([
+            "equip",
+            "--toolchain",
+            &env::var("CARGO_EQUIP_TEST_NIGHTLY_TOOLCHAIN")
+                .unwrap_or_else(|_| "nightly".to_owned()),
+            "--bin",
+            "a",
+            "--bin",
+            "b",
+            "--bin",
+            "try-add",
+        ])
+        .current_dir(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("tests")
+                .join("multi-bin"),
+        )
+        .assert()
+        .success();
+    let stdout = str::from_utf8(&assert.get_output().stdout)?;
+    assert!(stdout.contains("mod bin_a {\n"));
+    assert!(stdout.contains("mod bin_try_add {\n"));
+
+    let dir = tempfile::tempdir()?;
+    let src = &dir.path().join("packet.rs");
+    let exe = &dir.path().join("packet");
+    fs::write(src, stdout)?;
+    let status = Command::new("rustc")
+        .args(["--edition", "2018", "-o"])
+        .arg(exe)
+        .arg(src)
+        .status()?;
+    assert!(status.success());
+
+    let run = |args: &[&str]| -> anyhow::Result<String> {
+        let output = Command::new(exe).args(args).output()?;
+        assert!(output.status.success());
+        Ok(String::from_utf8(output.stdout)?)
+    };
+    assert_eq!("3\n", run(&[])?);
+    assert_eq!("7\n", run(&["b"])?);
+    assert_eq!("11\n", run(&["try-add"])?);
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use std::{env, path::PathBuf, str};
+
+#[test]
+fn entry_point() -> anyhow::Result<()> {
+    let output = cargo_equip(&["--bin", "a", "--profile", "judge", "--no-check"])?;
+    assert!(output.contains("pub fn solve() {"));
+    assert!(output.contains("\nfn main() {\n    solve()\n}\n"));
+
+    let output = cargo_equip(&[
+        "--bin",
+        "a",
+        "--profile",
+        "judge",
+        "--entry-point",
+        "run",
+        "--no-check",
+    ])?;
+    assert!(output.contains("pub fn run() {"));
+    assert!(!output.contains("solve"));
+    Ok(())
+}
+
+fn cargo_equip(args: &[&str]) -> anyhow::Result<String> {
+    let assert = assert_cmd::Command::cargo_bin("cargo-equip")?
+        .args(["equip", "--toolchain", &nightly()])
+        .args(args)
+        .current_dir(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("tests")
+                .join("multi-bin"),
+        )
+        .assert()
+        .success();
+    Ok(str::from_utf8(&assert.get_output().stdout)?.to_owned())
+}
+
+fn nightly() -> String {
+    env::var("CARGO_EQUIP_TEST_NIGHTLY_TOOLCHAIN").unwrap_or_else(|_| "nightly".to_owned())
+}
@@ -0,0 +1,96 @@
+use std::{env, path::PathBuf, str};
+
+const CODE: &str = r#"use multi_bin::add;
+
+fn main() {
+    println!("{}", add(5, 6));
+}
+"#;
+
+#[test]
+fn framed_request() -> anyhow::Result<()> {
+    let stdin = request("src/bin/a.rs", CODE) + &request("src/bin/b.rs", "fn main() {\n");
+    let responses = serve(&stdin)?;
+    assert_eq!(2, responses.len());
+
+    let (code, _, error) = &responses[0];
+    assert_eq!(None, *error);
+    assert!(code.contains("add(5, 6)"));
+    assert!(code.contains("pub mod multi_bin {"));
+
+    let (code, _, error) = &responses[1];
+    assert_eq!("", code);
+    assert!(error.is_some());
+    Ok(())
+}
+
+#[test]
+fn bad_frame() -> anyhow::Result<()> {
+    let stdin = "src/bin/a.rs\nnot-a-length\n".to_owned() + &request("src/bin/a.rs", CODE);
+    let responses = serve(&stdin)?;
+    assert_eq!(2, responses.len());
+
+    let (code, _, error) = &responses[0];
+    assert_eq!("", code);
+    assert_eq!(
+        Some(r#"expected the length of the code, got "not-a-length\n""#),
+        error.as_deref(),
+    );
+
+    let (code, _, error) = &responses[1];
+    assert_eq!(None, *error);
+    assert!(code.contains("add(5, 6)"));
+    Ok(())
+}
+
+fn request(path: &str, code: &str) -> String {
+    format!("{}\n{}\n{}", path, code.len(), code)
+}
+
+/// Returns the code, the warnings, and the error of each response.
+fn serve(stdin: &str) -> anyhow::Result<Vec<(String, String, Option<String>)>> {
+    let assert = assert_cmd::Command::cargo_bin("cargo-equip")?
+        .args(["equip", "--toolchain", &nightly(), "--server", "--no-check"])
+        .current_dir(multi_bin())
+        .write_stdin(stdin)
+        .assert()
+        .success();
+    let mut stdout = str::from_utf8(&assert.get_output().stdout)?;
+
+    let mut responses = vec![];
+    while !stdout.is_empty() {
+        let code = field(&mut stdout, "code")?;
+        let warnings = field(&mut stdout, "warnings")?;
+        let error = if let Some(rest) = stdout.strip_prefix("ok\n") {
+            stdout = rest;
+            None
+        } else {
+            Some(field(&mut stdout, "error")?)
+        };
+        responses.push((code, warnings, error));
+    }
+    Ok(responses)
+}
+
+fn field(stdout: &mut &str, name: &str) -> anyhow::Result<String> {
+    let (header, rest) = stdout
+        .split_once('\n')
+        .ok_or_else(|| anyhow::anyhow!("expected `{}`", name))?;
+    let len = header
+        .strip_prefix(&format!("{} ", name))
+        .ok_or_else(|| anyhow::anyhow!("expected `{}`, got {:?}", name, header))?
+        .parse::<usize>()?;
+    let (value, rest) = rest.split_at(len);
+    *stdout = rest;
+    Ok(value.to_owned())
+}
+
+fn nightly() -> String {
+    env::var("CARGO_EQUIP_TEST_NIGHTLY_TOOLCHAIN").unwrap_or_else(|_| "nightly".to_owned())
+}
+
+fn multi_bin() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("multi-bin")
+}
@@ -0,0 +1,60 @@
+use std::{env, fs, path::PathBuf, str};
+
+const CODE: &str = r#"use multi_bin::add;
+
+fn main() {
+    println!("{}", add(5, 6));
+}
+"#;
+
+#[test]
+fn src_stdin_with_bin() -> anyhow::Result<()> {
+    let assert = cargo_equip(&["--src", "-", "--bin", "b", "--no-check"])?
+        .write_stdin(CODE)
+        .assert()
+        .success();
+    let stdout = str::from_utf8(&assert.get_output().stdout)?;
+    assert!(stdout.starts_with("pub use __cargo_equip::prelude::*;\n\nuse multi_bin::add;"));
+    assert!(stdout.contains("add(5, 6)"));
+    assert!(!stdout.contains("add(3, 4)"));
+    assert!(stdout.contains("pub mod multi_bin {"));
+
+    let leftovers = fs::read_dir(multi_bin().join("src").join("bin"))?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|name| name.starts_with(".cargo-equip-stdin-"))
+        .collect::<Vec<_>>();
+    assert_eq!(Vec::<String>::new(), leftovers);
+    Ok(())
+}
+
+#[test]
+fn src_path_with_bin() -> anyhow::Result<()> {
+    cargo_equip(&["--src", "src/bin/a.rs", "--bin", "b", "--no-check"])?
+        .assert()
+        .failure();
+    cargo_equip(&["--src", "-", "--bin", "a", "--bin", "b", "--no-check"])?
+        .write_stdin(CODE)
+        .assert()
+        .failure();
+    Ok(())
+}
+
+fn cargo_equip(args: &[&str]) -> anyhow::Result<assert_cmd::Command> {
+    let mut cmd = assert_cmd::Command::cargo_bin("cargo-equip")?;
+    cmd.args(["equip", "--toolchain", &nightly()])
+        .args(args)
+        .current_dir(multi_bin());
+    Ok(cmd)
+}
+
+fn nightly() -> String {
+    env::var("CARGO_EQUIP_TEST_NIGHTLY_TOOLCHAIN").unwrap_or_else(|_| "nightly".to_owned())
+}
+
+fn multi_bin() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("multi-bin")
+}
@@ -0,0 +1,43 @@
+use cargo_equip::shell::Shell;
+use insta::assert_snapshot;
+use ra_ap_paths::AbsPathBuf;
+use std::{env, io, path::Path};
+use structopt::StructOpt as _;
+
+#[test]
+fn mixed() -> anyhow::Result<()> {
+    let dir = tempfile::Builder::new()
+        .prefix("cargo-equip-test-")
+        .tempdir()?;
+    let dir = dir.path().join("vendored");
+
+    cargo_equip::run(
+        cargo_equip::Opt::from_iter_safe(&[
+            "",
+            "equip",
+            "--toolchain",
+            &env::var("CARGO_EQUIP_TEST_NIGHTLY_TOOLCHAIN")
+                .unwrap_or_else(|_| "nightly".to_owned()),
+            "--bin",
+            "mixed",
+            "--vendor",
+            dir.to_str().unwrap(),
+        ])?,
+        cargo_equip::Context {
+            cwd: Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests")
+                .join("mixed"),
+            cargo_equip_exe: AbsPathBuf::assert(env!("CARGO_BIN_EXE_cargo-equip").into()),
+            cache_dir: Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests")
+                .join("cache"),
+            shell: &mut Shell::from_stdout(Box::new(io::sink())),
+        },
+    )?;
+
+    let manifest = cargo_util::paths::read(&dir.join("Cargo.toml"))?;
+    assert_snapshot!("mixed_manifest", manifest);
+    let lib = cargo_util::paths::read(&dir.join("crates").join("mixed").join("lib.rs"))?;
+    assert_snapshot!("mixed_lib", lib);
+    Ok(())
+}